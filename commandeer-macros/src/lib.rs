@@ -7,29 +7,110 @@ use syn::{
 };
 
 struct CommandeerArgs {
-    mode: Ident,
+    /// Expression evaluating to a `commandeer_test::Mode`.
+    mode: Expr,
+    /// Whether to also emit an ignored `{name}_record` test, see [`GOLDEN`].
+    golden: bool,
     commands: Vec<String>,
+    /// `key = value` pairs, each applied as a `CommandeerBuilder` method call.
+    config: Vec<(Ident, Expr)>,
 }
 
 const RECORD: &str = "Record";
 const REPLAY: &str = "Replay";
+const RECORD_ONCE: &str = "RecordOnce";
+const REPLAY_LEARN: &str = "ReplayLearn";
+/// Replays in the test itself and records in an `#[ignore]`d copy of it.
+const GOLDEN: &str = "Golden";
+
+/// `CommandeerBuilder` methods that can be set as `key = value` in the attribute.
+const CONFIG_KEYS: &[&str] = &[
+    "warn_unused",
+    "fail_on_miss",
+    "manage_path",
+    "expect_commands",
+    "passthrough",
+    "skip_if_missing",
+    "validate_fixture",
+    "test_name",
+    "no_wait_output",
+    "strict_path",
+    "strict_path_dirs",
+    "live_output",
+    "normalize_temp_paths",
+    "response_files",
+    "canonical_json",
+    "split_options",
+    "unordered_flags",
+    "capture_env",
+    "env_match",
+    "require_unique_match",
+    "locales",
+    "source_date_epoch",
+    "faketime",
+    "transform",
+    "expand_home",
+    "native_line_endings",
+    "concurrent_output",
+    "debug",
+    "trace_invocations",
+    "echo_invocation",
+    "scan_secrets",
+    "signal_replay",
+    "capture_stdin",
+    "record_timing",
+    "record_executable",
+    "record_rusage",
+    "record_fd_count",
+    "numeric_tolerance",
+    "blob_store",
+    "index",
+    "fixture_layers",
+    "max_per_key",
+    "output_files",
+    "timeout",
+];
 
 impl Parse for CommandeerArgs {
     fn parse(input: ParseStream) -> Result<Self> {
         let mut commands = vec![];
+        let mut config = vec![];
 
         let ident: Ident = input.parse()?;
+        let golden = ident == GOLDEN;
 
         let mode = match ident.to_string().as_str() {
-            x if [RECORD, REPLAY].contains(&x) => Ident::new(x, proc_macro2::Span::call_site()),
+            "mode" if input.peek(syn::Token![=]) => {
+                input.parse::<syn::Token![=]>()?;
+
+                input.parse()?
+            }
+            x if [RECORD, REPLAY, RECORD_ONCE, REPLAY_LEARN].contains(&x) => {
+                let ident = Ident::new(x, proc_macro2::Span::call_site());
+
+                parse_quote! { commandeer_test::Mode::#ident }
+            }
+            GOLDEN => parse_quote! { commandeer_test::Mode::Replay },
             _ => {
                 return Err(syn::Error::new(
                     ident.span(),
-                    format!("Expected '{RECORD}' or '{REPLAY}'"),
+                    format!(
+                        "Expected '{RECORD}', '{REPLAY}', '{RECORD_ONCE}', '{REPLAY_LEARN}', '{GOLDEN}' or 'mode = <expr>'"
+                    ),
                 ));
             }
         };
 
+        // A bare mode sets up the environment and leaves mocking to the test body.
+        if input.is_empty() {
+            return Ok(CommandeerArgs {
+                mode,
+                golden,
+                commands,
+                config,
+            });
+        }
+
         input.parse::<syn::Token![,]>()?;
 
         while !input.is_empty() {
@@ -37,8 +118,24 @@ impl Parse for CommandeerArgs {
                 let lit: syn::LitStr = input.parse()?;
 
                 commands.push(lit.value());
+            } else if input.peek(Ident) && input.peek2(syn::Token![=]) {
+                let key: Ident = input.parse()?;
+
+                if !CONFIG_KEYS.contains(&key.to_string().as_str()) {
+                    return Err(syn::Error::new(
+                        key.span(),
+                        format!(
+                            "Unknown config key '{key}', expected one of: {}",
+                            CONFIG_KEYS.join(", ")
+                        ),
+                    ));
+                }
+
+                input.parse::<syn::Token![=]>()?;
+
+                config.push((key, input.parse()?));
             } else {
-                return Err(input.error("Expected a command string"));
+                return Err(input.error("Expected a command string or `key = value` config"));
             }
 
             if input.peek(syn::Token![,]) {
@@ -46,31 +143,79 @@ impl Parse for CommandeerArgs {
             }
         }
 
-        if commands.is_empty() {
-            return Err(syn::Error::new(
-                input.span(),
-                "Expected at least one command string",
-            ));
-        }
-
-        Ok(CommandeerArgs { mode, commands })
+        Ok(CommandeerArgs {
+            mode,
+            golden,
+            commands,
+            config,
+        })
     }
 }
 
 /// Procedural macro for setting up commandeer test environment
 ///
-/// Usage: `#[commandeer(Record, "echo", "ls")]`
+/// Usage: `#[commandeer(Record, "echo", "ls")]`, or `#[commandeer(mode = my_mode(), "echo")]` to pass any expression
+/// evaluating to a `commandeer_test::Mode`.
+///
+/// `key = value` pairs after the mode configure the instance through the `CommandeerBuilder` method of the same
+/// name, e.g. `#[commandeer(Replay, "date", capture_env = ["TZ"], env_match = EnvMatch::Exact)]`.
+///
+/// This expands to code that creates a Commandeer instance and mocks the specified commands. With no commands,
+/// `#[commandeer(Replay)]` only creates the instance, bound as `commandeer`, so the body can mock conditionally.
 ///
-/// This expands to code that creates a Commandeer instance and mocks the specified commands
+/// `skip_if_missing = true` makes a replaying test return early, printing `skipped <name>: <reason>` to stderr, when
+/// its fixture doesn't exist or lacks a recording of one of the listed commands; see `Commandeer::skip_reason`.
+///
+/// `validate_fixture = true` checks the fixture once the listed commands are mocked, panicking with a description of
+/// what is wrong, e.g. no recording of one of them; see `Commandeer::validate_fixture`.
+///
+/// `#[commandeer(Golden, "git")]` replays like `Replay`, and also emits an `#[ignore]`d copy of the test named
+/// `{name}_record` that records to the same fixture, for re-recording with `cargo test -- --ignored _record`.
 #[proc_macro_attribute]
 pub fn commandeer(args: TokenStream, input: TokenStream) -> TokenStream {
     let args = parse_macro_input!(args as CommandeerArgs);
-    let mut input_fn = parse_macro_input!(input as ItemFn);
+    let input_fn = parse_macro_input!(input as ItemFn);
+
+    TokenStream::from(expand_tests(&args, input_fn))
+}
+
+/// The tests `#[commandeer(args)]` turns `input_fn` into: the test itself, then its `_record` copy if golden.
+fn expand_tests(args: &CommandeerArgs, input_fn: ItemFn) -> proc_macro2::TokenStream {
+    let test_file_name = format!("cmds_{}.json", input_fn.sig.ident);
+
+    let record_fn = args.golden.then(|| {
+        let mut record_fn = input_fn.clone();
+        record_fn.sig.ident = Ident::new(
+            &format!("{}_record", input_fn.sig.ident),
+            input_fn.sig.ident.span(),
+        );
+        record_fn.attrs.push(parse_quote! {
+            #[ignore = "records the fixture; run with `--ignored`"]
+        });
 
-    let fn_name = &input_fn.sig.ident;
+        expand(
+            args,
+            &parse_quote! { commandeer_test::Mode::Record },
+            &test_file_name,
+            record_fn,
+        )
+    });
 
-    let test_file_name = format!("cmds_{fn_name}.json");
+    let replay_fn = expand(args, &args.mode, &test_file_name, input_fn);
 
+    quote! {
+        #replay_fn
+        #record_fn
+    }
+}
+
+/// Prepends setting up a Commandeer instance in `mode` on `test_file_name` to `input_fn`'s body.
+fn expand(
+    args: &CommandeerArgs,
+    mode: &Expr,
+    test_file_name: &str,
+    mut input_fn: ItemFn,
+) -> proc_macro2::TokenStream {
     // Split by commas and parse each part
 
     let mock_commands: Vec<Expr> = args
@@ -83,13 +228,42 @@ pub fn commandeer(args: TokenStream, input: TokenStream) -> TokenStream {
         })
         .collect();
 
-    let mode = args.mode;
+    // Validation needs the commands mocked, so it runs after them rather than being a builder option.
+    let config = args
+        .config
+        .iter()
+        .filter(|(key, _)| key != "validate_fixture")
+        .map(|(key, value)| quote! { .#key(#value) });
 
     // Create the setup statements
     let setup_stmts: Vec<syn::Stmt> = vec![parse_quote! {
-        let commandeer = commandeer_test::Commandeer::new(#test_file_name, commandeer_test::Mode::#mode);
+        #[allow(unused_variables)]
+        let commandeer = commandeer_test::Commandeer::builder(#test_file_name, #mode)
+            #(#config)*
+            .build();
     }];
 
+    let skip_stmts: Vec<syn::Stmt> = if args.config.iter().any(|(key, _)| key == "skip_if_missing")
+    {
+        let commands = &args.commands;
+        let name = input_fn.sig.ident.to_string();
+        // A test returning `Result` passes by returning `Ok(())`.
+        let skipped = match input_fn.sig.output {
+            syn::ReturnType::Default => quote! {},
+            syn::ReturnType::Type(..) => quote! { ::core::result::Result::Ok(()) },
+        };
+
+        vec![parse_quote! {
+            if let Some(reason) = commandeer.skip_reason(&[#(#commands),*]) {
+                eprintln!("skipped {}: {reason}", #name);
+
+                return #skipped;
+            }
+        }]
+    } else {
+        vec![]
+    };
+
     let mock_stmts: Vec<syn::Stmt> = mock_commands
         .iter()
         .map(|expr| {
@@ -99,9 +273,26 @@ pub fn commandeer(args: TokenStream, input: TokenStream) -> TokenStream {
         })
         .collect();
 
+    let validate_stmts: Vec<syn::Stmt> = args
+        .config
+        .iter()
+        .filter(|(key, _)| key == "validate_fixture")
+        .map(|(_, value)| {
+            parse_quote! {
+                if #value {
+                    if let Err(err) = commandeer.validate_fixture() {
+                        panic!("{err}");
+                    }
+                }
+            }
+        })
+        .collect();
+
     // Prepend the setup code to the function body
     let mut new_stmts = setup_stmts;
+    new_stmts.extend(skip_stmts);
     new_stmts.extend(mock_stmts);
+    new_stmts.extend(validate_stmts);
     new_stmts.extend(input_fn.block.stmts);
 
     input_fn.block.stmts = new_stmts;
@@ -113,9 +304,43 @@ pub fn commandeer(args: TokenStream, input: TokenStream) -> TokenStream {
             input_fn.sig.fn_token,
             "Out of order error. `commandeer` macro must be above the `serial_test` macro.",
         )
-        .to_compile_error()
-        .into();
+        .to_compile_error();
     }
 
-    TokenStream::from(quote! { #input_fn })
+    quote! { #input_fn }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn golden_emits_ignored_record_copy() {
+        let args: CommandeerArgs = parse_quote! { Golden, "echo" };
+        let expanded = expand_tests(&args, parse_quote! { #[test] fn golden() {} });
+        let file: syn::File = syn::parse2(expanded).unwrap();
+
+        let [syn::Item::Fn(replay), syn::Item::Fn(record)] = file.items.as_slice() else {
+            panic!(
+                "expected the test and its record copy, got {}",
+                quote!(#file)
+            );
+        };
+        let ignored = |test: &ItemFn| test.attrs.iter().any(|attr| attr.path().is_ident("ignore"));
+        let body = |test: &ItemFn| {
+            let block = &test.block;
+
+            quote!(#block).to_string()
+        };
+
+        assert_eq!(replay.sig.ident, "golden");
+        assert!(!ignored(replay));
+        assert!(body(replay).contains("commandeer_test :: Mode :: Replay"));
+        assert!(body(replay).contains("\"cmds_golden.json\""));
+
+        assert_eq!(record.sig.ident, "golden_record");
+        assert!(ignored(record));
+        assert!(body(record).contains("commandeer_test :: Mode :: Record"));
+        assert!(body(record).contains("\"cmds_golden.json\""));
+    }
 }
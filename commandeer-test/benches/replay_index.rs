@@ -0,0 +1,67 @@
+//! Compares replay lookups from a large fixture with and without its index.
+//!
+//! Run with `cargo bench --bench replay_index`.
+
+use commandeer_test::{
+    CommandInvocation, MatchOptions, RecordedCommands, index_path, replay_command_sync_with,
+    save_recordings_sync, write_index,
+};
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
+
+const RECORDINGS: usize = 5_000;
+const LOOKUPS: u32 = 200;
+
+fn time_lookups(fixture: &Path) -> Duration {
+    let start = Instant::now();
+
+    for i in 0..LOOKUPS {
+        let arg = format!("{}", (i as usize * 7919) % RECORDINGS);
+
+        replay_command_sync_with(
+            &MatchOptions::default(),
+            fixture.to_path_buf(),
+            "tool".to_string(),
+            vec![arg],
+        )
+        .expect("Failed to replay")
+        .expect("Missing recording");
+    }
+
+    start.elapsed() / LOOKUPS
+}
+
+fn main() {
+    let dir = tempfile::TempDir::new().expect("Failed to create temp dir");
+    let fixture = dir.path().join("large.json");
+
+    let mut recordings = RecordedCommands::default();
+
+    for i in 0..RECORDINGS {
+        recordings.add_invocation(CommandInvocation {
+            binary_name: "tool".to_string(),
+            args: vec![i.to_string()],
+            stdout: format!("output line for invocation {i}\n")
+                .repeat(20)
+                .into_bytes(),
+            stderr: vec![],
+            exit_code: 0,
+            ..Default::default()
+        });
+    }
+
+    save_recordings_sync(&fixture, &recordings).expect("Failed to save fixture");
+
+    let full_parse = time_lookups(&fixture);
+
+    write_index(&fixture).expect("Failed to write index");
+    assert!(index_path(&fixture).exists());
+
+    let indexed = time_lookups(&fixture);
+
+    println!("{RECORDINGS} recordings, mean of {LOOKUPS} lookups:");
+    println!("  full parse: {full_parse:?}");
+    println!("  indexed:    {indexed:?}");
+}
@@ -0,0 +1,42 @@
+use crate::{CommandInvocation, Result};
+use sha2::{Digest, Sha256};
+use std::{fs, path::Path};
+
+/// Moves the invocation's stdout into the content-addressed store at `store`, pointing
+/// [`CommandInvocation::stdout_blob`] at it.
+///
+/// Blobs are named by the SHA-256 of their bytes, so identical output recorded by any number of fixtures is stored
+/// once. The reference is relative to the fixture's directory when the store is inside it, keeping fixtures portable
+/// across checkouts, and absolute otherwise. Empty stdout is left inline.
+pub(crate) fn store_stdout(
+    store: &Path,
+    fixture: &Path,
+    invocation: &mut CommandInvocation,
+) -> Result<()> {
+    let stdout = &invocation.stdout;
+
+    if stdout.is_empty() {
+        return Ok(());
+    }
+
+    fs::create_dir_all(store)?;
+
+    let blob = store.join(format!("{:x}", Sha256::digest(stdout)));
+
+    if !blob.exists() {
+        // Write under a temp name first so a concurrent reader never sees a partial blob.
+        let partial = tempfile::NamedTempFile::new_in(store)?;
+        fs::write(partial.path(), stdout)?;
+        partial.persist(&blob).map_err(|e| e.error)?;
+    }
+
+    let fixture_dir = fixture.parent().unwrap_or(Path::new(""));
+
+    invocation.stdout_blob = Some(match blob.strip_prefix(fixture_dir) {
+        Ok(relative) => relative.to_path_buf(),
+        Err(_) => std::path::absolute(&blob)?,
+    });
+    invocation.stdout.clear();
+
+    Ok(())
+}
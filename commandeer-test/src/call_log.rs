@@ -0,0 +1,121 @@
+use crate::{CommandeerError, Result};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::{fs, io::Write as _, path::Path};
+
+/// An invocation intercepted by a mock, as appended to the call log sidecar.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CallLogEntry {
+    pub binary_name: String,
+    pub args: Vec<String>,
+    pub exit_code: i32,
+    /// Whether a recording was found for the invocation. Always `true` when recording.
+    pub matched: bool,
+    /// Key of the recording that served the invocation, which differs from the key of its args when it matched
+    /// fuzzily, e.g. within [`crate::MatchOptions::numeric_tolerance`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// Process id of the mock, which the commands it runs see as [`COMMANDEER_PARENT`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+    /// Process id of the mock whose command made this invocation, if it was nested in one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_pid: Option<u32>,
+    /// When the mock started, in milliseconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started_ms: Option<u64>,
+}
+
+/// Environment variable the mock wrappers set to their process id, so a mock run by another mock's command knows
+/// its parent.
+pub const COMMANDEER_PARENT: &str = "COMMANDEER_PARENT";
+
+/// A mocked invocation and the mocked invocations its command made, see [`spawn_tree`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpawnNode {
+    pub call: CallLogEntry,
+    pub children: Vec<SpawnNode>,
+}
+
+/// Appends `entry` to the JSON-lines call log at `path`, creating it if needed.
+pub fn append_call_log(path: &Path, entry: &CallLogEntry) -> Result<()> {
+    append_json_line(path, entry)
+}
+
+/// Reads every entry of the call log at `path`. A missing log means nothing was invoked.
+pub fn read_call_log(path: &Path) -> Result<Vec<CallLogEntry>> {
+    read_json_lines(path)
+}
+
+/// Arranges the call log `entries` into a tree of which mocked invocation ran which, roots and children in start
+/// order.
+///
+/// The tree is rough: it only covers mocks, and an entry's parent is the latest mock started before it with the
+/// entry's [`CallLogEntry::parent_pid`], so pid reuse within a run can misplace an entry. Commands only run nested
+/// commands while recording, so on replay every call is a root.
+pub fn spawn_tree(entries: &[CallLogEntry]) -> Vec<SpawnNode> {
+    // The log is written as mocks finish, so a parent follows its children there.
+    let mut order: Vec<usize> = (0..entries.len()).collect();
+    order.sort_by_key(|&i| entries[i].started_ms);
+
+    let mut children: Vec<Vec<usize>> = vec![vec![]; entries.len()];
+    let mut roots = vec![];
+
+    for (position, &i) in order.iter().enumerate() {
+        let parent = entries[i].parent_pid.and_then(|parent_pid| {
+            order[..position]
+                .iter()
+                .rev()
+                .find(|&&j| entries[j].pid == Some(parent_pid))
+        });
+
+        match parent {
+            Some(&parent) => children[parent].push(i),
+            None => roots.push(i),
+        }
+    }
+
+    fn node(i: usize, entries: &[CallLogEntry], children: &[Vec<usize>]) -> SpawnNode {
+        SpawnNode {
+            call: entries[i].clone(),
+            children: children[i]
+                .iter()
+                .map(|&child| node(child, entries, children))
+                .collect(),
+        }
+    }
+
+    roots
+        .into_iter()
+        .map(|i| node(i, entries, &children))
+        .collect()
+}
+
+pub(crate) fn append_json_line(path: &Path, value: &impl Serialize) -> Result<()> {
+    let mut line = serde_json::to_string(value)?;
+    line.push('\n');
+
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(line.as_bytes())?;
+
+    Ok(())
+}
+
+pub(crate) fn read_json_lines<T: DeserializeOwned>(path: &Path) -> Result<Vec<T>> {
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+
+    fs::read_to_string(path)?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            serde_json::from_str(line).map_err(|source| CommandeerError::Parse {
+                path: path.to_path_buf(),
+                source,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,113 @@
+use crate::{CommandeerError, Result, encryption};
+use std::path::Path;
+
+/// Compression of a fixture, chosen by its extension: `.json.gz` with the `gzip` feature, `.json.zst` with `zstd`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    fn of(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+
+    fn unsupported(self, path: &Path) -> CommandeerError {
+        let feature = match self {
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+            Compression::None => unreachable!("uncompressed fixtures are always supported"),
+        };
+
+        CommandeerError::UnsupportedCompression {
+            path: path.to_path_buf(),
+            feature,
+        }
+    }
+}
+
+/// Whether the fixture at `path` is stored compressed or encrypted, so it can only be read whole and never in parts
+/// through an index.
+pub(crate) fn is_opaque(path: &Path) -> bool {
+    Compression::of(path) != Compression::None || encryption::is_encrypted(path)
+}
+
+/// Decrypts and decompresses the raw contents of the fixture at `path`. An empty file is an empty fixture whatever
+/// its extension.
+pub(crate) fn decode(path: &Path, bytes: Vec<u8>) -> Result<String> {
+    if bytes.is_empty() {
+        return Ok(String::new());
+    }
+
+    if encryption::is_encrypted(path) {
+        return decode(
+            &encryption::inner_path(path),
+            encryption::decrypt(path, bytes)?,
+        );
+    }
+
+    let bytes = match Compression::of(path) {
+        Compression::None => bytes,
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => {
+            use std::io::Read as _;
+
+            let mut decoded = vec![];
+            flate2::read::GzDecoder::new(bytes.as_slice()).read_to_end(&mut decoded)?;
+
+            decoded
+        }
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => zstd::decode_all(bytes.as_slice())?,
+        #[allow(unreachable_patterns)]
+        compression => return Err(compression.unsupported(path)),
+    };
+
+    String::from_utf8(bytes).map_err(|err| {
+        CommandeerError::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+    })
+}
+
+/// Compresses and encrypts serialized recordings for writing to the fixture at `path`.
+pub(crate) fn encode(path: &Path, json: String) -> Result<Vec<u8>> {
+    if encryption::is_encrypted(path) {
+        return encryption::encrypt(path, encode(&encryption::inner_path(path), json)?);
+    }
+
+    match Compression::of(path) {
+        Compression::None => Ok(json.into_bytes()),
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => {
+            use std::io::Write as _;
+
+            let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+            encoder.write_all(json.as_bytes())?;
+
+            Ok(encoder.finish()?)
+        }
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => Ok(zstd::encode_all(json.as_bytes(), 0)?),
+        #[allow(unreachable_patterns)]
+        compression => Err(compression.unsupported(path)),
+    }
+}
+
+/// Reads, decrypts and decompresses the fixture at `path`.
+pub(crate) fn read(path: &Path) -> Result<String> {
+    decode(path, std::fs::read(path)?)
+}
+
+/// Compresses, encrypts and writes `json` to the fixture at `path`.
+pub(crate) fn write(path: impl AsRef<Path>, json: String) -> Result<()> {
+    let path = path.as_ref();
+
+    std::fs::write(path, encode(path, json)?)?;
+
+    Ok(())
+}
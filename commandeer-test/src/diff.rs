@@ -0,0 +1,295 @@
+//! Comparing two sets of recordings, e.g. a fixture before and after re-recording, see
+//! [`RecordedCommands::equivalent`].
+
+use crate::{CommandInvocation, RecordedCommands};
+use std::{collections::BTreeSet, fmt};
+
+/// A field of a [`CommandInvocation`] that [`RecordedCommands::equivalent`] compares, and can be told to ignore.
+///
+/// The binary name and args aren't among them, as they make up the key recordings are compared under.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Field {
+    /// [`CommandInvocation::original_args`], which vary between runs when they hold temp paths.
+    OriginalArgs,
+    Stdout,
+    Stderr,
+    ExitCode,
+    Signal,
+    Encoding,
+    Env,
+    StdoutTemplate,
+    StdoutBlob,
+    /// [`CommandInvocation::stdin`] and how much of it was consumed.
+    Stdin,
+    /// [`CommandInvocation::duration_ms`].
+    Timing,
+    Executable,
+    Rusage,
+    FdCount,
+    Priority,
+    Wrapper,
+    WritesFiles,
+}
+
+impl Field {
+    pub const ALL: [Field; 17] = [
+        Field::OriginalArgs,
+        Field::Stdout,
+        Field::Stderr,
+        Field::ExitCode,
+        Field::Signal,
+        Field::Encoding,
+        Field::Env,
+        Field::StdoutTemplate,
+        Field::StdoutBlob,
+        Field::Stdin,
+        Field::Timing,
+        Field::Executable,
+        Field::Rusage,
+        Field::FdCount,
+        Field::Priority,
+        Field::Wrapper,
+        Field::WritesFiles,
+    ];
+
+    /// Fields that differ between runs of the same command without its behavior changing.
+    pub const VOLATILE: [Field; 6] = [
+        Field::OriginalArgs,
+        Field::Timing,
+        Field::Rusage,
+        Field::FdCount,
+        Field::Executable,
+        Field::Wrapper,
+    ];
+
+    fn differs(self, a: &CommandInvocation, b: &CommandInvocation) -> bool {
+        match self {
+            Field::OriginalArgs => a.original_args != b.original_args,
+            Field::Stdout => a.stdout != b.stdout,
+            Field::Stderr => a.stderr != b.stderr,
+            Field::ExitCode => a.exit_code != b.exit_code,
+            Field::Signal => a.signal != b.signal,
+            Field::Encoding => a.encoding != b.encoding,
+            Field::Env => a.env != b.env,
+            Field::StdoutTemplate => a.stdout_template != b.stdout_template,
+            Field::StdoutBlob => a.stdout_blob != b.stdout_blob,
+            Field::Stdin => (&a.stdin, a.stdin_consumed) != (&b.stdin, b.stdin_consumed),
+            Field::Timing => a.duration_ms != b.duration_ms,
+            Field::Executable => a.executable != b.executable,
+            Field::Rusage => a.rusage != b.rusage,
+            Field::FdCount => a.fd_count != b.fd_count,
+            Field::Priority => a.priority != b.priority,
+            Field::Wrapper => a.wrapper != b.wrapper,
+            Field::WritesFiles => a.writes_files != b.writes_files,
+        }
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Field::OriginalArgs => "original_args",
+            Field::Stdout => "stdout",
+            Field::Stderr => "stderr",
+            Field::ExitCode => "exit_code",
+            Field::Signal => "signal",
+            Field::Encoding => "encoding",
+            Field::Env => "env",
+            Field::StdoutTemplate => "stdout_template",
+            Field::StdoutBlob => "stdout_blob",
+            Field::Stdin => "stdin",
+            Field::Timing => "timing",
+            Field::Executable => "executable",
+            Field::Rusage => "rusage",
+            Field::FdCount => "fd_count",
+            Field::Priority => "priority",
+            Field::Wrapper => "wrapper",
+            Field::WritesFiles => "writes_files",
+        })
+    }
+}
+
+/// One way two sets of recordings differ under a key, within the test scope `test` if any.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    pub test: Option<String>,
+    pub key: String,
+    pub kind: DifferenceKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DifferenceKind {
+    /// The key has this many recordings on the left and right; the ones both sides have are still compared.
+    Count { left: usize, right: usize },
+    /// The recordings at `index` under the key differ in these fields.
+    Fields { index: usize, fields: Vec<Field> },
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(test) = &self.test {
+            write!(f, "[{test}] ")?;
+        }
+
+        match &self.kind {
+            DifferenceKind::Count { left, right } => {
+                write!(f, "{}: {left} recordings, then {right}", self.key)
+            }
+            DifferenceKind::Fields { index, fields } => {
+                let fields: Vec<_> = fields.iter().map(Field::to_string).collect();
+
+                write!(f, "{}[{index}]: differs in {}", self.key, fields.join(", "))
+            }
+        }
+    }
+}
+
+/// Every [`Difference`] between two sets of recordings, sorted by test scope and key.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FixtureDiff {
+    pub differences: Vec<Difference>,
+}
+
+impl FixtureDiff {
+    /// Whether the recordings compared had no differences.
+    pub fn is_equivalent(&self) -> bool {
+        self.differences.is_empty()
+    }
+}
+
+impl fmt::Display for FixtureDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for difference in &self.differences {
+            writeln!(f, "{difference}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl RecordedCommands {
+    /// How these recordings differ from `other`, comparing every [`Field`] except those in `ignore`, e.g.
+    /// [`Field::VOLATILE`] to see whether re-recording a fixture changed anything but timings.
+    ///
+    /// Recordings are compared key by key, top-level and in each test scope, and in recording order within a key. A
+    /// key recorded on only one side shows up as a [`DifferenceKind::Count`] with 0 on the other. The fixture's
+    /// description and minimum version aren't compared.
+    pub fn equivalent(&self, other: &RecordedCommands, ignore: &[Field]) -> FixtureDiff {
+        let mut diff = FixtureDiff::default();
+        let empty = RecordedCommands::default();
+
+        compare(self, other, None, ignore, &mut diff.differences);
+
+        let tests: BTreeSet<_> = self.tests.keys().chain(other.tests.keys()).collect();
+
+        for test in tests {
+            compare(
+                self.tests.get(test).unwrap_or(&empty),
+                other.tests.get(test).unwrap_or(&empty),
+                Some(test),
+                ignore,
+                &mut diff.differences,
+            );
+        }
+
+        diff
+    }
+}
+
+fn compare(
+    left: &RecordedCommands,
+    right: &RecordedCommands,
+    test: Option<&str>,
+    ignore: &[Field],
+    differences: &mut Vec<Difference>,
+) {
+    let keys: BTreeSet<_> = left.commands.keys().chain(right.commands.keys()).collect();
+
+    for key in keys {
+        let left = left.commands.get(key).map_or(&[][..], Vec::as_slice);
+        let right = right.commands.get(key).map_or(&[][..], Vec::as_slice);
+        let difference = |kind| Difference {
+            test: test.map(str::to_string),
+            key: key.clone(),
+            kind,
+        };
+
+        if left.len() != right.len() {
+            differences.push(difference(DifferenceKind::Count {
+                left: left.len(),
+                right: right.len(),
+            }));
+        }
+
+        for (index, (a, b)) in left.iter().zip(right).enumerate() {
+            let fields: Vec<_> = Field::ALL
+                .into_iter()
+                .filter(|field| !ignore.contains(field) && field.differs(a, b))
+                .collect();
+
+            if !fields.is_empty() {
+                differences.push(difference(DifferenceKind::Fields { index, fields }));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recordings(duration_ms: u64, stdout: &str) -> RecordedCommands {
+        let mut recordings = RecordedCommands::default();
+        let mut invocation: CommandInvocation = serde_json::from_str(&format!(
+            r#"{{"binary_name":"make","args":["all"],"stdout":{stdout:?},"stderr":"","exit_code":0}}"#
+        ))
+        .unwrap();
+        invocation.duration_ms = Some(duration_ms);
+        recordings.add_invocation(invocation);
+
+        recordings
+    }
+
+    #[test]
+    fn timing_is_ignored_when_asked() {
+        let before = recordings(120, "built\n");
+        let after = recordings(340, "built\n");
+
+        assert!(before.equivalent(&after, &[Field::Timing]).is_equivalent());
+        assert!(before.equivalent(&after, &Field::VOLATILE).is_equivalent());
+
+        let strict = before.equivalent(&after, &[]);
+
+        assert_eq!(
+            strict.differences,
+            [Difference {
+                test: None,
+                key: "make:all".to_string(),
+                kind: DifferenceKind::Fields {
+                    index: 0,
+                    fields: vec![Field::Timing]
+                },
+            }]
+        );
+        assert_eq!(strict.to_string(), "make:all[0]: differs in timing\n");
+    }
+
+    #[test]
+    fn missing_recordings_and_output_changes_are_reported() {
+        let before = recordings(120, "built\n");
+        let mut after = recordings(120, "rebuilt\n");
+        after.for_test_mut("clean").add_invocation(
+            serde_json::from_str(
+                r#"{"binary_name":"make","args":["clean"],"stdout":"","stderr":"","exit_code":0}"#,
+            )
+            .unwrap(),
+        );
+
+        let diff = before.equivalent(&after, &Field::VOLATILE);
+
+        assert_eq!(
+            diff.to_string(),
+            "make:all[0]: differs in stdout\n[clean] make:clean: 0 recordings, then 1\n"
+        );
+    }
+}
@@ -0,0 +1,145 @@
+//! Encryption at rest of fixtures ending in `.enc`, e.g. `cmds.json.enc` or `cmds.json.gz.enc`, for recordings that
+//! must hold sensitive data without committing it in plaintext.
+//!
+//! The file is a random 96-bit nonce followed by the AES-256-GCM ciphertext of the fixture as it would otherwise be
+//! stored, compressed first if the name asks for it. The key is the SHA-256 of [`COMMANDEER_KEY`].
+
+use crate::{CommandeerError, Result};
+use std::path::{Path, PathBuf};
+
+/// Environment variable holding the secret fixtures ending in `.enc` are encrypted with. Any string works, but it
+/// should be long and random, e.g. from `openssl rand -hex 32`.
+pub const COMMANDEER_KEY: &str = "COMMANDEER_KEY";
+
+/// Whether the fixture at `path` is stored encrypted.
+pub(crate) fn is_encrypted(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "enc")
+}
+
+/// The name the contents of the encrypted fixture at `path` are otherwise stored under, which picks their
+/// compression and format.
+pub(crate) fn inner_path(path: &Path) -> PathBuf {
+    path.with_extension("")
+}
+
+#[cfg(not(feature = "encryption"))]
+pub(crate) fn decrypt(path: &Path, _bytes: Vec<u8>) -> Result<Vec<u8>> {
+    Err(CommandeerError::UnsupportedEncryption {
+        path: path.to_path_buf(),
+    })
+}
+
+#[cfg(not(feature = "encryption"))]
+pub(crate) fn encrypt(path: &Path, _bytes: Vec<u8>) -> Result<Vec<u8>> {
+    Err(CommandeerError::UnsupportedEncryption {
+        path: path.to_path_buf(),
+    })
+}
+
+#[cfg(feature = "encryption")]
+pub(crate) use aead::{decrypt, encrypt};
+
+#[cfg(feature = "encryption")]
+mod aead {
+    use super::*;
+    use aes_gcm::{
+        Aes256Gcm, Key, KeyInit as _, Nonce,
+        aead::{Aead as _, AeadCore as _, OsRng},
+    };
+    use sha2::{Digest as _, Sha256};
+
+    /// Bytes of the nonce stored ahead of the ciphertext.
+    const NONCE_LEN: usize = 12;
+
+    fn error(path: &Path, reason: impl Into<String>) -> CommandeerError {
+        CommandeerError::Encryption {
+            path: path.to_path_buf(),
+            reason: reason.into(),
+        }
+    }
+
+    fn cipher(path: &Path) -> Result<Aes256Gcm> {
+        let secret = std::env::var(COMMANDEER_KEY)
+            .map_err(|_| error(path, format!("{COMMANDEER_KEY} is not set")))?;
+        let key: [u8; 32] = Sha256::digest(secret.as_bytes()).into();
+
+        Ok(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)))
+    }
+
+    /// The fixture contents stored encrypted in `bytes` from the file at `path`.
+    pub(crate) fn decrypt(path: &Path, bytes: Vec<u8>) -> Result<Vec<u8>> {
+        if bytes.len() < NONCE_LEN {
+            return Err(error(path, "it is too short to hold a nonce"));
+        }
+
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+
+        cipher(path)?
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| {
+                error(
+                    path,
+                    format!("wrong {COMMANDEER_KEY} or corrupted contents"),
+                )
+            })
+    }
+
+    /// `bytes` encrypted for storing in the fixture at `path`, under a fresh nonce.
+    pub(crate) fn encrypt(path: &Path, bytes: Vec<u8>) -> Result<Vec<u8>> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher(path)?
+            .encrypt(&nonce, bytes.as_slice())
+            .map_err(|_| error(path, "encryption failed"))?;
+
+        Ok([nonce.as_slice(), &ciphertext].concat())
+    }
+
+    #[cfg(all(test, feature = "sync"))]
+    mod tests {
+        use super::*;
+        use crate::{RecordedCommands, load_recordings_sync, save_recordings_sync};
+
+        #[test]
+        #[serial_test::serial]
+        fn encrypted_fixture_round_trips_under_the_right_key() {
+            let dir = tempfile::TempDir::new().unwrap();
+            let fixture = dir.path().join("secret.json.enc");
+            let mut recordings = RecordedCommands::default();
+            recordings.add_invocation(
+                serde_json::from_str(
+                    r#"{"binary_name":"vault","args":["read"],"stdout":"hunter2\n","stderr":"","exit_code":0}"#,
+                )
+                .unwrap(),
+            );
+
+            unsafe {
+                std::env::set_var(COMMANDEER_KEY, "test key");
+            }
+
+            save_recordings_sync(&fixture, &recordings).unwrap();
+
+            let stored = std::fs::read(&fixture).unwrap();
+
+            assert!(!stored.windows(7).any(|window| window == b"hunter2"));
+            assert_eq!(
+                serde_json::to_value(load_recordings_sync(&fixture).unwrap()).unwrap(),
+                serde_json::to_value(&recordings).unwrap()
+            );
+
+            unsafe {
+                std::env::set_var(COMMANDEER_KEY, "other key");
+            }
+
+            let wrong = load_recordings_sync(&fixture).unwrap_err();
+
+            unsafe {
+                std::env::remove_var(COMMANDEER_KEY);
+            }
+
+            assert!(
+                wrong.to_string().contains("wrong COMMANDEER_KEY"),
+                "{wrong}"
+            );
+        }
+    }
+}
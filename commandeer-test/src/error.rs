@@ -0,0 +1,95 @@
+use std::{io, path::PathBuf};
+
+/// Failures returned by the commandeer library API.
+#[derive(Debug, thiserror::Error)]
+pub enum CommandeerError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// A recordings file or call log isn't valid JSON.
+    #[error("Failed to parse {}: {source}", path.display())]
+    Parse {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("Failed to serialize recordings: {0}")]
+    Serialize(#[from] serde_json::Error),
+    #[error("Couldn't get parent of recording {}", .0.display())]
+    InvalidFixturePath(PathBuf),
+    /// The command being recorded couldn't be found, e.g. because it is mistyped or not installed.
+    #[error("Command not found: {command} (searched PATH={path})")]
+    CommandNotFound {
+        command: String,
+        /// The `PATH` the command was looked up on.
+        path: String,
+    },
+    #[error("Unknown encoding label '{0}'")]
+    UnknownEncoding(String),
+    /// A fixture's `min_version` is newer than this version of commandeer.
+    #[error("{} requires commandeer >= {required}, but this is {running}", path.display())]
+    FixtureTooNew {
+        path: PathBuf,
+        required: String,
+        running: &'static str,
+    },
+    /// [`crate::Commandeer::validate_fixture`] found the fixture unusable for the test.
+    #[error("Invalid fixture {}: {reason}", path.display())]
+    InvalidFixture { path: PathBuf, reason: String },
+    /// Recordings of more than one invocation matched under [`crate::MatchOptions::require_unique_match`].
+    #[error("`{command}` matched {matches} recordings, but a unique match is required")]
+    AmbiguousMatch { command: String, matches: usize },
+    /// Recording found suspected credentials under [`crate::SecretScan::Deny`].
+    #[error("Refusing to record `{command}`, possible secrets in {findings}")]
+    SecretsDetected { command: String, findings: String },
+    #[error("CARGO_MANIFEST_DIR is not set")]
+    MissingManifestDir,
+    /// A fixture's extension asks for a compression whose crate feature isn't enabled.
+    #[error("{} is compressed, which needs the `{feature}` feature of commandeer-test", path.display())]
+    UnsupportedCompression {
+        path: PathBuf,
+        feature: &'static str,
+    },
+    /// A fixture is a VCR cassette, but the `vcr` feature that reads and writes them isn't enabled.
+    #[error("{} is a VCR cassette, which needs the `vcr` feature of commandeer-test", path.display())]
+    UnsupportedCassette { path: PathBuf },
+    /// A fixture ends in `.enc`, but the `encryption` feature that reads and writes encrypted fixtures isn't enabled.
+    #[error("{} is encrypted, which needs the `encryption` feature of commandeer-test", path.display())]
+    UnsupportedEncryption { path: PathBuf },
+    /// An encrypted fixture couldn't be decrypted or encrypted, e.g. because [`crate::COMMANDEER_KEY`] is unset or
+    /// wrong.
+    #[cfg(feature = "encryption")]
+    #[error("Failed to decrypt or encrypt {}: {reason}", path.display())]
+    Encryption { path: PathBuf, reason: String },
+    /// A VCR cassette isn't valid YAML, or can't be written as it.
+    #[cfg(feature = "vcr")]
+    #[error("Failed to read or write cassette {}: {source}", path.display())]
+    Cassette {
+        path: PathBuf,
+        #[source]
+        source: serde_yaml::Error,
+    },
+    /// The command being recorded ran past [`crate::RecordOptions::timeout`] and was killed.
+    #[error("`{command}` didn't finish within {timeout:?}")]
+    Timeout {
+        command: String,
+        timeout: std::time::Duration,
+    },
+    /// Searching the workspace for the mock binary to build failed or was ambiguous.
+    #[error("Couldn't detect the mock binary: {0}")]
+    MockBinDetection(String),
+    /// A mock wrapper script couldn't be written or made executable.
+    #[error("Failed to write mock {}: {source}", path.display())]
+    WriteMock {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    /// A generated mock wrapper script isn't valid bash, which is a bug in commandeer.
+    #[error("Generated mock {} isn't valid bash: {message}", path.display())]
+    MalformedWrapper { path: PathBuf, message: String },
+    /// Building the `commandeer` mock binary failed.
+    #[error("Failed to build mock binary: {0}")]
+    BuildFailed(#[from] escargot::error::CargoError),
+}
+
+pub type Result<T, E = CommandeerError> = std::result::Result<T, E>;
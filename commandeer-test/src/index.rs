@@ -0,0 +1,255 @@
+use crate::{CommandeerError, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+/// Sidecar index written by [`write_index`], e.g. `cmds.index.json` for `cmds.json`.
+pub fn index_path(fixture: &Path) -> PathBuf {
+    fixture.with_extension("index.json")
+}
+
+/// Byte ranges of each key's recordings within the fixture it was built from.
+#[derive(Serialize, Deserialize)]
+struct FixtureIndex {
+    /// Length and modification time of the fixture, to detect an index that no longer describes it.
+    fixture_len: u64,
+    fixture_modified: Option<u64>,
+    /// The fixture's `min_version`, which lookups leave to a full parse to enforce.
+    #[serde(default)]
+    min_version: Option<String>,
+    /// Offset and length of the JSON array of recordings under each top-level key.
+    entries: BTreeMap<String, (u64, u64)>,
+}
+
+#[derive(Deserialize)]
+struct RawRecordings<'a> {
+    #[serde(default)]
+    min_version: Option<String>,
+    #[serde(borrow, default)]
+    commands: HashMap<String, &'a RawValue>,
+}
+
+/// The fields of a recording its key is computed from.
+#[derive(Deserialize)]
+struct KeyedInvocation {
+    binary_name: String,
+    args: Vec<String>,
+}
+
+/// Whether every recording in `invocations` belongs under `key`, which isn't so for fixtures keyed before
+/// [`crate::recording_key`] escaped spaces.
+fn keyed_by(key: &str, invocations: &RawValue) -> bool {
+    serde_json::from_str::<Vec<KeyedInvocation>>(invocations.get()).is_ok_and(|invocations| {
+        invocations.iter().all(|invocation| {
+            crate::recording_key(&invocation.binary_name, &invocation.args) == key
+        })
+    })
+}
+
+fn modified_nanos(metadata: &fs::Metadata) -> Option<u64> {
+    let since_epoch = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+
+    u64::try_from(since_epoch.as_nanos()).ok()
+}
+
+/// Writes the [`index_path`] of `fixture`, letting replay read only the recordings a lookup needs instead of parsing
+/// the whole file.
+///
+/// Only top-level recordings are indexed; replay scoped to a test name, or with fuzzy matching, still parses the whole
+/// fixture. An index left behind by a later change to the fixture is detected and ignored. Compressed or encrypted
+/// fixtures and cassettes can't be read in parts, so none is written for them, nor for fixtures whose keys are out
+/// of date, which are rekeyed by a full parse until they are next saved.
+pub fn write_index(fixture: &Path) -> Result<()> {
+    if crate::compression::is_opaque(fixture) || crate::vcr::is_cassette(fixture) {
+        return Ok(());
+    }
+
+    let contents = fs::read_to_string(fixture)?;
+    let metadata = fs::metadata(fixture)?;
+
+    let raw: RawRecordings = if contents.trim().is_empty() {
+        RawRecordings {
+            min_version: None,
+            commands: HashMap::new(),
+        }
+    } else {
+        serde_json::from_str(&contents).map_err(|source| CommandeerError::Parse {
+            path: fixture.to_path_buf(),
+            source,
+        })?
+    };
+
+    if !raw
+        .commands
+        .iter()
+        .all(|(key, invocations)| keyed_by(key, invocations))
+    {
+        return match fs::remove_file(index_path(fixture)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        };
+    }
+
+    let entries = raw
+        .commands
+        .into_iter()
+        .map(|(key, value)| {
+            let offset = value.get().as_ptr() as usize - contents.as_ptr() as usize;
+
+            (key, (offset as u64, value.get().len() as u64))
+        })
+        .collect();
+
+    let index = FixtureIndex {
+        fixture_len: metadata.len(),
+        fixture_modified: modified_nanos(&metadata),
+        min_version: raw.min_version,
+        entries,
+    };
+
+    fs::write(index_path(fixture), serde_json::to_string(&index)?)?;
+
+    Ok(())
+}
+
+/// The top-level recordings under `key` read through the fixture's index, or `None` if there is no index or it is
+/// out of date.
+#[cfg(feature = "sync")]
+pub(crate) fn lookup(fixture: &Path, key: &str) -> Result<Option<crate::RecordedCommands>> {
+    use crate::CommandInvocation;
+    use std::io::{Read as _, Seek as _, SeekFrom};
+
+    let Ok(index) = fs::read(index_path(fixture)) else {
+        return Ok(None);
+    };
+
+    let Ok(index) = serde_json::from_slice::<FixtureIndex>(&index) else {
+        return Ok(None);
+    };
+
+    let Ok(metadata) = fs::metadata(fixture) else {
+        return Ok(None);
+    };
+
+    if metadata.len() != index.fixture_len
+        || modified_nanos(&metadata) != index.fixture_modified
+        || index.min_version.is_some()
+    {
+        return Ok(None);
+    }
+
+    let mut recordings = crate::RecordedCommands::default();
+
+    let Some(&(offset, len)) = index.entries.get(key) else {
+        return Ok(Some(recordings));
+    };
+
+    let mut entry = vec![0; len as usize];
+    let mut file = fs::File::open(fixture)?;
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut entry)?;
+
+    let invocations: Vec<CommandInvocation> =
+        serde_json::from_slice(&entry).map_err(|source| CommandeerError::Parse {
+            path: fixture.to_path_buf(),
+            source,
+        })?;
+
+    recordings.commands.insert(key.to_string(), invocations);
+
+    Ok(Some(recordings))
+}
+
+#[cfg(all(test, feature = "sync"))]
+mod tests {
+    use super::*;
+    use crate::{CommandInvocation, RecordedCommands};
+
+    fn invocation(binary_name: &str, args: &[&str], stdout: &str) -> CommandInvocation {
+        CommandInvocation {
+            binary_name: binary_name.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            stdout: stdout.into(),
+            stderr: vec![],
+            exit_code: 0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn index_lookup_matches_full_parse() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixture = dir.path().join("indexed.json");
+
+        let mut recordings = RecordedCommands::default();
+        recordings.add_invocation(invocation("git", &["status"], "clean\n"));
+        recordings.add_invocation(invocation("git", &["status"], "dirty\n"));
+        recordings.add_invocation(invocation("echo", &["\"quoted\" ü"], "\"quoted\" ü\n"));
+        recordings
+            .for_test_mut("scoped")
+            .add_invocation(invocation("git", &["log"], "scoped\n"));
+
+        crate::save_recordings_sync(&fixture, &recordings).unwrap();
+        write_index(&fixture).unwrap();
+
+        let full = crate::load_recordings_sync(&fixture).unwrap();
+
+        for key in ["git:status", r#"echo:"quoted"\sü"#, "git:log", "missing:"] {
+            let indexed = lookup(&fixture, key).unwrap().unwrap();
+
+            assert_eq!(
+                serde_json::to_value(indexed.commands.get(key)).unwrap(),
+                serde_json::to_value(full.commands.get(key)).unwrap(),
+                "{key}"
+            );
+        }
+
+        // Rewriting the fixture without reindexing makes the index stale.
+        recordings.add_invocation(invocation("ls", &[], "a\n"));
+        crate::save_recordings_sync(&fixture, &recordings).unwrap();
+
+        assert!(lookup(&fixture, "git:status").unwrap().is_none());
+    }
+
+    #[test]
+    fn stale_keys_are_left_to_full_parse() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixture = dir.path().join("legacy.json");
+
+        // Keyed as before spaces were escaped, with `["a b"]` and `["a", "b"]` sharing a key.
+        fs::write(
+            &fixture,
+            r#"{
+  "commands": {
+    "echo:a b": [
+      { "binary_name": "echo", "args": ["a b"], "stdout": "one\n", "stderr": "", "exit_code": 0 },
+      { "binary_name": "echo", "args": ["a", "b"], "stdout": "two\n", "stderr": "", "exit_code": 0 }
+    ]
+  }
+}"#,
+        )
+        .unwrap();
+        fs::write(index_path(&fixture), "{}").unwrap();
+
+        write_index(&fixture).unwrap();
+
+        assert!(!index_path(&fixture).exists());
+
+        let recordings = crate::load_recordings_sync(&fixture).unwrap();
+        let stdout = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+
+            recordings
+                .find_invocation("echo", &args)
+                .map(|invocation| invocation.stdout.clone())
+        };
+
+        assert_eq!(stdout(&["a b"]).as_deref(), Some(b"one\n".as_slice()));
+        assert_eq!(stdout(&["a", "b"]).as_deref(), Some(b"two\n".as_slice()));
+    }
+}
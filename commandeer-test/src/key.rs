@@ -0,0 +1,186 @@
+//! The keys recordings are stored under in a fixture's `commands` map.
+
+/// Written in place of an empty arg, which would otherwise vanish between two spaces.
+const EMPTY_ARG: &str = r"\e";
+
+/// The key an invocation is stored under in a fixture's `commands` map: the binary name, a `:`, then the args
+/// joined by spaces.
+///
+/// Anything that would make the key ambiguous is escaped with a backslash: `\s` for a space inside an arg, `\\`,
+/// `\n`, `\r`, `\t`, `\u{..}` for other control characters and a `:` in the binary name, and `\e` for an empty arg.
+/// Plain args are left as they are, so `["a b"]` and `["a", "b"]` get distinct keys and [`parse_recording_key`]
+/// recovers the invocation from either.
+///
+/// ```
+/// use commandeer_test::recording_key;
+///
+/// assert_eq!(recording_key("git", &["log", "--oneline"]), "git:log --oneline");
+/// assert_eq!(recording_key("git", &["commit", "-m", "a b"]), r"git:commit -m a\sb");
+/// assert_eq!(recording_key("pwd", &[] as &[&str]), "pwd:");
+/// assert_eq!(recording_key("pwd", &[""]), r"pwd:\e");
+/// ```
+pub fn recording_key(binary_name: &str, args: &[impl AsRef<str>]) -> String {
+    let mut key = String::new();
+
+    escape_into(&mut key, binary_name, true);
+    key.push(':');
+
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            key.push(' ');
+        }
+
+        match arg.as_ref() {
+            "" => key.push_str(EMPTY_ARG),
+            arg => escape_into(&mut key, arg, false),
+        }
+    }
+
+    key
+}
+
+/// The binary name and args [`recording_key`] made `key` from, or `None` if it isn't such a key.
+pub fn parse_recording_key(key: &str) -> Option<(String, Vec<String>)> {
+    let (binary_name, args) = key.split_once(':')?;
+    let binary_name = unescape(binary_name)?;
+
+    if args.is_empty() {
+        return Some((binary_name, vec![]));
+    }
+
+    let args = args
+        .split(' ')
+        .map(|arg| match arg {
+            EMPTY_ARG => Some(String::new()),
+            "" => None,
+            arg => unescape(arg),
+        })
+        .collect::<Option<_>>()?;
+
+    Some((binary_name, args))
+}
+
+fn escape_into(key: &mut String, word: &str, binary_name: bool) {
+    for c in word.chars() {
+        match c {
+            '\\' => key.push_str(r"\\"),
+            ' ' => key.push_str(r"\s"),
+            '\n' => key.push_str(r"\n"),
+            '\r' => key.push_str(r"\r"),
+            '\t' => key.push_str(r"\t"),
+            ':' if binary_name => key.push_str(r"\u{3a}"),
+            c if c.is_control() => key.push_str(&format!(r"\u{{{:x}}}", c as u32)),
+            c => key.push(c),
+        }
+    }
+}
+
+fn unescape(word: &str) -> Option<String> {
+    let mut out = String::new();
+    let mut chars = word.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+
+        match chars.next()? {
+            '\\' => out.push('\\'),
+            's' => out.push(' '),
+            'n' => out.push('\n'),
+            'r' => out.push('\r'),
+            't' => out.push('\t'),
+            'u' => {
+                let rest = chars.as_str().strip_prefix('{')?;
+                let (hex, rest) = rest.split_once('}')?;
+
+                out.push(char::from_u32(u32::from_str_radix(hex, 16).ok()?)?);
+                chars = rest.chars();
+            }
+            _ => return None,
+        }
+    }
+
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn round_trip(binary_name: &str, args: &[&str]) {
+        let key = recording_key(binary_name, args);
+        let parsed = parse_recording_key(&key);
+
+        assert_eq!(
+            parsed,
+            Some((
+                binary_name.to_string(),
+                args.iter().map(|arg| arg.to_string()).collect()
+            )),
+            "{key}"
+        );
+    }
+
+    #[test]
+    fn regression_args_round_trip() {
+        round_trip("echo", &["nul\0byte"]);
+        round_trip("echo", &["line\nbreak", "cr\r", "tab\t"]);
+        round_trip("echo", &["🦀 crab", "👍"]);
+        round_trip("echo", &["", "", "x", ""]);
+        round_trip("echo", &[r"back\slash", r"\s", r"\e", r"\u{41}"]);
+        round_trip("odd:name", &[":"]);
+        round_trip("", &[]);
+
+        assert_eq!(recording_key("echo", &["nul\0byte"]), r"echo:nul\u{0}byte");
+        assert_eq!(recording_key("echo", &["👍"]), "echo:👍");
+        assert_ne!(
+            recording_key("pwd", &[""]),
+            recording_key("pwd", &[] as &[&str])
+        );
+        assert_ne!(
+            recording_key("echo", &["a b"]),
+            recording_key("echo", &["a", "b"])
+        );
+    }
+
+    #[test]
+    fn malformed_keys_are_rejected() {
+        for key in [
+            "no-colon",
+            r"echo:\q",
+            r"echo:\u{zz}",
+            r"echo:\u{d800}",
+            "echo:a  b",
+            r"echo:\",
+        ] {
+            assert_eq!(parse_recording_key(key), None, "{key}");
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn keys_round_trip(binary_name in any::<String>(), args in prop::collection::vec(any::<String>(), 0..6)) {
+            let key = recording_key(&binary_name, &args);
+
+            prop_assert_eq!(parse_recording_key(&key), Some((binary_name, args)));
+        }
+
+        #[test]
+        fn distinct_invocations_get_distinct_keys(
+            a in ("[a-z:]{0,3}", prop::collection::vec("[ a-z\\\\:\0\n]{0,4}", 0..4)),
+            b in ("[a-z:]{0,3}", prop::collection::vec("[ a-z\\\\:\0\n]{0,4}", 0..4)),
+        ) {
+            prop_assume!(a != b);
+
+            prop_assert_ne!(recording_key(&a.0, &a.1), recording_key(&b.0, &b.1));
+        }
+
+        #[test]
+        fn joining_args_changes_the_key(args in prop::collection::vec("[a-z ]{0,4}", 2..5)) {
+            prop_assert_ne!(recording_key("sh", &args), recording_key("sh", &[args.join(" ")]));
+        }
+    }
+}
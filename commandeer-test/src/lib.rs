@@ -1,41 +1,276 @@
-use anyhow::{Result, anyhow};
 use escargot::CargoBuild;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
-    env, fmt, fs,
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, HashSet},
+    env,
+    fmt::{self, Write as _},
+    fs,
+    io::{Read, Write},
     path::{Path, PathBuf},
+    process::{Output, Stdio},
+    sync::{Arc, Mutex, OnceLock, mpsc},
+    time::{Duration, Instant},
 };
+use stdin::StdinFeed;
 use tempfile::TempDir;
-use tokio::{
-    fs::{DirBuilder, try_exists},
-    io::AsyncReadExt as _,
-    process::Command,
-};
+#[cfg(feature = "async")]
+use tokio::{fs::DirBuilder, io::AsyncReadExt as _, process::Command};
 
+pub use call_log::{
+    COMMANDEER_PARENT, CallLogEntry, SpawnNode, append_call_log, read_call_log, spawn_tree,
+};
 pub use commandeer_macros::commandeer;
+pub use diff::{Difference, DifferenceKind, Field, FixtureDiff};
+pub use encryption::COMMANDEER_KEY;
+pub use error::{CommandeerError, Result};
+pub use index::{index_path, write_index};
+pub use key::{parse_recording_key, recording_key};
+pub use locale::{LOCALE_VAR, active_locale};
+pub use matching::{
+    EnvMatch, MatchOptions, NumericTolerance, TEMP_PATH_TOKEN, canonicalize_json_args,
+    expand_response_files, normalize_temp_paths, sort_flag_args, split_option_args,
+};
+pub use output_files::WrittenFile;
+pub use replayer::Replayer;
+pub use rusage::{FD_COUNT_DRIFT, ResourceUsage, open_fd_count};
+pub use secrets::SecretScan;
+pub use signal::SignalReplay;
+pub use summary::{
+    COMMANDEER_SUMMARY, Outcome, Summary, SummaryEntry, append_summary, read_summary,
+};
+pub use timing::TimingStats;
+pub use transform::{BuiltinTransform, HOME_TOKEN, TIMESTAMP_TOKEN, Transform};
+pub use verify::ExitCodes;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+mod blob_store;
+mod call_log;
+mod compression;
+mod diff;
+mod encryption;
+mod error;
+mod index;
+mod key;
+mod locale;
+mod matching;
+mod mock_bin;
+mod output_files;
+mod replayer;
+mod rusage;
+mod secrets;
+mod signal;
+mod stdin;
+mod summary;
+mod template;
+mod timing;
+mod transform;
+mod vcr;
+mod verify;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct CommandInvocation {
     pub binary_name: String,
+    /// The args as recordings are keyed, after the normalizations [`RecordOptions`] enables.
     pub args: Vec<String>,
-    pub stdout: String,
-    pub stderr: String,
+    /// The args the command was actually invoked with, when normalizing them for the key changed them; re-running
+    /// the recording, as `refresh` and exported scripts do, uses these. See
+    /// [`Self::original_args`](method@Self::original_args).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_args: Option<Vec<String>>,
+    /// The bytes the command wrote to stdout, replayed as is. Stored as a string when they are UTF-8 and as an array
+    /// of bytes otherwise.
+    #[serde(with = "stdin::bytes")]
+    pub stdout: Vec<u8>,
+    /// The bytes the command wrote to stderr, stored like [`Self::stdout`].
+    #[serde(with = "stdin::bytes")]
+    pub stderr: Vec<u8>,
     pub exit_code: i32,
+    /// Encoding the command emitted its output in, as an [`encoding_rs`] label.
+    ///
+    /// Only a hint for showing `stdout` and `stderr`, see [`Self::decode`]: they are recorded, matched and replayed as
+    /// raw bytes. `None` means UTF-8.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+    /// Values of the [`RecordOptions::capture_env`] allowlist when recorded; unset variables are left out.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// Template file rendered at replay in place of `stdout`, for outputs too large or dynamic to store inline.
+    ///
+    /// Takes precedence over `stdout`, which is then ignored. Relative paths are resolved against the fixture's
+    /// directory. See [`Self::render_stdout_template`] for the substitutions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdout_template: Option<PathBuf>,
+    /// File holding the raw stdout bytes, streamed at replay by [`output_invocation_streaming`] without loading it
+    /// into memory.
+    ///
+    /// For multi-megabyte outputs. Takes precedence over both `stdout` and `stdout_template`. Relative paths are
+    /// resolved against the fixture's directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdout_blob: Option<PathBuf>,
+    /// Stdin the command was fed while recording, when [`RecordOptions::capture_stdin`] was set.
+    ///
+    /// Diagnostic only: replay doesn't read stdin or consider it when matching. Stored as a string when it is UTF-8
+    /// and as an array of bytes otherwise.
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "stdin")]
+    pub stdin: Option<Vec<u8>>,
+    /// How many bytes of [`Self::stdin`] the command read before exiting, e.g. 5 of 100 for `dd bs=1 count=5`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdin_consumed: Option<usize>,
+    /// How long the command took to record, when [`RecordOptions::record_timing`] was set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    /// The binary the command was last seen running while recording, when [`RecordOptions::record_executable`] was
+    /// set, e.g. `/usr/bin/python3.12` for a `tool` script that execs into Python.
+    ///
+    /// Diagnostic only, for untangling layered wrappers. It is polled every few milliseconds on Linux and not
+    /// recorded elsewhere, so an exec shortly before the command exits can be missed, leaving an earlier link of the
+    /// chain.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub executable: Option<PathBuf>,
+    /// CPU time and peak memory of the command, when [`RecordOptions::record_rusage`] was set, shown by
+    /// `list --verbose`. Diagnostic only and never used for matching.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rusage: Option<ResourceUsage>,
+    /// File descriptors open in the recording process as the command started, when
+    /// [`RecordOptions::record_fd_count`] was set; those not close-on-exec are inherited by the command. Replaying
+    /// with more than [`FD_COUNT_DRIFT`] more or fewer open warns, as a hint for tools whose behavior depends on
+    /// what they inherit. Diagnostic only and never used for matching.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fd_count: Option<usize>,
+    /// Signal that killed the command, on Unix, in which case [`Self::exit_code`] is the 128 + signal number a shell
+    /// reports. Replayed as [`SignalReplay`] says.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signal: Option<i32>,
+    /// Picks between recordings that match the same invocation, highest first, e.g. the same key recorded under
+    /// different [`Self::env`] or several args within [`MatchOptions::numeric_tolerance`]. `None` counts as 0, and
+    /// among equal priorities an exact match wins over a fuzzy one and then the earliest recording.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i32>,
+    /// Path of the mock wrapper that intercepted the invocation while recording, to spot conflicting shims on `PATH`.
+    ///
+    /// Diagnostic only. It lies in a per-run temp dir, so it changes whenever the invocation is re-recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wrapper: Option<PathBuf>,
+    /// Files the command wrote through one of [`RecordOptions::output_file_options`], recreated on replay.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub writes_files: Vec<WrittenFile>,
+}
+
+/// The candidate with the highest [`CommandInvocation::priority`], the earliest among equals.
+pub(crate) fn highest_priority<'a>(
+    candidates: impl IntoIterator<Item = &'a CommandInvocation>,
+) -> Option<&'a CommandInvocation> {
+    candidates
+        .into_iter()
+        .fold(None, |best, candidate| match best {
+            Some(best) if best.priority.unwrap_or(0) >= candidate.priority.unwrap_or(0) => {
+                Some(best)
+            }
+            _ => Some(candidate),
+        })
+}
+
+/// Resolves `path` from a recording against the directory of the fixture it came from.
+fn fixture_relative(fixture: &Path, path: &Path) -> PathBuf {
+    fixture
+        .parent()
+        .map_or_else(|| path.to_path_buf(), |dir| dir.join(path))
+}
+
+impl CommandInvocation {
+    /// The args to re-run this recording with: [`Self::original_args`](field@Self::original_args) if set, else
+    /// [`Self::args`].
+    pub fn original_args(&self) -> &[String] {
+        self.original_args.as_deref().unwrap_or(&self.args)
+    }
+
+    /// Replaces `stdout` with the rendered [`Self::stdout_template`], if there is one, for a replay of
+    /// `args` from `fixture`.
+    ///
+    /// `$ARG1`, `$ARG2`, ... expand to the invoked args (1-based), `${NAME}` to the environment variable `NAME`, and
+    /// `$$` to a literal `$`. Missing args and unset variables expand to nothing.
+    pub fn render_stdout_template(&mut self, fixture: &Path, args: &[String]) -> Result<()> {
+        if self.stdout_blob.is_some() {
+            return Ok(());
+        }
+
+        let Some(template) = self.stdout_template.take() else {
+            return Ok(());
+        };
+
+        let rendered = template::render(
+            &fs::read_to_string(fixture_relative(fixture, &template))?,
+            args,
+        );
+        self.stdout = self.encode(&rendered).into_owned();
+
+        Ok(())
+    }
+
+    fn encoding(&self) -> Option<&'static encoding_rs::Encoding> {
+        self.encoding
+            .as_deref()
+            .and_then(|label| encoding_rs::Encoding::for_label(label.as_bytes()))
+    }
+
+    fn encode<'a>(&self, text: &'a str) -> Cow<'a, [u8]> {
+        match self.encoding() {
+            Some(encoding) => encoding.encode(text).0,
+            None => Cow::Borrowed(text.as_bytes()),
+        }
+    }
+
+    /// Decodes `output` recorded by this invocation, e.g. its [`Self::stdout`], for display in
+    /// [`Self::encoding`](field@Self::encoding). Invalid sequences are shown as U+FFFD.
+    pub fn decode<'a>(&self, output: &'a [u8]) -> Cow<'a, str> {
+        match self.encoding() {
+            Some(encoding) => encoding.decode_without_bom_handling(output).0,
+            None => String::from_utf8_lossy(output),
+        }
+    }
+
+    /// Rewrites `stdout` and `stderr` with `edit` as text in [`Self::encoding`], for transforms.
+    ///
+    /// Output that doesn't decode cleanly, e.g. binary data, is left as is rather than rewritten lossily.
+    pub(crate) fn edit_outputs(&mut self, edit: impl Fn(&str) -> String) {
+        let encoding = self.encoding().unwrap_or(encoding_rs::UTF_8);
+
+        // UTF-16 decodes, but encoding_rs only encodes it back as UTF-8.
+        if encoding.output_encoding() != encoding {
+            return;
+        }
+
+        for output in [&mut self.stdout, &mut self.stderr] {
+            let Some(text) = encoding.decode_without_bom_handling_and_without_replacement(output)
+            else {
+                continue;
+            };
+
+            let edited = edit(&text);
+            *output = encoding.encode(&edited).0.into_owned();
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct RecordedCommands {
+    /// Oldest commandeer version that can load this fixture, so an older mock binary errors instead of misreading
+    /// fields it doesn't know. Checked when the fixture is loaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_version: Option<String>,
+    /// What the fixture's scenario is and why it exists, written by hand and shown by `list`. Kept when the fixture is
+    /// re-recorded from scratch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
     commands: HashMap<String, Vec<CommandInvocation>>,
+    /// Recordings scoped to a single test, for fixtures shared by several tests.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    tests: HashMap<String, RecordedCommands>,
 }
 
 impl RecordedCommands {
-    fn generate_key(binary_name: &str, args: &[String]) -> String {
-        format!("{binary_name}:{}", args.join(" "))
-    }
-
     pub fn add_invocation(&mut self, invocation: CommandInvocation) {
-        let key = Self::generate_key(&invocation.binary_name, &invocation.args);
+        let key = recording_key(&invocation.binary_name, &invocation.args);
 
         self.commands.entry(key).or_default().push(invocation);
     }
@@ -45,288 +280,5806 @@ impl RecordedCommands {
         binary_name: &str,
         args: &[String],
     ) -> Option<&CommandInvocation> {
-        let key = Self::generate_key(binary_name, args);
+        highest_priority(self.invocations(binary_name, args))
+    }
 
-        self.commands.get(&key)?.first()
+    /// All invocations recorded for `binary_name` with `args`, in recording order.
+    pub fn invocations(&self, binary_name: &str, args: &[String]) -> &[CommandInvocation] {
+        let key = recording_key(binary_name, args);
+
+        self.commands.get(&key).map_or(&[], Vec::as_slice)
     }
-}
 
-pub async fn load_recordings(file_path: &PathBuf) -> Result<RecordedCommands> {
-    let mut f = tokio::fs::File::options();
+    /// Recordings belonging to `test_name` in a shared fixture.
+    pub fn for_test(&self, test_name: &str) -> Option<&RecordedCommands> {
+        self.tests.get(test_name)
+    }
 
-    let mut contents = String::new();
-    f.create(true)
-        .write(true)
-        .read(true)
-        .open(file_path)
-        .await?
-        .read_to_string(&mut contents)
-        .await?;
+    /// Recordings belonging to `test_name` in a shared fixture, created if missing.
+    pub fn for_test_mut(&mut self, test_name: &str) -> &mut RecordedCommands {
+        self.tests.entry(test_name.to_string()).or_default()
+    }
 
-    if contents.trim().is_empty() {
-        return Ok(RecordedCommands::default());
+    /// Drops every recording of `binary_name`, whatever its args, at the top level and in every test scope, returning
+    /// how many keys were removed.
+    pub fn remove_command(&mut self, binary_name: &str) -> usize {
+        let before = self.commands.len();
+
+        self.commands.retain(|_, invocations| {
+            invocations
+                .iter()
+                .all(|invocation| invocation.binary_name != binary_name)
+        });
+
+        let scoped: usize = self
+            .tests
+            .values_mut()
+            .map(|scope| scope.remove_command(binary_name))
+            .sum();
+
+        before - self.commands.len() + scoped
     }
 
-    let recordings: RecordedCommands = serde_json::from_str(&contents)?;
+    /// Lays `top` over these recordings: each of its keys replaces the whole recorded vector of that key here, and
+    /// test scopes are overlaid the same way.
+    pub fn overlay(&mut self, top: RecordedCommands) {
+        self.commands.extend(top.commands);
 
-    Ok(recordings)
-}
+        for (test_name, recordings) in top.tests {
+            self.for_test_mut(&test_name).overlay(recordings);
+        }
 
-pub async fn save_recordings(file_path: &PathBuf, recordings: &RecordedCommands) -> Result<()> {
-    let json = serde_json::to_string_pretty(recordings)?;
+        if top.min_version.is_some() {
+            self.min_version = top.min_version;
+        }
 
-    tokio::fs::write(file_path, json.as_bytes()).await?;
+        if top.description.is_some() {
+            self.description = top.description;
+        }
+    }
 
-    Ok(())
-}
+    /// Whether any top-level recording is of `binary_name`.
+    fn records(&self, binary_name: &str) -> bool {
+        self.commands
+            .values()
+            .flatten()
+            .any(|invocation| invocation.binary_name == binary_name)
+    }
 
-pub async fn record_command(
-    truncate: bool,
-    file_path: PathBuf,
-    command: String,
-    args: Vec<String>,
-) -> Result<CommandInvocation> {
-    let recording_dir = file_path
-        .parent()
-        .ok_or_else(|| anyhow!("Couldn't get parent of recording {}", file_path.display()))?;
+    /// Files every recording under the [`recording_key`] of its own binary name and args.
+    ///
+    /// Fixtures recorded before keys escaped spaces used `a b` for both `["a b"]` and `["a", "b"]`, so their keys
+    /// are recomputed on load rather than trusted.
+    fn rekey(&mut self) {
+        let stale = self.commands.iter().any(|(key, invocations)| {
+            invocations
+                .iter()
+                .any(|invocation| recording_key(&invocation.binary_name, &invocation.args) != *key)
+        });
 
-    DirBuilder::new()
-        .recursive(true)
-        .create(recording_dir)
-        .await?;
+        if stale {
+            let mut commands: Vec<_> = std::mem::take(&mut self.commands).into_iter().collect();
+            commands.sort_by(|a, b| a.0.cmp(&b.0));
 
-    let mut recordings = if truncate {
-        if try_exists(&file_path).await? {
-            tokio::fs::remove_file(&file_path).await?;
+            for invocation in commands
+                .into_iter()
+                .flat_map(|(_, invocations)| invocations)
+            {
+                self.add_invocation(invocation);
+            }
         }
 
-        RecordedCommands::default()
-    } else {
-        load_recordings(&file_path).await?
-    };
+        for recordings in self.tests.values_mut() {
+            recordings.rekey();
+        }
+    }
 
-    let output = Command::new(&command).args(&args).output().await?;
+    /// No recordings, only [`Self::description`], for re-recording a fixture from scratch.
+    fn cleared(self) -> Self {
+        Self {
+            description: self.description,
+            ..Self::default()
+        }
+    }
 
-    let invocation = CommandInvocation {
-        binary_name: command,
-        args,
-        stdout: String::from_utf8_lossy(&output.stdout).to_string(),
-        stderr: String::from_utf8_lossy(&output.stderr).to_string(),
-        exit_code: output.status.code().unwrap_or(-1),
-    };
+    /// Drops every recording belonging to `test_name`, returning whether there were any.
+    pub fn remove_test(&mut self, test_name: &str) -> bool {
+        self.tests.remove(test_name).is_some()
+    }
 
-    recordings.add_invocation(invocation.clone());
-    save_recordings(&file_path, &recordings).await?;
+    /// Adds `invocation`, then drops the oldest recordings under its key beyond `max_per_key`.
+    fn add_invocation_capped(&mut self, invocation: CommandInvocation, max_per_key: Option<usize>) {
+        let key = recording_key(&invocation.binary_name, &invocation.args);
+        let invocations = self.commands.entry(key).or_default();
 
-    Ok(invocation)
-}
+        invocations.push(invocation);
 
-pub async fn replay_command(
-    file_path: PathBuf,
-    command: String,
-    args: Vec<String>,
-) -> Result<Option<CommandInvocation>> {
-    let recordings = load_recordings(&file_path).await?;
+        if let Some(max) = max_per_key {
+            let excess = invocations.len().saturating_sub(max.max(1));
 
-    Ok(recordings.find_invocation(&command, &args).cloned())
-}
+            invocations.drain(..excess);
+        }
+    }
 
-pub fn output_invocation(invocation: &CommandInvocation) {
-    print!("{}", invocation.stdout);
-    eprint!("{}", invocation.stderr);
+    fn scope_mut(&mut self, test_name: Option<&str>) -> &mut RecordedCommands {
+        match test_name {
+            Some(test_name) => self.for_test_mut(test_name),
+            None => self,
+        }
+    }
 }
 
-pub fn exit_with_code(code: i32) -> ! {
-    std::process::exit(code);
+/// Options controlling how [`record_command_with`] and [`record_command_sync_with`] run and store a command.
+#[derive(Debug, Clone, Default)]
+pub struct RecordOptions {
+    /// Whether to truncate the file before recording.
+    pub truncate: bool,
+    /// Executable to run in place of resolving the command name on `PATH`.
+    ///
+    /// The invocation is still stored under the command name, which lets a recording key such as `gcc` stand in for
+    /// `/opt/toolchain/bin/gcc`.
+    pub program: Option<PathBuf>,
+    /// Encoding label (e.g. `latin1`, `shift_jis`) stored as
+    /// [`CommandInvocation::encoding`](field@CommandInvocation::encoding), which `list` decodes the recorded output in.
+    pub encoding: Option<String>,
+    /// `argv[0]` to run the real command with, for multi-call binaries such as busybox that dispatch on it.
+    ///
+    /// Defaults to the program path. Only honoured on Unix.
+    pub arg0: Option<String>,
+    /// Store the invocation under this test in a shared fixture; truncating then only clears that test.
+    pub test_name: Option<String>,
+    /// Store args with temp paths replaced, see [`normalize_temp_paths`]. The real command still gets the real args.
+    pub normalize_temp_paths: bool,
+    /// Store args with `@file` response files expanded and hashed, see [`expand_response_files`]. The real command
+    /// still gets the `@file` args.
+    pub response_files: bool,
+    /// Store args that are JSON documents in canonical form, see [`canonicalize_json_args`]. The real command still
+    /// gets the args as given.
+    pub canonical_json: bool,
+    /// Options whose attached values are stored as a separate arg, see [`split_option_args`].
+    pub split_options: Vec<String>,
+    /// Store args with flags sorted, given the options that take a value; see [`sort_flag_args`].
+    pub unordered_flags: Option<Vec<String>>,
+    /// Environment variables whose values are captured in [`CommandInvocation::env`], for [`EnvMatch`] on replay.
+    pub capture_env: Vec<String>,
+    /// Run the command once under each of these locales, with [`LOCALE_VAR`] set to it, and store a recording of
+    /// each with the locale in its [`CommandInvocation::env`], for replay under [`MatchOptions::match_locale`].
+    ///
+    /// The command's own output is the recording under the active locale, see [`active_locale`], or the first one if
+    /// that isn't among them.
+    pub locales: Vec<String>,
+    /// Applied in order to each invocation after it runs and before it is saved.
+    pub transforms: Vec<Transform>,
+    /// `SOURCE_DATE_EPOCH` to run the real command with, so tools honouring it embed a fixed time.
+    pub source_date_epoch: Option<u64>,
+    /// `FAKETIME` to run the real command with, e.g. `@2024-01-01 00:00:00`.
+    ///
+    /// Only takes effect when libfaketime is preloaded into the command, e.g. via `LD_PRELOAD` in the environment.
+    pub faketime: Option<String>,
+    /// Finish recording once the command exits instead of waiting for EOF on its output.
+    ///
+    /// Daemonizing commands (e.g. `ssh -f`) leave a background process holding the output pipes, which would otherwise
+    /// block recording forever. Only output written before the command exits, plus whatever arrives within
+    /// [`NO_WAIT_DRAIN`] after, is recorded; anything the background process writes later is dropped.
+    pub no_wait_output: bool,
+    /// Copy the command's stdout and stderr to this process's as they arrive, while still buffering them for the
+    /// recording, so a long-running command shows progress instead of staying silent until it exits.
+    pub live_output: bool,
+    /// Directory of a content-addressed store that recorded stdout is moved into, referenced from the fixture by
+    /// [`CommandInvocation::stdout_blob`].
+    ///
+    /// Blobs are named by a hash of their contents, so fixtures sharing a store hold each distinct output once.
+    pub blob_store: Option<PathBuf>,
+    /// Rewrite the fixture's [`index_path`] after recording, so replay can read single entries without parsing the
+    /// whole file; see [`write_index`].
+    pub index: bool,
+    /// Read this process's stdin to EOF, feed it to the command and store it in [`CommandInvocation::stdin`].
+    ///
+    /// Without it the command runs with stdin closed. Only enable it when stdin is a pipe or file that will reach EOF,
+    /// as recording otherwise blocks waiting for input.
+    pub capture_stdin: bool,
+    /// Store how long the command took in [`CommandInvocation::duration_ms`], for `list --timing`.
+    pub record_timing: bool,
+    /// Store the binary the command ended up running in [`CommandInvocation::executable`].
+    pub record_executable: bool,
+    /// Store the command's CPU time and peak memory in [`CommandInvocation::rusage`]. Only supported on Unix, and
+    /// ignored elsewhere.
+    pub record_rusage: bool,
+    /// Store how many file descriptors were open as the command started in [`CommandInvocation::fd_count`]. Only
+    /// supported on Unix, and ignored elsewhere.
+    pub record_fd_count: bool,
+    /// Check args, captured env and stdin, and output for credentials before saving, see [`SecretScan`].
+    pub scan_secrets: SecretScan,
+    /// Keep at most this many recordings per key, dropping the oldest, so retry loops don't grow the fixture without
+    /// bound. Treated as 1 when 0.
+    pub max_per_key: Option<usize>,
+    /// Mock wrapper that intercepted the invocation, stored as [`CommandInvocation::wrapper`].
+    pub wrapper: Option<PathBuf>,
+    /// Options whose value is a file the command writes, e.g. `-o` for `tool -o out.txt`, snapshotted into
+    /// [`CommandInvocation::writes_files`] after it runs.
+    pub output_file_options: Vec<String>,
+    /// Kill the command and fail with [`CommandeerError::Timeout`] if it hasn't finished within this long, recording
+    /// nothing. Without [`Self::no_wait_output`] the command has finished once it exits and its output reaches EOF.
+    pub timeout: Option<Duration>,
+    /// Args passed to the real command ahead of the invocation's own, where global options such as git's
+    /// `--no-pager` go. The recording is still stored and matched under the original args.
+    pub extra_args: Vec<String>,
 }
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
-pub enum Mode {
-    Record,
-    Replay,
-}
+/// How long [`RecordOptions::no_wait_output`] keeps reading output after the command has exited.
+pub const NO_WAIT_DRAIN: Duration = Duration::from_millis(100);
 
-pub struct Commandeer {
-    mock_runner: escargot::CargoRun,
-    temp_dir: TempDir,
-    fixture: PathBuf,
-    mode: Mode,
-    original_path: String,
-}
+/// How often a running command is checked on for [`RecordOptions::timeout`], [`RecordOptions::record_executable`]
+/// and [`RecordOptions::record_rusage`].
+const POLL: Duration = Duration::from_millis(10);
 
-impl fmt::Display for Mode {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Mode::Record => write!(f, "record"),
-            Mode::Replay => write!(f, "replay"),
-        }
+impl RecordOptions {
+    /// How to wait for the command, when plain [`std::process::Command::output`] won't do.
+    fn wait(&self) -> Option<Wait> {
+        let rusage = cfg!(unix) && self.record_rusage;
+
+        (self.no_wait_output
+            || self.live_output
+            || self.timeout.is_some()
+            || self.record_executable
+            || rusage)
+            .then_some(Wait {
+                no_wait: self.no_wait_output,
+                timeout: self.timeout,
+                watch_executable: self.record_executable,
+                rusage,
+                live: self.live_output,
+            })
     }
-}
 
-impl Commandeer {
-    pub fn new(test_name: impl AsRef<Path>, mode: Mode) -> Self {
-        let dir = PathBuf::from(
-            std::env::var("CARGO_MANIFEST_DIR").expect("Failed to get crate directory."),
-        );
+    /// File descriptors open now, if [`Self::record_fd_count`] is set.
+    fn fd_count(&self) -> Option<usize> {
+        self.record_fd_count.then(open_fd_count).flatten()
+    }
 
-        std::fs::DirBuilder::new()
-            .recursive(true)
-            .create(&dir)
-            .expect("Failed to create testcmds dir");
+    /// Time since `started`, if [`Self::record_timing`] is set.
+    fn duration_ms(&self, started: Instant) -> Option<u64> {
+        self.record_timing
+            .then(|| started.elapsed().as_millis().try_into().unwrap_or(u64::MAX))
+    }
 
-        let fixture = dir.join("testcmds").join(test_name);
+    fn program<'a>(&'a self, command: &'a str) -> &'a Path {
+        self.program.as_deref().unwrap_or(Path::new(command))
+    }
 
-        if fixture.exists() && mode == Mode::Record {
-            std::fs::remove_file(&fixture).expect("Failed to remove existing fixture file");
+    /// The locale of each run of the command, `None` for a single run in the caller's; see [`Self::locales`].
+    fn locale_runs(&self) -> Vec<Option<&str>> {
+        if self.locales.is_empty() {
+            return vec![None];
         }
 
-        let mock_runner = CargoBuild::new()
-            .manifest_path(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml"))
-            .package("commandeer-test")
-            .bin("commandeer")
-            .run()
-            .expect("Failed to build mock binary");
+        self.locales
+            .iter()
+            .map(|locale| Some(locale.as_str()))
+            .collect()
+    }
 
-        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    /// Like [`Self::command`], run under `locale` when set.
+    fn command_in(
+        &self,
+        command: &str,
+        args: &[String],
+        locale: Option<&str>,
+    ) -> std::process::Command {
+        let mut child = self.command(command, args);
 
-        let original_path = std::env::var("PATH").unwrap_or_default();
-        let new_path = format!("{}:{original_path}", temp_dir.path().display());
+        if let Some(locale) = locale {
+            child.env(LOCALE_VAR, locale);
+        }
 
-        unsafe {
-            std::env::set_var("PATH", new_path);
+        child
+    }
+
+    /// The real command to record, with `argv[0]` applied.
+    fn command(&self, command: &str, args: &[String]) -> std::process::Command {
+        let mut child = std::process::Command::new(self.program(command));
+
+        #[cfg(unix)]
+        if let Some(arg0) = &self.arg0 {
+            use std::os::unix::process::CommandExt as _;
+
+            child.arg0(arg0);
         }
 
-        Self {
-            mock_runner,
-            temp_dir,
-            fixture,
-            mode,
-            original_path,
+        if let Some(epoch) = self.source_date_epoch {
+            child.env("SOURCE_DATE_EPOCH", epoch.to_string());
+        }
+
+        if let Some(faketime) = &self.faketime {
+            child.env("FAKETIME", faketime);
         }
+
+        child.args(&self.extra_args);
+        child.args(args);
+
+        child
     }
-    pub fn mock_command(&self, command_name: &str) -> PathBuf {
-        let mock_path = self.temp_dir.path().join(command_name);
+}
 
-        let wrapper = format!(
-            r#"#!/usr/bin/env bash
-exec env PATH="{}" {} {} --file {} --command {command_name} "$@"
-"#,
-            self.original_path,
-            self.mock_runner.path().display(),
-            self.mode,
-            self.fixture.display(),
-        );
+/// Reads `pipe` on a detached thread, since a background process may keep it open long after we're done with it.
+///
+/// Signals `done` at EOF. Each chunk read is also written to `tee` as it arrives, when set.
+fn drain_detached(
+    pipe: Option<impl Read + Send + 'static>,
+    done: mpsc::Sender<()>,
+    tee: Option<fn() -> Box<dyn Write>>,
+) -> Arc<Mutex<Vec<u8>>> {
+    let buffer = Arc::new(Mutex::new(vec![]));
+    let shared = buffer.clone();
 
-        fs::write(&mock_path, wrapper).expect("Failed to write mock wrapper script");
+    std::thread::spawn(move || {
+        let mut chunk = [0; 4096];
 
-        #[cfg(unix)]
-        {
-            use std::os::unix::fs::PermissionsExt as _;
+        if let Some(mut pipe) = pipe {
+            let mut tee = tee.map(|open| open());
 
-            let mut perms = fs::metadata(&mock_path)
-                .expect("Could not get permissions")
-                .permissions();
+            while let Ok(n @ 1..) = pipe.read(&mut chunk) {
+                // Live output is best effort; the recording still gets every byte if our own stdout goes away.
+                if let Some(out) = &mut tee
+                    && (out.write_all(&chunk[..n]).is_err() || out.flush().is_err())
+                {
+                    tee = None;
+                }
 
-            perms.set_mode(0o755);
+                shared
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .extend_from_slice(&chunk[..n]);
+            }
+        }
+
+        let _ = done.send(());
+    });
+
+    buffer
+}
 
-            fs::set_permissions(&mock_path, perms).expect("Could not set permissions");
+/// Output of a recorded command, with what was observed of it while it ran.
+struct RunOutput {
+    output: Output,
+    /// How much of the stdin fed to it the command read.
+    stdin_consumed: Option<usize>,
+    /// The binary it was last seen running, see [`RecordOptions::record_executable`].
+    executable: Option<PathBuf>,
+    /// See [`RecordOptions::record_rusage`].
+    rusage: Option<ResourceUsage>,
+}
+
+impl From<Output> for RunOutput {
+    fn from(output: Output) -> Self {
+        Self {
+            output,
+            stdin_consumed: None,
+            executable: None,
+            rusage: None,
         }
+    }
+}
+
+/// How [`output_detached`] waits for a command.
+#[derive(Debug, Clone, Copy)]
+struct Wait {
+    /// See [`RecordOptions::no_wait_output`].
+    no_wait: bool,
+    timeout: Option<Duration>,
+    /// Poll the binary the command is running, see [`RecordOptions::record_executable`].
+    watch_executable: bool,
+    /// Reap the command with `wait4` to collect its resource usage, see [`RecordOptions::record_rusage`].
+    rusage: bool,
+    /// Copy output to this process's as it arrives, see [`RecordOptions::live_output`].
+    live: bool,
+}
 
-        mock_path
+/// Like [`std::process::Command::output`], but feeds the command `stdin` when set.
+fn output_with_stdin(
+    mut command: std::process::Command,
+    stdin: Option<Vec<u8>>,
+) -> std::io::Result<RunOutput> {
+    if stdin.is_none() {
+        return Ok(command.output()?.into());
     }
+
+    let feed = StdinFeed::attach(&mut command, stdin)?;
+    let output = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?
+        .wait_with_output()?;
+
+    Ok(RunOutput {
+        stdin_consumed: feed.map(StdinFeed::consumed).transpose()?,
+        ..output.into()
+    })
 }
 
-impl Drop for Commandeer {
-    fn drop(&mut self) {
-        unsafe {
-            std::env::set_var("PATH", &self.original_path);
+/// Reads this process's stdin to EOF, for [`RecordOptions::capture_stdin`].
+fn read_stdin() -> std::io::Result<Vec<u8>> {
+    let mut stdin = vec![];
+    std::io::stdin().read_to_end(&mut stdin)?;
+
+    Ok(stdin)
+}
+
+/// Like [`std::process::Command::output`], but reads output on detached threads so that waiting can end early: once
+/// the command exits with `no_wait` (see [`RecordOptions::no_wait_output`]), or at `timeout`, when the command is
+/// killed and this fails with [`std::io::ErrorKind::TimedOut`].
+fn output_detached(
+    mut command: std::process::Command,
+    stdin: Option<Vec<u8>>,
+    wait: Wait,
+) -> std::io::Result<RunOutput> {
+    let Wait {
+        no_wait,
+        timeout,
+        watch_executable,
+        rusage,
+        live,
+    } = wait;
+
+    let feed = StdinFeed::attach(&mut command, stdin)?;
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let (done_tx, done_rx) = mpsc::channel();
+    let tee = |open: fn() -> Box<dyn Write>| live.then_some(open);
+    let stdout = drain_detached(
+        child.stdout.take(),
+        done_tx.clone(),
+        tee(|| Box::new(std::io::stdout())),
+    );
+    let stderr = drain_detached(
+        child.stderr.take(),
+        done_tx,
+        tee(|| Box::new(std::io::stderr())),
+    );
+
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let timed_out = || {
+        std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            "command didn't finish in time",
+        )
+    };
+
+    let mut executable = None;
+    let mut usage = None;
+
+    let status = if deadline.is_some() || watch_executable || rusage {
+        loop {
+            if watch_executable && let Some(exe) = running_executable(child.id()) {
+                executable = Some(exe);
+            }
+
+            if let Some((status, reaped_usage)) = try_reap(&mut child, rusage)? {
+                usage = reaped_usage;
+                break status;
+            }
+
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                let _ = child.kill();
+                child.wait()?;
+
+                return Err(timed_out());
+            }
+
+            std::thread::sleep(POLL);
+        }
+    } else {
+        child.wait()?
+    };
+
+    if no_wait {
+        let drain = Instant::now() + NO_WAIT_DRAIN;
+
+        for _ in 0..2 {
+            if done_rx
+                .recv_timeout(drain.saturating_duration_since(Instant::now()))
+                .is_err()
+            {
+                break;
+            }
+        }
+    } else {
+        for _ in 0..2 {
+            let received = match deadline {
+                Some(deadline) => done_rx
+                    .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                    .is_ok(),
+                None => done_rx.recv().is_ok(),
+            };
+
+            // A background process the command left behind still holds its output open.
+            if !received {
+                return Err(timed_out());
+            }
         }
     }
+
+    let take = |buffer: Arc<Mutex<Vec<u8>>>| {
+        std::mem::take(&mut *buffer.lock().unwrap_or_else(|e| e.into_inner()))
+    };
+
+    let output = Output {
+        status,
+        stdout: take(stdout),
+        stderr: take(stderr),
+    };
+
+    Ok(RunOutput {
+        output,
+        stdin_consumed: feed.map(StdinFeed::consumed).transpose()?,
+        executable,
+        rusage: usage,
+    })
 }
 
-#[cfg(test)]
-mod tests {
-    use crate as commandeer_test;
-    use crate::{Commandeer, Mode, commandeer};
+/// Like [`std::process::Child::try_wait`], but through `wait4` when `rusage` is set so the command's resource usage
+/// is collected too.
+fn try_reap(
+    child: &mut std::process::Child,
+    rusage: bool,
+) -> std::io::Result<Option<(std::process::ExitStatus, Option<ResourceUsage>)>> {
+    #[cfg(unix)]
+    if rusage {
+        return Ok(rusage::try_wait4(child.id())?.map(|(status, usage)| (status, Some(usage))));
+    }
 
-    #[serial_test::serial]
-    fn test_mock_cmd() {
-        let commandeer = Commandeer::new("test_recordings.json", Mode::Replay);
-        let mock_path = commandeer.mock_command("echo");
+    #[cfg(not(unix))]
+    let _ = rusage;
 
-        let status = std::process::Command::new("echo")
-            .arg("foo")
-            .status()
-            .unwrap();
+    Ok(child.try_wait()?.map(|status| (status, None)))
+}
 
-        assert!(status.success());
+/// The binary process `pid` is running, where the OS exposes it.
+fn running_executable(pid: u32) -> Option<PathBuf> {
+    if cfg!(target_os = "linux") {
+        fs::read_link(format!("/proc/{pid}/exe")).ok()
+    } else {
+        None
+    }
+}
 
-        assert!(mock_path.exists());
+/// `major.minor.patch` of a version, ignoring any pre-release or build suffix.
+fn parse_version(version: &str) -> Option<[u64; 3]> {
+    let version = version.split(['-', '+']).next()?;
+    let mut parts = [0; 3];
+
+    for (part, number) in parts.iter_mut().zip(version.split('.')) {
+        *part = number.trim().parse().ok()?;
     }
 
-    #[commandeer(Replay, "echo")]
-    #[serial_test::serial]
-    fn my_test() {
-        let output = std::process::Command::new("echo")
-            .arg("hello")
-            .output()
-            .unwrap();
+    Some(parts)
+}
 
-        assert!(output.status.success());
+/// Serializes `recordings` for the fixture at `file_path`: as a VCR cassette if it is a `.yaml` or `.yml` file, as
+/// JSON otherwise.
+fn serialize_recordings(file_path: &Path, recordings: &RecordedCommands) -> Result<String> {
+    if vcr::is_cassette(file_path) {
+        vcr::to_string(file_path, recordings)
+    } else {
+        Ok(serde_json::to_string_pretty(recordings)?)
     }
+}
 
-    #[commandeer(Replay, "date")]
-    #[tokio::test]
-    #[serial_test::serial]
-    async fn async_replay() {
-        let output = std::process::Command::new("date").output().unwrap();
+fn parse_recordings(file_path: &Path, contents: &str) -> Result<RecordedCommands> {
+    if contents.trim().is_empty() {
+        return Ok(RecordedCommands::default());
+    }
 
-        insta::assert_debug_snapshot!(output, @r#"
-        Output {
-            status: ExitStatus(
-                unix_wait_status(
-                    0,
-                ),
-            ),
-            stdout: "Wed Aug 20 12:46:19 EDT 2025\n",
-            stderr: "",
+    let mut recordings: RecordedCommands = if vcr::is_cassette(file_path) {
+        vcr::parse(file_path, contents)?
+    } else {
+        serde_json::from_str(contents).map_err(|source| CommandeerError::Parse {
+            path: file_path.to_path_buf(),
+            source,
+        })?
+    };
+
+    if let Some(required) = &recordings.min_version {
+        let running = env!("CARGO_PKG_VERSION");
+
+        // An unparseable requirement can't be shown to be met, so it is refused too.
+        if parse_version(required).is_none_or(|required| parse_version(running) < Some(required)) {
+            return Err(CommandeerError::FixtureTooNew {
+                path: file_path.to_path_buf(),
+                required: required.clone(),
+                running,
+            });
         }
-        "#);
     }
 
-    #[commandeer(Replay, "git", "date")]
+    recordings.rekey();
+
+    Ok(recordings)
+}
+
+/// Runs `child`, the real command behind `command`, to completion as `options` say, feeding it `stdin`.
+#[cfg(feature = "sync")]
+fn run_sync(
+    options: &RecordOptions,
+    command: &str,
+    child: std::process::Command,
+    stdin: Option<Vec<u8>>,
+) -> Result<RunOutput> {
+    match options.wait() {
+        Some(wait) => output_detached(child, stdin, wait),
+        None => output_with_stdin(child, stdin),
+    }
+    .map_err(|e| spawn_error(options, command, e))
+}
+
+fn spawn_error(options: &RecordOptions, command: &str, error: std::io::Error) -> CommandeerError {
+    match (error.kind(), options.timeout) {
+        (std::io::ErrorKind::NotFound, _) => CommandeerError::CommandNotFound {
+            command: options.program(command).display().to_string(),
+            path: env::var_os("PATH")
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+        },
+        (std::io::ErrorKind::TimedOut, Some(timeout)) => CommandeerError::Timeout {
+            command: command.to_string(),
+            timeout,
+        },
+        _ => error.into(),
+    }
+}
+
+fn invocation_from_output(
+    options: &RecordOptions,
+    command: String,
+    args: Vec<String>,
+    output: Output,
+) -> Result<CommandInvocation> {
+    let encoding = options
+        .encoding
+        .as_deref()
+        .map(|label| {
+            encoding_rs::Encoding::for_label(label.as_bytes())
+                .ok_or_else(|| CommandeerError::UnknownEncoding(label.to_string()))
+        })
+        .transpose()?;
+
+    let keyed = matching::normalize_args(
+        &args,
+        options.response_files,
+        &options.split_options,
+        options.canonical_json,
+        options.unordered_flags.as_deref(),
+        options.normalize_temp_paths,
+    );
+    let original_args = (keyed != args).then_some(args);
+
+    let signal = signal::terminating(&output.status);
+
+    let mut invocation = CommandInvocation {
+        binary_name: command,
+        args: keyed,
+        original_args,
+        stdout: output.stdout,
+        stderr: output.stderr,
+        exit_code: output
+            .status
+            .code()
+            .or(signal.map(|signal| 128 + signal))
+            .unwrap_or(-1),
+        env: options
+            .capture_env
+            .iter()
+            .filter_map(|name| Some((name.clone(), env::var(name).ok()?)))
+            .collect(),
+        encoding: encoding.map(|encoding| encoding.name().to_string()),
+        signal,
+        wrapper: options.wrapper.clone(),
+        ..Default::default()
+    };
+
+    for transform in &options.transforms {
+        transform.apply(&mut invocation);
+    }
+
+    Ok(invocation)
+}
+
+/// The directory holding the fixture at `file_path`, created before recording into it.
+fn recording_dir(file_path: &Path) -> Result<&Path> {
+    file_path
+        .parent()
+        .ok_or_else(|| CommandeerError::InvalidFixturePath(file_path.to_path_buf()))
+}
+
+/// When a run of the command being recorded started, and what it inherited.
+struct RunStart {
+    instant: Instant,
+    fd_count: Option<usize>,
+}
+
+/// What [`record_command_with`] and [`record_command_sync_with`] share, leaving them to load and save the fixture
+/// and to run the command once per locale.
+struct Recorder<'a> {
+    options: &'a RecordOptions,
+    file_path: &'a Path,
+    command: &'a str,
+    args: &'a [String],
+    recordings: RecordedCommands,
+    /// Fed to every run, see [`RecordOptions::capture_stdin`].
+    stdin: Option<Vec<u8>>,
+    recorded: Vec<CommandInvocation>,
+}
+
+impl<'a> Recorder<'a> {
+    /// Starts recording into `loaded`, the fixture's recordings as they were loaded.
+    fn new(
+        options: &'a RecordOptions,
+        file_path: &'a Path,
+        command: &'a str,
+        args: &'a [String],
+        loaded: Result<RecordedCommands>,
+    ) -> Result<Self> {
+        let mut recordings = if options.truncate && options.test_name.is_none() {
+            // A fixture that no longer loads is replaced outright.
+            loaded.map(RecordedCommands::cleared).unwrap_or_default()
+        } else {
+            loaded?
+        };
+
+        if let (true, Some(test_name)) = (options.truncate, &options.test_name) {
+            recordings.remove_test(test_name);
+        }
+
+        Ok(Self {
+            options,
+            file_path,
+            command,
+            args,
+            recordings,
+            stdin: options.capture_stdin.then(read_stdin).transpose()?,
+            recorded: vec![],
+        })
+    }
+
+    /// The real command to run under `locale`, and when it started.
+    fn start(&self, locale: Option<&str>) -> (std::process::Command, RunStart) {
+        let child = self.options.command_in(self.command, self.args, locale);
+        let start = RunStart {
+            fd_count: self.options.fd_count(),
+            instant: Instant::now(),
+        };
+
+        (child, start)
+    }
+
+    /// Turns the `run` of the command under `locale` into a recording.
+    fn add_run(&mut self, locale: Option<&str>, start: RunStart, run: RunOutput) -> Result<()> {
+        let options = self.options;
+        let writes_files = output_files::capture(self.args, &options.output_file_options)?;
+        let mut invocation = invocation_from_output(
+            options,
+            self.command.to_string(),
+            self.args.to_vec(),
+            run.output,
+        )?;
+        invocation.stdin = self.stdin.clone();
+        invocation.stdin_consumed = run.stdin_consumed;
+        invocation.executable = run.executable;
+        invocation.rusage = run.rusage;
+        invocation.fd_count = start.fd_count;
+        invocation.duration_ms = options.duration_ms(start.instant);
+        invocation.writes_files = writes_files;
+        locale::tag(&mut invocation, locale);
+
+        secrets::check(options.scan_secrets, &invocation, self.file_path)?;
+
+        if let Some(store) = &options.blob_store {
+            blob_store::store_stdout(store, self.file_path, &mut invocation)?;
+        }
+
+        self.recorded.push(invocation);
+
+        Ok(())
+    }
+
+    /// The recordings to save, with every run added, and the run to return for the active locale.
+    fn finish(mut self) -> (RecordedCommands, CommandInvocation) {
+        for invocation in &self.recorded {
+            self.recordings
+                .scope_mut(self.options.test_name.as_deref())
+                .add_invocation_capped(invocation.clone(), self.options.max_per_key);
+        }
+
+        (self.recordings, locale::for_active(self.recorded))
+    }
+}
+
+#[cfg(feature = "async")]
+pub async fn load_recordings(file_path: &PathBuf) -> Result<RecordedCommands> {
+    let mut f = tokio::fs::File::options();
+
+    let mut contents = vec![];
+    f.create(true)
+        .write(true)
+        .read(true)
+        .open(file_path)
+        .await?
+        .read_to_end(&mut contents)
+        .await?;
+
+    parse_recordings(file_path, &compression::decode(file_path, contents)?)
+}
+
+#[cfg(feature = "async")]
+pub async fn save_recordings(file_path: &PathBuf, recordings: &RecordedCommands) -> Result<()> {
+    let json = serialize_recordings(file_path, recordings)?;
+
+    tokio::fs::write(file_path, compression::encode(file_path, json)?).await?;
+
+    Ok(())
+}
+
+#[cfg(feature = "async")]
+pub async fn record_command(
+    truncate: bool,
+    file_path: PathBuf,
+    command: String,
+    args: Vec<String>,
+) -> Result<CommandInvocation> {
+    let options = RecordOptions {
+        truncate,
+        ..RecordOptions::default()
+    };
+
+    record_command_with(&options, file_path, command, args).await
+}
+
+#[cfg(feature = "async")]
+pub async fn record_command_with(
+    options: &RecordOptions,
+    file_path: PathBuf,
+    command: String,
+    args: Vec<String>,
+) -> Result<CommandInvocation> {
+    DirBuilder::new()
+        .recursive(true)
+        .create(recording_dir(&file_path)?)
+        .await?;
+
+    let loaded = load_recordings(&file_path).await;
+    let mut recorder = Recorder::new(options, &file_path, &command, &args, loaded)?;
+
+    for locale in options.locale_runs() {
+        let (child, started) = recorder.start(locale);
+        let stdin = recorder.stdin.clone();
+
+        let run = if let Some(wait) = options.wait() {
+            tokio::task::spawn_blocking(move || output_detached(child, stdin, wait))
+                .await
+                .map_err(std::io::Error::other)?
+        } else if stdin.is_some() {
+            tokio::task::spawn_blocking(move || output_with_stdin(child, stdin))
+                .await
+                .map_err(std::io::Error::other)?
+        } else {
+            Command::from(child).output().await.map(RunOutput::from)
+        }
+        .map_err(|e| spawn_error(options, &command, e))?;
+
+        recorder.add_run(locale, started, run)?;
+    }
+
+    let (recordings, recorded) = recorder.finish();
+    save_recordings(&file_path, &recordings).await?;
+
+    if options.index {
+        write_index(&file_path)?;
+    }
+
+    Ok(recorded)
+}
+
+#[cfg(feature = "async")]
+pub async fn replay_command(
+    file_path: PathBuf,
+    command: String,
+    args: Vec<String>,
+) -> Result<Option<CommandInvocation>> {
+    replay_command_with(&MatchOptions::default(), file_path, command, args).await
+}
+
+/// Async counterpart of [`load_layered_recordings_sync`].
+#[cfg(feature = "async")]
+pub async fn load_layered_recordings(layers: &[PathBuf]) -> Result<RecordedCommands> {
+    let mut recordings = RecordedCommands::default();
+
+    for layer in layers {
+        if tokio::fs::try_exists(layer).await? {
+            recordings.overlay(load_recordings(layer).await?);
+        }
+    }
+
+    Ok(recordings)
+}
+
+/// Async counterpart of [`load_replay_recordings_sync`].
+#[cfg(feature = "async")]
+pub async fn load_replay_recordings(
+    options: &MatchOptions,
+    file_path: &Path,
+) -> Result<RecordedCommands> {
+    let override_file = override_path(file_path);
+
+    if !tokio::fs::try_exists(&override_file).await? && options.fixture_layers.is_empty() {
+        return load_recordings(&file_path.to_path_buf()).await;
+    }
+
+    let mut layers = options.fixture_layers.clone();
+    layers.extend([file_path.to_path_buf(), override_file]);
+
+    load_layered_recordings(&layers).await
+}
+
+#[cfg(feature = "async")]
+pub async fn replay_command_with(
+    options: &MatchOptions,
+    file_path: PathBuf,
+    command: String,
+    args: Vec<String>,
+) -> Result<Option<CommandInvocation>> {
+    let recordings = load_replay_recordings(options, &file_path).await?;
+
+    let Some(mut invocation) = recordings
+        .try_find_matching(&command, &args, options)?
+        .cloned()
+    else {
+        return Ok(None);
+    };
+
+    invocation.render_stdout_template(&file_path, &args)?;
+
+    Ok(Some(invocation))
+}
+
+#[cfg(feature = "sync")]
+pub fn load_recordings_sync(file_path: &PathBuf) -> Result<RecordedCommands> {
+    use std::io::Read as _;
+
+    let mut contents = vec![];
+    fs::File::options()
+        .create(true)
+        .write(true)
+        .read(true)
+        .truncate(false)
+        .open(file_path)?
+        .read_to_end(&mut contents)?;
+
+    parse_recordings(file_path, &compression::decode(file_path, contents)?)
+}
+
+#[cfg(feature = "sync")]
+pub fn save_recordings_sync(file_path: &PathBuf, recordings: &RecordedCommands) -> Result<()> {
+    let json = serialize_recordings(file_path, recordings)?;
+
+    compression::write(file_path, json)?;
+
+    Ok(())
+}
+
+#[cfg(feature = "sync")]
+pub fn record_command_sync(
+    truncate: bool,
+    file_path: PathBuf,
+    command: String,
+    args: Vec<String>,
+) -> Result<CommandInvocation> {
+    let options = RecordOptions {
+        truncate,
+        ..RecordOptions::default()
+    };
+
+    record_command_sync_with(&options, file_path, command, args)
+}
+
+#[cfg(feature = "sync")]
+pub fn record_command_sync_with(
+    options: &RecordOptions,
+    file_path: PathBuf,
+    command: String,
+    args: Vec<String>,
+) -> Result<CommandInvocation> {
+    fs::DirBuilder::new()
+        .recursive(true)
+        .create(recording_dir(&file_path)?)?;
+
+    let loaded = load_recordings_sync(&file_path);
+    let mut recorder = Recorder::new(options, &file_path, &command, &args, loaded)?;
+
+    for locale in options.locale_runs() {
+        let (child, started) = recorder.start(locale);
+        let run = run_sync(options, &command, child, recorder.stdin.clone())?;
+
+        recorder.add_run(locale, started, run)?;
+    }
+
+    let (recordings, recorded) = recorder.finish();
+    save_recordings_sync(&file_path, &recordings)?;
+
+    if options.index {
+        write_index(&file_path)?;
+    }
+
+    Ok(recorded)
+}
+
+/// Re-runs every recording in `scope`, including those of each test, returning how many were refreshed.
+#[cfg(feature = "sync")]
+fn refresh_scope(options: &RecordOptions, scope: &mut RecordedCommands) -> Result<usize> {
+    let mut refreshed = 0;
+
+    for invocation in scope.commands.values_mut().flatten() {
+        if invocation.stdout_template.is_some() || invocation.stdout_blob.is_some() {
+            eprintln!(
+                "commandeer: skipping `{} {}`, its stdout is stored in a separate file",
+                invocation.binary_name,
+                invocation.original_args().join(" ")
+            );
+
+            continue;
+        }
+
+        // Decode with the encoding the recording was made in, unless overridden.
+        let options = RecordOptions {
+            encoding: options.encoding.clone().or(invocation.encoding.clone()),
+            ..options.clone()
+        };
+
+        // A recording made under one of several locales is refreshed under it again.
+        let child = options.command_in(
+            &invocation.binary_name,
+            invocation.original_args(),
+            invocation.env.get(LOCALE_VAR).map(String::as_str),
+        );
+        let fd_count = options.fd_count();
+        let started = Instant::now();
+        let run = run_sync(
+            &options,
+            &invocation.binary_name,
+            child,
+            invocation.stdin.clone(),
+        )?;
+
+        let fresh = invocation_from_output(
+            &options,
+            invocation.binary_name.clone(),
+            invocation.original_args().to_vec(),
+            run.output,
+        )?;
+
+        invocation.stdout = fresh.stdout;
+        invocation.stderr = fresh.stderr;
+        invocation.exit_code = fresh.exit_code;
+        invocation.signal = fresh.signal;
+        invocation.encoding = fresh.encoding;
+        invocation.stdin_consumed = run.stdin_consumed;
+        invocation.executable = run.executable;
+        invocation.rusage = run.rusage;
+        invocation.fd_count = fd_count;
+        invocation.duration_ms = options.duration_ms(started);
+
+        refreshed += 1;
+    }
+
+    for test in scope.tests.values_mut() {
+        refreshed += refresh_scope(options, test)?;
+    }
+
+    Ok(refreshed)
+}
+
+/// Re-runs every recorded invocation in `file_path` against the real command and rewrites its output and exit code
+/// in place, keeping its args, captured env and stdin. Returns how many recordings were refreshed.
+///
+/// `options` applies as when recording, e.g. its transforms. Recordings whose stdout lives in a template or blob file
+/// are skipped.
+#[cfg(feature = "sync")]
+pub fn refresh_recordings_sync(options: &RecordOptions, file_path: &PathBuf) -> Result<usize> {
+    let mut recordings = load_recordings_sync(file_path)?;
+
+    let refreshed = refresh_scope(options, &mut recordings)?;
+
+    save_recordings_sync(file_path, &recordings)?;
+
+    Ok(refreshed)
+}
+
+/// Loads `layers` in order, each overriding the recordings of those before it per key, see
+/// [`RecordedCommands::overlay`]. Layers that don't exist are skipped.
+#[cfg(feature = "sync")]
+pub fn load_layered_recordings_sync(layers: &[PathBuf]) -> Result<RecordedCommands> {
+    let mut recordings = RecordedCommands::default();
+
+    for layer in layers {
+        if fs::exists(layer)? {
+            recordings.overlay(load_recordings_sync(layer)?);
+        }
+    }
+
+    Ok(recordings)
+}
+
+#[cfg(feature = "sync")]
+pub fn replay_command_sync(
+    file_path: PathBuf,
+    command: String,
+    args: Vec<String>,
+) -> Result<Option<CommandInvocation>> {
+    replay_command_sync_with(&MatchOptions::default(), file_path, command, args)
+}
+
+/// The recordings replaying `file_path` under `options` picks from: its [`MatchOptions::fixture_layers`], then the
+/// fixture, then its [`override_path`] sidecar, each overriding the ones before it per key.
+#[cfg(feature = "sync")]
+pub fn load_replay_recordings_sync(
+    options: &MatchOptions,
+    file_path: &Path,
+) -> Result<RecordedCommands> {
+    let override_file = override_path(file_path);
+
+    if !fs::exists(&override_file)? && options.fixture_layers.is_empty() {
+        return load_recordings_sync(&file_path.to_path_buf());
+    }
+
+    let mut layers = options.fixture_layers.clone();
+    layers.extend([file_path.to_path_buf(), override_file]);
+
+    load_layered_recordings_sync(&layers)
+}
+
+#[cfg(feature = "sync")]
+pub fn replay_command_sync_with(
+    options: &MatchOptions,
+    file_path: PathBuf,
+    command: String,
+    args: Vec<String>,
+) -> Result<Option<CommandInvocation>> {
+    let override_file = override_path(&file_path);
+    let overridden = fs::exists(&override_file)?;
+
+    let indexed = if options.test_name.is_none()
+        && !options.is_fuzzy()
+        && options.fixture_layers.is_empty()
+        && !overridden
+    {
+        index::lookup(
+            &file_path,
+            &recording_key(&command, &options.key_args(&args)),
+        )?
+    } else {
+        None
+    };
+
+    let recordings = match indexed {
+        Some(recordings) => recordings,
+        None => load_replay_recordings_sync(options, &file_path)?,
+    };
+
+    let Some(mut invocation) = recordings
+        .try_find_matching(&command, &args, options)?
+        .cloned()
+    else {
+        return Ok(None);
+    };
+
+    invocation.render_stdout_template(&file_path, &args)?;
+
+    Ok(Some(invocation))
+}
+
+pub fn output_invocation(invocation: &CommandInvocation) {
+    // Nothing sensible can be done if the consumer closed its end of the pipe.
+    let _ = std::io::stdout().write_all(&invocation.stdout);
+    let _ = std::io::stdout().flush();
+    let _ = std::io::stderr().write_all(&invocation.stderr);
+}
+
+/// Like [`output_invocation`], but copies a [`CommandInvocation::stdout_blob`] to stdout in chunks.
+///
+/// Returns the number of stdout and stderr bytes written.
+pub fn output_invocation_streaming(invocation: &CommandInvocation, fixture: &Path) -> Result<u64> {
+    let Some(blob) = &invocation.stdout_blob else {
+        output_invocation(invocation);
+
+        let written = invocation.stdout.len() + invocation.stderr.len();
+
+        return Ok(written as u64);
+    };
+
+    let mut blob = fs::File::open(fixture_relative(fixture, blob))?;
+    let mut stdout = std::io::stdout().lock();
+
+    // As in `output_invocation`, a consumer closing its end of the pipe isn't an error.
+    let written = std::io::copy(&mut blob, &mut stdout).unwrap_or_default();
+    let _ = stdout.flush();
+
+    let _ = std::io::stderr().write_all(&invocation.stderr);
+
+    Ok(written + invocation.stderr.len() as u64)
+}
+
+/// Like [`output_invocation_streaming`], but writes stdout and stderr from separate threads at the same time.
+///
+/// Exercises consumers that read both streams concurrently, and doesn't stall one that reads stderr before
+/// stdout. Recordings hold the streams separately without timing, so the interleaving is whatever the threads and
+/// pipes produce rather than what was recorded. Returns the number of stdout and stderr bytes written.
+pub fn output_invocation_concurrent(invocation: &CommandInvocation, fixture: &Path) -> Result<u64> {
+    std::thread::scope(|scope| {
+        let stderr = scope.spawn(|| {
+            // As in `output_invocation`, a consumer closing its end of the pipe isn't an error.
+            let _ = std::io::stderr().write_all(&invocation.stderr);
+
+            invocation.stderr.len() as u64
+        });
+
+        let mut stdout = std::io::stdout().lock();
+
+        let written = match &invocation.stdout_blob {
+            Some(blob) => {
+                let mut blob = fs::File::open(fixture_relative(fixture, blob))?;
+
+                std::io::copy(&mut blob, &mut stdout).unwrap_or_default()
+            }
+            None => {
+                let _ = stdout.write_all(&invocation.stdout);
+
+                invocation.stdout.len() as u64
+            }
+        };
+        let _ = stdout.flush();
+
+        Ok(written + stderr.join().unwrap_or_default())
+    })
+}
+
+/// Like [`output_invocation_streaming`], but writes stdout `chunk_size` bytes at a time, flushing and sleeping for
+/// `delay` between chunks, then stderr.
+///
+/// Simulates a command producing output over time, for consumers that read line by line (progress bars, log
+/// tails). Only the total output matches the recording: chunk boundaries ignore lines and characters. Returns the
+/// number of stdout and stderr bytes written.
+pub fn output_invocation_chunked(
+    invocation: &CommandInvocation,
+    fixture: &Path,
+    chunk_size: usize,
+    delay: Duration,
+) -> Result<u64> {
+    let bytes = match &invocation.stdout_blob {
+        Some(blob) => Cow::Owned(fs::read(fixture_relative(fixture, blob))?),
+        None => Cow::Borrowed(invocation.stdout.as_slice()),
+    };
+
+    let mut stdout = std::io::stdout().lock();
+
+    for (i, chunk) in bytes.chunks(chunk_size.max(1)).enumerate() {
+        if i > 0 {
+            std::thread::sleep(delay);
+        }
+
+        // As in `output_invocation`, a consumer closing its end of the pipe isn't an error.
+        if stdout
+            .write_all(chunk)
+            .and_then(|()| stdout.flush())
+            .is_err()
+        {
+            break;
+        }
+    }
+
+    let _ = std::io::stderr().write_all(&invocation.stderr);
+
+    Ok((bytes.len() + invocation.stderr.len()) as u64)
+}
+
+/// How [`format_recordings_with`] shows ANSI escape sequences in recorded output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AnsiDisplay {
+    /// Quote output with escapes shown as `\u{1b}`.
+    #[default]
+    Escaped,
+    /// Quote output with escape sequences removed, for readable listing of colored output.
+    Strip,
+    /// Write output verbatim on its own lines, so a terminal renders the escapes.
+    Raw,
+}
+
+/// Renders every recording in `recordings` as human-readable text, sorted by key.
+pub fn format_recordings(recordings: &RecordedCommands) -> String {
+    format_recordings_with(recordings, &ListOptions::default())
+}
+
+/// What [`format_recordings_with`] shows of each recording.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ListOptions {
+    /// How ANSI escape sequences in output are shown.
+    pub ansi: AnsiDisplay,
+    /// Show captured stdin, quoted when it is UTF-8 and as a byte count and hexdump preview otherwise.
+    pub show_stdin: bool,
+    /// Show [`TimingStats`] under each key with timed recordings.
+    pub timing: bool,
+    /// Show diagnostics recorded with each invocation, such as its [`CommandInvocation::rusage`].
+    pub verbose: bool,
+}
+
+/// Like [`format_recordings`], configured by `options`.
+pub fn format_recordings_with(recordings: &RecordedCommands, options: &ListOptions) -> String {
+    let mut keys: Vec<_> = recordings.commands.keys().collect();
+    keys.sort();
+
+    let mut out = String::new();
+
+    if let Some(description) = &recordings.description {
+        for line in description.lines() {
+            let _ = writeln!(out, "# {line}");
+        }
+
+        let _ = writeln!(out);
+    }
+
+    for key in keys {
+        let _ = writeln!(out, "{key}");
+
+        if options.timing
+            && let Some(stats) = TimingStats::from_invocations(&recordings.commands[key])
+        {
+            let _ = writeln!(out, "  timing: {stats}");
+        }
+
+        for (i, invocation) in recordings.commands[key].iter().enumerate() {
+            let _ = write!(out, "  [{i}] exit {}", invocation.exit_code);
+
+            #[cfg(windows)]
+            if let Some(name) = windows_crash_name(invocation.exit_code) {
+                let _ = write!(out, " ({name})");
+            }
+
+            if let Some(signal) = invocation.signal {
+                let _ = write!(out, ", signal {signal}");
+            }
+
+            if let Some(encoding) = &invocation.encoding {
+                let _ = write!(out, ", encoding {encoding}");
+            }
+
+            let _ = writeln!(out);
+
+            if let Some(wrapper) = &invocation.wrapper {
+                let _ = writeln!(out, "    wrapper: {}", wrapper.display());
+            }
+
+            if let Some(executable) = &invocation.executable {
+                let _ = writeln!(out, "    executable: {}", executable.display());
+            }
+
+            if let (true, Some(rusage)) = (options.verbose, &invocation.rusage) {
+                let _ = writeln!(out, "    rusage: {rusage}");
+            }
+
+            if let (true, Some(fd_count)) = (options.verbose, invocation.fd_count) {
+                let _ = writeln!(out, "    fds: {fd_count}");
+            }
+
+            if let (true, Some(stdin)) = (options.show_stdin, &invocation.stdin) {
+                let _ = writeln!(out, "    stdin: {}", stdin::describe(stdin));
+            }
+
+            for (name, output) in [
+                ("stdout", invocation.decode(&invocation.stdout)),
+                ("stderr", invocation.decode(&invocation.stderr)),
+            ] {
+                match options.ansi {
+                    AnsiDisplay::Escaped => {
+                        let _ = writeln!(out, "    {name}: {output:?}");
+                    }
+                    AnsiDisplay::Strip => {
+                        let _ = writeln!(
+                            out,
+                            "    {name}: {:?}",
+                            strip_ansi_escapes::strip_str(&output)
+                        );
+                    }
+                    AnsiDisplay::Raw => {
+                        let _ = writeln!(out, "    {name}:");
+                        out.push_str(&output);
+
+                        if !output.is_empty() && !output.ends_with('\n') {
+                            out.push('\n');
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    out
+}
+
+/// Renders `recordings` as a shell script that re-records each of them against the real commands, one
+/// `commandeer exec` per distinct invocation, for regenerating `file` on a fresh machine or documenting what it
+/// captures.
+///
+/// The script records into `file` unless given another path as its first argument. It appends rather than
+/// truncates, so run it against a fresh path or a cleared fixture.
+pub fn export_script(recordings: &RecordedCommands, file: &Path) -> String {
+    let mut out = String::from("#!/bin/sh\nset -e\n\n");
+    let _ = writeln!(out, "file=${{1:-{}}}", shell_quote(&file.to_string_lossy()));
+
+    export_lines(&mut out, recordings, None);
+
+    let mut tests: Vec<_> = recordings.tests.keys().collect();
+    tests.sort();
+
+    for test_name in tests {
+        export_lines(&mut out, &recordings.tests[test_name], Some(test_name));
+    }
+
+    out
+}
+
+fn export_lines(out: &mut String, recordings: &RecordedCommands, test_name: Option<&str>) {
+    let mut keys: Vec<_> = recordings.commands.keys().collect();
+    keys.sort();
+
+    let test_name = test_name
+        .map(|test_name| format!(" --test-name {}", shell_quote(test_name)))
+        .unwrap_or_default();
+
+    for key in keys {
+        let mut exported: Vec<&[String]> = vec![];
+
+        for invocation in &recordings.commands[key] {
+            if exported.contains(&invocation.original_args()) {
+                continue;
+            }
+
+            exported.push(invocation.original_args());
+
+            let argv: Vec<String> = std::iter::once(&invocation.binary_name)
+                .chain(invocation.original_args())
+                .map(|arg| shell_quote(arg))
+                .collect();
+
+            let _ = writeln!(
+                out,
+                "commandeer exec --file \"$file\"{test_name} -- {}",
+                argv.join(" ")
+            );
+        }
+    }
+}
+
+/// Maps `code` to the value the OS reports once a process exits with it.
+///
+/// Unix only keeps the low 8 bits of an exit status, so `300` is observed as `44` and `-1` as `255`. Windows exit
+/// codes are 32 bits wide and are returned unchanged.
+pub fn normalize_exit_code(code: i32) -> i32 {
+    if cfg!(unix) { code & 0xFF } else { code }
+}
+
+/// Names the `NTSTATUS` a Windows exit code stands for when the process crashed, e.g. `STATUS_ACCESS_VIOLATION`.
+///
+/// `ExitStatus::code` reports these as large (negative as `i32`) codes, which are recorded and replayed unchanged.
+#[cfg(windows)]
+pub fn windows_crash_name(exit_code: i32) -> Option<&'static str> {
+    let name = match exit_code.cast_unsigned() {
+        0x8000_0003 => "STATUS_BREAKPOINT",
+        0xC000_0005 => "STATUS_ACCESS_VIOLATION",
+        0xC000_001D => "STATUS_ILLEGAL_INSTRUCTION",
+        0xC000_0094 => "STATUS_INTEGER_DIVIDE_BY_ZERO",
+        0xC000_00FD => "STATUS_STACK_OVERFLOW",
+        0xC000_013A => "STATUS_CONTROL_C_EXIT",
+        0xC000_0374 => "STATUS_HEAP_CORRUPTION",
+        0xC000_0409 => "STATUS_STACK_BUFFER_OVERRUN",
+        _ => return None,
+    };
+
+    Some(name)
+}
+
+/// Exits with `code`, warning when it is outside the platform's range (e.g. a hand-edited recording).
+pub fn exit_with_code(code: i32) -> ! {
+    let normalized = normalize_exit_code(code);
+
+    if normalized != code {
+        eprintln!(
+            "commandeer: warning: exit code {code} is out of range for this platform, exiting with {normalized}"
+        );
+    }
+
+    std::process::exit(normalized);
+}
+
+/// Exits as the command recorded in `invocation` did: by its [`CommandInvocation::signal`] when `signal_replay` is
+/// [`SignalReplay::Raise`], otherwise with [`exit_with_code`].
+pub fn exit_as_recorded(invocation: &CommandInvocation, signal_replay: SignalReplay) -> ! {
+    #[cfg(unix)]
+    if let (SignalReplay::Raise, Some(signal)) = (signal_replay, invocation.signal) {
+        signal::raise(signal);
+    }
+
+    #[cfg(not(unix))]
+    let _ = signal_replay;
+
+    exit_with_code(invocation.exit_code)
+}
+
+/// Stand-in for `env` written by [`Commandeer::mock_env`], applying `env`'s leading options and `NAME=value`
+/// assignments itself before running the command on the current `PATH`.
+const ENV_DISPATCHER: &str = r#"#!/usr/bin/env bash
+args=("$@")
+clear=
+unset_names=()
+
+while (($#)); do
+    case $1 in
+        -i | - | --ignore-environment) clear=1 ;;
+        -u | --unset)
+            (($# > 1)) || exec /usr/bin/env "${args[@]}"
+            unset_names+=("$2")
+            shift
+            ;;
+        --unset=*) unset_names+=("${1#--unset=}") ;;
+        -u*) unset_names+=("${1#-u}") ;;
+        --)
+            shift
+            break
+            ;;
+        -*) exec /usr/bin/env "${args[@]}" ;;
+        *) break ;;
+    esac
+    shift
+done
+
+assignments=()
+
+while (($#)) && [[ $1 == *=* ]]; do
+    assignments+=("$1")
+    shift
+done
+
+(($#)) || exec /usr/bin/env "${args[@]}"
+
+if [[ -n $clear ]]; then
+    for name in $(compgen -e); do
+        [[ $name == PATH ]] || unset "$name"
+    done
+fi
+
+for name in "${unset_names[@]}"; do
+    unset "$name"
+done
+
+for assignment in "${assignments[@]}"; do
+    export "$assignment"
+done
+
+exec "$@"
+"#;
+
+/// Writes an executable mock script to `path`.
+fn write_script(path: &Path, script: &str) -> Result<()> {
+    let context = |source| CommandeerError::WriteMock {
+        path: path.to_path_buf(),
+        source,
+    };
+
+    fs::write(path, script).map_err(context)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let mut perms = fs::metadata(path).map_err(context)?.permissions();
+
+        perms.set_mode(0o755);
+
+        fs::set_permissions(path, perms).map_err(context)?;
+    }
+
+    Ok(())
+}
+
+/// Prefix of the temp dirs holding each [`Commandeer`]'s mock wrappers.
+const TEMP_DIR_PREFIX: &str = "commandeer-";
+
+/// The mock dir of another live [`Commandeer`] if it is first on `path`, as when instances are nested.
+fn shadowed_instance(path: &str) -> Option<PathBuf> {
+    let first = env::split_paths(path).next()?;
+    let is_mock_dir = first
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with(TEMP_DIR_PREFIX));
+
+    (is_mock_dir && first.parent() == Some(env::temp_dir().as_path()) && first.is_dir())
+        .then_some(first)
+}
+
+/// System dirs left on `PATH` after the mock dir under [`CommandeerBuilder::strict_path`], so shells and coreutils
+/// still resolve.
+pub const STRICT_PATH_DIRS: [&str; 3] = ["/usr/local/bin", "/usr/bin", "/bin"];
+
+/// `PATH` with `mock_dir` as its first and only occurrence, followed by `allowed` when set or else the entries of
+/// `original`.
+fn mocked_path(mock_dir: &Path, original: &str, allowed: Option<&[PathBuf]>) -> String {
+    let rest: Vec<PathBuf> = match allowed {
+        Some(allowed) => allowed.to_vec(),
+        None => env::split_paths(original).collect(),
+    };
+
+    std::iter::once(mock_dir.display().to_string())
+        .chain(
+            rest.iter()
+                .filter(|dir| *dir != mock_dir)
+                .map(|dir| dir.display().to_string()),
+        )
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// Fails with [`CommandeerError::MalformedWrapper`] if bash can't parse the script at `path`, so a bug in a
+/// generated wrapper shows up when mocking rather than as a cryptic failure of the mocked command. Skipped when
+/// there is no `bash` to ask, in which case the wrapper can't run anyway.
+fn check_syntax(path: &Path) -> Result<()> {
+    let output = match std::process::Command::new("bash")
+        .arg("-n")
+        .arg(path)
+        .output()
+    {
+        Ok(output) => output,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+
+    if output.status.success() {
+        return Ok(());
+    }
+
+    Err(CommandeerError::MalformedWrapper {
+        path: path.to_path_buf(),
+        message: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+    })
+}
+
+/// Quotes `path` as a single word for the wrapper and exported scripts.
+fn quote_path(path: &Path) -> String {
+    shell_quote(&path.display().to_string())
+}
+
+/// Quotes `s` as a single word for the wrapper and exported scripts.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+fn is_executable(path: &Path) -> bool {
+    let Ok(metadata) = fs::metadata(path) else {
+        return false;
+    };
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+    }
+
+    #[cfg(not(unix))]
+    {
+        metadata.is_file()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Mode {
+    Record,
+    Replay,
+    /// Replay existing recordings and record only the invocations that have none yet.
+    RecordOnce,
+    /// Replay existing recordings, running the real command on a miss and appending it to the fixture's
+    /// [`pending_path`] for review instead of to the fixture itself.
+    ReplayLearn,
+}
+
+/// Sidecar that [`Mode::ReplayLearn`] records misses to, e.g. `cmds.pending.json` for `cmds.json`.
+///
+/// Once reviewed, its recordings can be merged into the fixture by hand or the file deleted.
+pub fn pending_path(fixture: &Path) -> PathBuf {
+    fixture.with_extension("pending.json")
+}
+
+/// Sidecar whose recordings replay in place of the fixture's own for every key it holds, e.g. `cmds.override.json`
+/// for `cmds.json`.
+///
+/// For tweaking a response during a quick experiment without re-recording; it isn't meant to be committed. Recording
+/// never writes to it.
+pub fn override_path(fixture: &Path) -> PathBuf {
+    fixture.with_extension("override.json")
+}
+
+/// Environment variable naming a prebuilt `commandeer` binary to use instead of building one with cargo.
+pub const COMMANDEER_BIN: &str = "COMMANDEER_BIN";
+
+/// Environment variable the mock wrappers set to their [`Mode`], visible to the mock and anything it runs.
+pub const COMMANDEER_ACTIVE_MODE: &str = "COMMANDEER_ACTIVE_MODE";
+
+/// Environment variable the mock wrappers set to their fixture path, visible to the mock and anything it runs.
+pub const COMMANDEER_ACTIVE_FIXTURE: &str = "COMMANDEER_ACTIVE_FIXTURE";
+
+/// Features to build the `commandeer` binary with, so mocks read and write the fixture formats this build supports.
+const MOCK_FEATURES: &[&str] = &[
+    "sync",
+    #[cfg(feature = "gzip")]
+    "gzip",
+    #[cfg(feature = "zstd")]
+    "zstd",
+    #[cfg(feature = "encryption")]
+    "encryption",
+];
+
+/// Locates the `commandeer` binary the mocks exec into, building it only when [`COMMANDEER_BIN`] is unset.
+///
+/// Builds the [`CommandeerBuilder::mock_bin`] target if one was set, else a binary in the test's workspace that
+/// depends on this crate if there is exactly one, else this crate's own `commandeer` binary.
+fn mock_binary(mock_bin: Option<&mock_bin::MockBin>) -> Result<PathBuf> {
+    if let Some(bin) = env::var_os(COMMANDEER_BIN) {
+        return Ok(PathBuf::from(bin));
+    }
+
+    let workspace = env::var_os("CARGO_MANIFEST_DIR")
+        .map(|dir| PathBuf::from(dir).join("Cargo.toml"))
+        .filter(|manifest| manifest.exists());
+
+    let mock_bin = match (mock_bin, &workspace) {
+        (Some(_), None) => return Err(CommandeerError::MissingManifestDir),
+        (Some(mock_bin), Some(_)) => Some(mock_bin.clone()),
+        (None, Some(workspace)) => detected_mock_bin(workspace)?,
+        (None, None) => None,
+    };
+
+    let run = match (mock_bin, workspace) {
+        (Some(mock_bin), Some(workspace)) => CargoBuild::new()
+            .manifest_path(workspace)
+            .package(mock_bin.package)
+            .bin(mock_bin.bin)
+            .run()?,
+        _ => CargoBuild::new()
+            .manifest_path(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml"))
+            .package("commandeer-test")
+            .bin("commandeer")
+            .no_default_features()
+            .features(MOCK_FEATURES.join(" "))
+            .run()?,
+    };
+
+    Ok(run.path().to_path_buf())
+}
+
+/// [`mock_bin::detect`] for `workspace`, run once per test process since the workspace doesn't change under it.
+fn detected_mock_bin(workspace: &Path) -> Result<Option<mock_bin::MockBin>> {
+    static DETECTED: OnceLock<Option<mock_bin::MockBin>> = OnceLock::new();
+
+    if let Some(detected) = DETECTED.get() {
+        return Ok(detected.clone());
+    }
+
+    let under_test = env::var("CARGO_PKG_NAME").ok();
+    let detected = mock_bin::detect(workspace, under_test.as_deref())?;
+
+    Ok(DETECTED.get_or_init(|| detected).clone())
+}
+
+pub struct Commandeer {
+    mock_runner: PathBuf,
+    temp_dir: TempDir,
+    fixture: PathBuf,
+    mode: Mode,
+    original_path: String,
+    call_log: PathBuf,
+    options: CommandeerOptions,
+    /// Names passed to [`Self::mock_command`] and [`Self::mock_path_layout`], for [`Self::validate_fixture`].
+    mocked: Mutex<Vec<String>>,
+}
+
+/// Settings collected by [`CommandeerBuilder`].
+#[derive(Debug, Clone, Default)]
+struct CommandeerOptions {
+    warn_unused: bool,
+    fail_on_miss: bool,
+    expected_commands: Option<Vec<String>>,
+    passthrough: Vec<String>,
+    skip_if_missing: bool,
+    unmanaged_path: bool,
+    strict_path: Option<Vec<PathBuf>>,
+    match_options: MatchOptions,
+    test_name: Option<String>,
+    no_wait_output: bool,
+    live_output: bool,
+    normalize_temp_paths: bool,
+    response_files: bool,
+    canonical_json: bool,
+    split_options: Vec<String>,
+    unordered_flags: Option<Vec<String>>,
+    capture_env: Vec<String>,
+    locales: Vec<String>,
+    transforms: Vec<BuiltinTransform>,
+    source_date_epoch: Option<u64>,
+    faketime: Option<String>,
+    blob_store: Option<PathBuf>,
+    index: bool,
+    max_per_key: Option<usize>,
+    output_file_options: Vec<String>,
+    timeout: Option<Duration>,
+    command_timeouts: HashMap<String, Duration>,
+    extra_record_args: HashMap<String, Vec<String>>,
+    expand_home: bool,
+    native_line_endings: bool,
+    capture_stdin: bool,
+    record_timing: bool,
+    record_executable: bool,
+    record_rusage: bool,
+    record_fd_count: bool,
+    concurrent_output: bool,
+    stream_chunks: Option<(usize, Duration)>,
+    scan_secrets: SecretScan,
+    debug: bool,
+    trace_invocations: bool,
+    echo_invocation: bool,
+    signal_replay: SignalReplay,
+    mock_bin: Option<mock_bin::MockBin>,
+}
+
+/// Configures a [`Commandeer`] before it takes over `PATH`; created with [`Commandeer::builder`].
+pub struct CommandeerBuilder {
+    test_name: PathBuf,
+    mode: Mode,
+    options: CommandeerOptions,
+}
+
+impl CommandeerBuilder {
+    /// Warn on drop about recordings in the fixture that were never matched during replay.
+    pub fn warn_unused(mut self, warn_unused: bool) -> Self {
+        self.options.warn_unused = warn_unused;
+        self
+    }
+
+    /// Panic on drop if any mocked invocation had no recording, listing every miss.
+    ///
+    /// A miss only makes the mocked command exit with 1, so without this a test passes whenever the code under test
+    /// tolerates the failure.
+    pub fn fail_on_miss(mut self, fail_on_miss: bool) -> Self {
+        self.options.fail_on_miss = fail_on_miss;
+        self
+    }
+
+    /// Whether to prepend the mock dir to the process-wide `PATH` while the instance lives, the default. Without it
+    /// `PATH` is left alone and only commands given [`Commandeer::path`], e.g. through [`Commandeer::command`], see
+    /// the mocks.
+    pub fn manage_path(mut self, manage_path: bool) -> Self {
+        self.options.unmanaged_path = !manage_path;
+        self
+    }
+
+    /// Whether `PATH` holds only the mock dir and [`STRICT_PATH_DIRS`], rather than the mock dir followed by the
+    /// original `PATH`, so nothing but a mock or a system binary can resolve: a same-named tool from a dir the test
+    /// environment added, or anything unmocked installed outside those dirs, is out of reach. Recorded commands run
+    /// with the same dirs, minus the mock dir.
+    pub fn strict_path(mut self, strict_path: bool) -> Self {
+        self.options.strict_path =
+            strict_path.then(|| STRICT_PATH_DIRS.into_iter().map(PathBuf::from).collect());
+        self
+    }
+
+    /// Like [`Self::strict_path`], but with `dirs` after the mock dir in place of [`STRICT_PATH_DIRS`].
+    pub fn strict_path_dirs(mut self, dirs: impl IntoIterator<Item = impl Into<PathBuf>>) -> Self {
+        self.options.strict_path = Some(dirs.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Declare the only commands the test may invoke, panicking on drop with every intercepted invocation of any
+    /// other, as with [`Self::mock_all`] or a mock added outside the test. With [`Self::warn_unused`], also warns
+    /// about expected commands that were never invoked.
+    pub fn expect_commands<I, S>(mut self, commands: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.options.expected_commands = Some(commands.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Run these commands for real in every mode instead of recording or replaying them, e.g. `date` alongside a
+    /// replayed `git`. Mocking one writes a wrapper that execs the binary it resolves to on the original `PATH`, so
+    /// its invocations aren't logged or stored, and it needs no recording in the fixture.
+    pub fn passthrough<I, S>(mut self, commands: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.options.passthrough = commands.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Let replay tests skip themselves when their fixture isn't present, for fixtures only recorded in some
+    /// environments; see [`Commandeer::skip_reason`]. The `commandeer` macro does so for its listed commands.
+    pub fn skip_if_missing(mut self, skip_if_missing: bool) -> Self {
+        self.options.skip_if_missing = skip_if_missing;
+        self
+    }
+
+    /// Scope recordings to `test_name`, so several tests can share one fixture file without cross-matching.
+    ///
+    /// Record mode then only clears this test's recordings instead of removing the whole file.
+    pub fn test_name(mut self, test_name: impl Into<String>) -> Self {
+        self.options.test_name = Some(test_name.into());
+        self
+    }
+
+    /// Record daemonizing commands without waiting for their background processes; see
+    /// [`RecordOptions::no_wait_output`].
+    pub fn no_wait_output(mut self, no_wait_output: bool) -> Self {
+        self.options.no_wait_output = no_wait_output;
+        self
+    }
+
+    /// Show each recorded command's output as it runs instead of once it has finished; see
+    /// [`RecordOptions::live_output`].
+    pub fn live_output(mut self, live_output: bool) -> Self {
+        self.options.live_output = live_output;
+        self
+    }
+
+    /// Key recordings with paths inside the system temp dir replaced by [`TEMP_PATH_TOKEN`], so args naming a
+    /// per-run temp dir match across runs. Applies to both recording and replay; see [`normalize_temp_paths`].
+    pub fn normalize_temp_paths(mut self, normalize_temp_paths: bool) -> Self {
+        self.options.normalize_temp_paths = normalize_temp_paths;
+        self
+    }
+
+    /// Key recordings with each `@file` response file arg replaced by a hash of the args it holds, so toolchain
+    /// invocations passing thousands of inputs that way match on what they pass. Applies to both recording and
+    /// replay; see [`expand_response_files`].
+    pub fn response_files(mut self, response_files: bool) -> Self {
+        self.options.response_files = response_files;
+        self
+    }
+
+    /// Key recordings with args that are JSON objects or arrays in canonical form, so the same document with its
+    /// keys in another order or formatted differently matches. Applies to both recording and replay; see
+    /// [`canonicalize_json_args`].
+    pub fn canonical_json(mut self, canonical_json: bool) -> Self {
+        self.options.canonical_json = canonical_json;
+        self
+    }
+
+    /// Treat `--opt=value` as `--opt value`, and `-ovalue` as `-o value`, for each of `options` when keying
+    /// recordings. Applies to both recording and replay; see [`split_option_args`].
+    pub fn split_options<I, S>(mut self, options: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.options.split_options = options.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Key recordings with their flags sorted, so the same flags passed in a different order match. `value_options`
+    /// lists the options that take the following arg as their value, e.g. `-c` for `git -c x log`. Applies to both
+    /// recording and replay; see [`sort_flag_args`].
+    pub fn unordered_flags<I, S>(mut self, value_options: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.options.unordered_flags = Some(value_options.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Capture the values of the `vars` environment variables with each recording, for [`Self::env_match`].
+    pub fn capture_env<I, S>(mut self, vars: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.options.capture_env = vars.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Fail replay of an invocation that more than one recording matches; see [`MatchOptions::require_unique_match`].
+    pub fn require_unique_match(mut self, require_unique_match: bool) -> Self {
+        self.options.match_options.require_unique_match = require_unique_match;
+        self
+    }
+
+    /// Record each command once per locale in `locales` and replay the recording made under the active one; see
+    /// [`RecordOptions::locales`] and [`MatchOptions::match_locale`].
+    pub fn locales(mut self, locales: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.options.locales = locales.into_iter().map(Into::into).collect();
+        self.options.match_options.match_locale = !self.options.locales.is_empty();
+        self
+    }
+
+    /// How replay compares the environment, restricted to [`Self::capture_env`], against the captured env.
+    pub fn env_match(mut self, env_match: EnvMatch) -> Self {
+        self.options.match_options.env_match = env_match;
+        self
+    }
+
+    /// Record with `SOURCE_DATE_EPOCH` set to `epoch`, so tools honouring it produce reproducible timestamps.
+    ///
+    /// Tools that read the clock directly are unaffected; see also [`Self::faketime`].
+    pub fn source_date_epoch(mut self, epoch: u64) -> Self {
+        self.options.source_date_epoch = Some(epoch);
+        self
+    }
+
+    /// Record with `FAKETIME` set to `spec`, which libfaketime uses when preloaded into the recorded command.
+    pub fn faketime(mut self, spec: impl Into<String>) -> Self {
+        self.options.faketime = Some(spec.into());
+        self
+    }
+
+    /// Store recorded stdout once per distinct output in the content-addressed store at `dir`; see
+    /// [`RecordOptions::blob_store`].
+    ///
+    /// Relative paths are resolved against the `testcmds` fixtures directory, so `.blob_store(".blobs")` dedups
+    /// across every fixture there.
+    pub fn blob_store(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.options.blob_store = Some(dir.into());
+        self
+    }
+
+    /// Replay from `layers` beneath the fixture, e.g. a shared baseline, with later layers and then the fixture
+    /// itself replacing the recordings of any key they also hold; see [`load_layered_recordings_sync`].
+    ///
+    /// Relative paths are resolved against the `testcmds` fixtures directory, like the fixture. Recording only
+    /// writes to the fixture.
+    pub fn fixture_layers<I, P>(mut self, layers: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<PathBuf>,
+    {
+        self.options.match_options.fixture_layers = layers.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Keep an index of the fixture up to date while recording, for faster replay from large fixtures; see
+    /// [`write_index`].
+    pub fn index(mut self, index: bool) -> Self {
+        self.options.index = index;
+        self
+    }
+
+    /// Keep only the latest `max` recordings of each invocation; see [`RecordOptions::max_per_key`].
+    pub fn max_per_key(mut self, max: usize) -> Self {
+        self.options.max_per_key = Some(max);
+        self
+    }
+
+    /// Capture the files commands write through `options`, e.g. `-o` or `--output`, and recreate them on replay; see
+    /// [`RecordOptions::output_file_options`].
+    pub fn output_files<I, S>(mut self, options: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.options.output_file_options = options.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Fail recording any command that runs longer than `timeout`, unless [`Self::command_timeout`] sets its own; see
+    /// [`RecordOptions::timeout`].
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.options.timeout = Some(timeout);
+        self
+    }
+
+    /// Fail recording `command` if it runs longer than `timeout`, overriding [`Self::timeout`] for it.
+    pub fn command_timeout(mut self, command: impl Into<String>, timeout: Duration) -> Self {
+        self.options
+            .command_timeouts
+            .insert(command.into(), timeout);
+        self
+    }
+
+    /// Run the real `command` with `args` ahead of those the code under test passed when recording it, e.g.
+    /// `--no-pager` for `git` or `--color=never`, for stable output; see [`RecordOptions::extra_args`].
+    pub fn extra_record_args(mut self, command: impl Into<String>, args: &[&str]) -> Self {
+        self.options.extra_record_args.insert(
+            command.into(),
+            args.iter().map(|arg| arg.to_string()).collect(),
+        );
+        self
+    }
+
+    /// Store how long each recorded command took, for `list --timing`; see [`RecordOptions::record_timing`].
+    pub fn record_timing(mut self, record_timing: bool) -> Self {
+        self.options.record_timing = record_timing;
+        self
+    }
+
+    /// Record the binary each command ends up running after any `exec` chain, shown by `list`; see
+    /// [`RecordOptions::record_executable`].
+    pub fn record_executable(mut self, record_executable: bool) -> Self {
+        self.options.record_executable = record_executable;
+        self
+    }
+
+    /// Record each command's CPU time and peak memory, shown by `list --verbose`; see
+    /// [`RecordOptions::record_rusage`].
+    pub fn record_rusage(mut self, record_rusage: bool) -> Self {
+        self.options.record_rusage = record_rusage;
+        self
+    }
+
+    /// Record how many file descriptors each command starts with, shown by `list --verbose` and checked on replay;
+    /// see [`RecordOptions::record_fd_count`].
+    pub fn record_fd_count(mut self, record_fd_count: bool) -> Self {
+        self.options.record_fd_count = record_fd_count;
+        self
+    }
+
+    /// Record the stdin each command is fed, for [`Commandeer::recorded_stdin`]; see
+    /// [`RecordOptions::capture_stdin`].
+    pub fn capture_stdin(mut self, capture_stdin: bool) -> Self {
+        self.options.capture_stdin = capture_stdin;
+        self
+    }
+
+    /// Write each invocation's stdout and stderr concurrently instead of one after the other; see
+    /// [`output_invocation_concurrent`].
+    pub fn concurrent_output(mut self, concurrent_output: bool) -> Self {
+        self.options.concurrent_output = concurrent_output;
+        self
+    }
+
+    /// Write each invocation's stdout `chunk_size` bytes at a time with `delay` between chunks, simulating a command
+    /// that streams its output; see [`output_invocation_chunked`].
+    pub fn stream_chunks(mut self, chunk_size: usize, delay: Duration) -> Self {
+        self.options.stream_chunks = Some((chunk_size, delay));
+        self
+    }
+
+    /// Check each recording for credentials before it is saved; see [`RecordOptions::scan_secrets`].
+    pub fn scan_secrets(mut self, scan_secrets: SecretScan) -> Self {
+        self.options.scan_secrets = scan_secrets;
+        self
+    }
+
+    /// Replace [`HOME_TOKEN`] in replayed output with the current `$HOME`, undoing [`BuiltinTransform::MaskHome`].
+    pub fn expand_home(mut self, expand_home: bool) -> Self {
+        self.options.expand_home = expand_home;
+        self
+    }
+
+    /// Replay output with the platform's line endings, undoing [`BuiltinTransform::NormalizeLineEndings`] on Windows.
+    pub fn native_line_endings(mut self, native_line_endings: bool) -> Self {
+        self.options.native_line_endings = native_line_endings;
+        self
+    }
+
+    /// Apply `transform` to each recording before it is saved, after any added earlier.
+    pub fn transform(mut self, transform: BuiltinTransform) -> Self {
+        self.options.transforms.push(transform);
+        self
+    }
+
+    /// Let replay match numeric args within `tolerance` when no recording matches exactly.
+    pub fn numeric_tolerance(mut self, tolerance: NumericTolerance) -> Self {
+        self.options.match_options.numeric_tolerance = Some(tolerance);
+        self
+    }
+
+    /// Have each mock print its command, [`COMMANDEER_ACTIVE_MODE`] and [`COMMANDEER_ACTIVE_FIXTURE`] to stderr
+    /// before running, to trace which instance handles nested invocations.
+    pub fn debug(mut self, debug: bool) -> Self {
+        self.options.debug = debug;
+        self
+    }
+
+    /// Have each mock print a one-line summary of what it did to stderr, e.g.
+    /// `commandeer replay hit git [status] -> exit 0 (42 bytes)`, so CI logs show the replay timeline.
+    pub fn trace_invocations(mut self, trace_invocations: bool) -> Self {
+        self.options.trace_invocations = trace_invocations;
+        self
+    }
+
+    /// Have replay print each invocation to stderr before its recorded output, as `set -x` does, e.g.
+    /// `+ git status`, to follow a failing test's commands inline.
+    pub fn echo_invocation(mut self, echo_invocation: bool) -> Self {
+        self.options.echo_invocation = echo_invocation;
+        self
+    }
+
+    /// How mocks replay recordings of commands killed by a signal: with the conventional 128 + signal exit code by
+    /// default, or by raising the signal; see [`SignalReplay`].
+    pub fn signal_replay(mut self, signal_replay: SignalReplay) -> Self {
+        self.options.signal_replay = signal_replay;
+        self
+    }
+
+    /// Build the mocks from binary target `bin` of workspace package `package`, for workspaces that wrap the
+    /// `commandeer` CLI in a package of their own. Without it such a binary is detected when it is the only one
+    /// with this crate as a normal dependency, other than the package under test. Ignored when [`COMMANDEER_BIN`] is
+    /// set; otherwise building fails with [`CommandeerError::MissingManifestDir`] if `CARGO_MANIFEST_DIR` doesn't
+    /// locate the workspace.
+    pub fn mock_bin(mut self, package: impl Into<String>, bin: impl Into<String>) -> Self {
+        self.options.mock_bin = Some(mock_bin::MockBin {
+            package: package.into(),
+            bin: bin.into(),
+        });
+        self
+    }
+
+    pub fn build(self) -> Commandeer {
+        self.try_build().expect("Failed to set up commandeer")
+    }
+
+    /// Like [`Self::build`], but returns setup failures (e.g. the mock binary failing to build) instead of panicking.
+    pub fn try_build(self) -> Result<Commandeer> {
+        Commandeer::from_builder(self)
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mode::Record => write!(f, "record"),
+            Mode::Replay => write!(f, "replay"),
+            Mode::RecordOnce => write!(f, "record-once"),
+            Mode::ReplayLearn => write!(f, "replay-learn"),
+        }
+    }
+}
+
+impl Commandeer {
+    pub fn new(test_name: impl AsRef<Path>, mode: Mode) -> Self {
+        Self::builder(test_name, mode).build()
+    }
+
+    /// Like [`Self::new`], but returns setup failures instead of panicking.
+    pub fn try_new(test_name: impl AsRef<Path>, mode: Mode) -> Result<Self> {
+        Self::builder(test_name, mode).try_build()
+    }
+
+    pub fn builder(test_name: impl AsRef<Path>, mode: Mode) -> CommandeerBuilder {
+        CommandeerBuilder {
+            test_name: test_name.as_ref().to_path_buf(),
+            mode,
+            options: CommandeerOptions::default(),
+        }
+    }
+
+    fn from_builder(builder: CommandeerBuilder) -> Result<Self> {
+        let CommandeerBuilder {
+            test_name,
+            mode,
+            mut options,
+        } = builder;
+
+        let dir = PathBuf::from(
+            std::env::var("CARGO_MANIFEST_DIR").map_err(|_| CommandeerError::MissingManifestDir)?,
+        );
+
+        std::fs::DirBuilder::new().recursive(true).create(&dir)?;
+
+        // Mocks may run from whatever directory the code under test changed into, so the wrapper must not depend on
+        // the cwd to find the fixture.
+        let fixture = std::path::absolute(dir.join("testcmds").join(test_name))?;
+
+        options.blob_store = options
+            .blob_store
+            .map(|store| std::path::absolute(dir.join("testcmds").join(store)))
+            .transpose()?;
+
+        for layer in &mut options.match_options.fixture_layers {
+            *layer = std::path::absolute(dir.join("testcmds").join(&layer))?;
+        }
+
+        if fixture.exists() && mode == Mode::Record {
+            match &options.test_name {
+                Some(test_name) => {
+                    let mut recordings = parse_recordings(&fixture, &compression::read(&fixture)?)?;
+
+                    if recordings.remove_test(test_name) {
+                        compression::write(&fixture, serialize_recordings(&fixture, &recordings)?)?;
+                    }
+                }
+                None => {
+                    let cleared = parse_recordings(&fixture, &compression::read(&fixture)?)
+                        .ok()
+                        .map(RecordedCommands::cleared)
+                        .filter(|recordings| recordings.description.is_some());
+
+                    match cleared {
+                        Some(cleared) => {
+                            compression::write(&fixture, serialize_recordings(&fixture, &cleared)?)?
+                        }
+                        None => std::fs::remove_file(&fixture)?,
+                    }
+                }
+            }
+        }
+
+        let mock_runner = mock_binary(options.mock_bin.as_ref())?;
+
+        let temp_dir = tempfile::Builder::new().prefix(TEMP_DIR_PREFIX).tempdir()?;
+
+        let original_path = std::env::var("PATH").unwrap_or_default();
+
+        if let (false, Some(shadowed)) = (options.unmanaged_path, shadowed_instance(&original_path))
+        {
+            eprintln!(
+                "commandeer: warning: the mocks in {} are already first on PATH; this instance's mocks will shadow \
+                 them until it is dropped",
+                shadowed.display()
+            );
+        }
+        if !options.unmanaged_path {
+            let new_path = mocked_path(
+                temp_dir.path(),
+                &original_path,
+                options.strict_path.as_deref(),
+            );
+
+            unsafe {
+                std::env::set_var("PATH", new_path);
+            }
+        }
+
+        let call_log = temp_dir.path().join("commandeer-calls.jsonl");
+
+        Ok(Self {
+            mock_runner,
+            temp_dir,
+            fixture,
+            mode,
+            original_path,
+            call_log,
+            options,
+            mocked: Mutex::default(),
+        })
+    }
+
+    /// The fixture invocations are recorded to and replayed from.
+    pub fn fixture(&self) -> &Path {
+        &self.fixture
+    }
+
+    /// Why a test replaying `commands` should be skipped under [`CommandeerBuilder::skip_if_missing`]: neither the
+    /// fixture nor its layers or [`override_path`] file exist, or together they hold no recording of one of
+    /// `commands` for this instance's test. `None` when it can run, including whenever the option is off or the mode
+    /// can record.
+    ///
+    /// The test harness has no runtime skip, so a skipping test returns early and passes:
+    ///
+    /// ```no_run
+    /// # use commandeer_test::{Commandeer, Mode};
+    /// let commandeer = Commandeer::builder("cmds_cluster.json", Mode::Replay)
+    ///     .skip_if_missing(true)
+    ///     .build();
+    ///
+    /// if let Some(reason) = commandeer.skip_reason(&["kubectl"]) {
+    ///     eprintln!("skipped: {reason}");
+    ///     return;
+    /// }
+    /// ```
+    pub fn skip_reason(&self, commands: &[&str]) -> Option<String> {
+        if !self.options.skip_if_missing || self.mode != Mode::Replay {
+            return None;
+        }
+
+        // A fixture that can't be read skips as holding no recordings; `validate_fixture` reports why.
+        let mut recordings = match self.layered_recordings() {
+            Ok(None) => return Some(format!("{} doesn't exist", self.fixture.display())),
+            Ok(Some(recordings)) => recordings,
+            Err(_) => RecordedCommands::default(),
+        };
+
+        if let Some(test_name) = &self.options.test_name {
+            recordings = recordings.tests.remove(test_name).unwrap_or_default();
+        }
+
+        commands
+            .iter()
+            .find(|command| !recordings.records(command))
+            .map(|command| {
+                format!(
+                    "{} has no recordings of `{command}`",
+                    self.fixture.display()
+                )
+            })
+    }
+
+    /// The recordings replay picks from, the fixture laid over its [`CommandeerBuilder::fixture_layers`] and under
+    /// its [`override_path`] file, or `None` if none of them exist.
+    fn layered_recordings(&self) -> Result<Option<RecordedCommands>> {
+        let mut layers = self.options.match_options.fixture_layers.clone();
+        layers.extend([self.fixture.clone(), override_path(&self.fixture)]);
+
+        let mut recordings = RecordedCommands::default();
+        let mut found = false;
+
+        for layer in &layers {
+            if layer.exists() {
+                recordings.overlay(parse_recordings(layer, &compression::read(layer)?)?);
+                found = true;
+            }
+        }
+
+        Ok(found.then_some(recordings))
+    }
+
+    /// Checks the fixture before the test runs, so a broken one fails upfront instead of as a replay miss midway:
+    /// it must parse and not need a newer commandeer, and under [`Mode::Replay`] it must exist and hold a recording,
+    /// for this instance's test, of every command mocked so far.
+    ///
+    /// Recordings in [`CommandeerBuilder::fixture_layers`] and the [`override_path`] file count too. With the
+    /// `commandeer` macro, `validate_fixture = true` calls this once the listed commands are mocked and panics with
+    /// the error.
+    pub fn validate_fixture(&self) -> Result<()> {
+        let invalid = |reason| CommandeerError::InvalidFixture {
+            path: self.fixture.clone(),
+            reason,
+        };
+
+        let recordings = self.layered_recordings()?;
+
+        if self.mode != Mode::Replay {
+            return Ok(());
+        }
+
+        let Some(mut recordings) = recordings else {
+            return Err(invalid("it doesn't exist".to_string()));
+        };
+
+        if let Some(test_name) = &self.options.test_name {
+            recordings = recordings.tests.remove(test_name).unwrap_or_default();
+        }
+
+        let mocked = self.mocked.lock().unwrap_or_else(|e| e.into_inner());
+
+        match mocked.iter().find(|command| !recordings.records(command)) {
+            Some(command) => Err(invalid(match &self.options.test_name {
+                Some(test_name) => format!("no recordings of `{command}` for test `{test_name}`"),
+                None => format!("no recordings of `{command}`"),
+            })),
+            None => Ok(()),
+        }
+    }
+
+    /// Directory holding the mock wrappers, which is prepended to `PATH`.
+    pub fn temp_path(&self) -> &Path {
+        self.temp_dir.path()
+    }
+
+    /// `PATH` with the mock dir prepended, or under [`CommandeerBuilder::strict_path`] followed only by the allowed
+    /// dirs, for running code with the mocks under [`CommandeerBuilder::manage_path`]`(false)`.
+    pub fn path(&self) -> String {
+        mocked_path(
+            self.temp_dir.path(),
+            &self.original_path,
+            self.options.strict_path.as_deref(),
+        )
+    }
+
+    /// A command for `program` that resolves it and anything it runs with [`Self::path`], so it sees the mocks even
+    /// when the process-wide `PATH` doesn't.
+    pub fn command(&self, program: impl AsRef<std::ffi::OsStr>) -> std::process::Command {
+        let mut command = std::process::Command::new(program);
+        command.env("PATH", self.path());
+
+        command
+    }
+
+    pub fn mock_command(&self, command_name: &str) -> PathBuf {
+        self.try_mock_command(command_name)
+            .expect("Failed to write mock wrapper script")
+    }
+
+    /// Like [`Self::mock_command`], but returns an error instead of panicking when the wrapper can't be written,
+    /// e.g. because the disk is full or the temp dir isn't writable.
+    pub fn try_mock_command(&self, command_name: &str) -> Result<PathBuf> {
+        let mock_path = self.temp_dir.path().join(command_name);
+
+        self.write_wrapper(&mock_path, command_name, None)?;
+
+        Ok(mock_path)
+    }
+
+    /// Mocks `command_name` as absent, whatever the real `PATH` holds: it exits with 127 and prints
+    /// `<command_name>: command not found` to stderr, as a shell does for an unknown command.
+    ///
+    /// Unlike a replay miss this models intentional absence, so it is neither recorded nor added to the call log.
+    pub fn mock_missing(&self, command_name: &str) -> PathBuf {
+        self.try_mock_missing(command_name)
+            .expect("Failed to write mock wrapper script")
+    }
+
+    /// Like [`Self::mock_missing`], but returns an error instead of panicking when the wrapper can't be written.
+    pub fn try_mock_missing(&self, command_name: &str) -> Result<PathBuf> {
+        let mock_path = self.temp_dir.path().join(command_name);
+
+        write_script(
+            &mock_path,
+            &format!(
+                "#!/usr/bin/env bash\necho {} >&2\nexit 127\n",
+                shell_quote(&format!("{command_name}: command not found"))
+            ),
+        )?;
+        check_syntax(&mock_path)?;
+
+        Ok(mock_path)
+    }
+
+    /// Mocks `sh` so a pipeline run via `sh -c "a | b"` is stored as one invocation of `sh`.
+    ///
+    /// The recording holds the `-c` script as its args and the pipeline's final stdout, stderr and exit code. While
+    /// recording, the commands inside the pipeline run for real against the original `PATH`, so they are neither
+    /// mocked nor recorded individually; on replay none of them run at all.
+    pub fn mock_pipeline(&self) -> PathBuf {
+        self.mock_command("sh")
+    }
+
+    /// Mocks `env` so the command it runs resolves to its mock even under `env -i`.
+    ///
+    /// The real `env` already finds mocks for a plain `env FOO=bar git status` through `PATH`, but `-i` drops `PATH`
+    /// and so reaches `/usr/bin/git`. This stand-in applies `-i` (keeping `PATH`), `-u NAME` and the `NAME=value`
+    /// assignments itself and then runs the command, so `git`'s mock sees `FOO` set. `env` itself is neither
+    /// recorded nor added to the call log, and any other option, or no command to run, hands the whole invocation
+    /// to the real `env` unchanged.
+    pub fn mock_env(&self) -> PathBuf {
+        self.try_mock_env()
+            .expect("Failed to write mock wrapper script")
+    }
+
+    /// Like [`Self::mock_env`], but returns an error instead of panicking when the wrapper can't be written.
+    pub fn try_mock_env(&self) -> Result<PathBuf> {
+        let mock_path = self.temp_dir.path().join("env");
+
+        write_script(&mock_path, ENV_DISPATCHER)?;
+        check_syntax(&mock_path)?;
+
+        Ok(mock_path)
+    }
+
+    /// Mocks every executable on the original `PATH`, returning the mocked names sorted.
+    ///
+    /// Combined with [`Mode::Record`] this captures a whole session without enumerating commands up front, producing
+    /// a fixture that can be committed and replayed. `bash` and `env` are left alone because the wrappers run through
+    /// them.
+    pub fn mock_all(&self) -> Vec<String> {
+        const WRAPPER_DEPENDENCIES: [&str; 2] = ["bash", "env"];
+
+        let mut names = HashSet::new();
+
+        for dir in env::split_paths(&self.original_path) {
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+
+            for entry in entries.flatten() {
+                let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                    continue;
+                };
+
+                if WRAPPER_DEPENDENCIES.contains(&name.as_str()) || !is_executable(&entry.path()) {
+                    continue;
+                }
+
+                names.insert(name);
+            }
+        }
+
+        let mut names: Vec<String> = names.into_iter().collect();
+        names.sort();
+
+        for name in &names {
+            self.mock_command(name);
+        }
+
+        names
+    }
+
+    /// Mocks commands that are invoked through an absolute path rather than resolved on `PATH`.
+    ///
+    /// Each `(real_path, command_name)` pair materializes a wrapper at `real_path` re-rooted under [`Self::temp_path`]
+    /// (`/opt/toolchain/bin/gcc` becomes `<temp>/opt/toolchain/bin/gcc`) whose invocations are stored under
+    /// `command_name`. Recording runs `real_path` itself. Only the temp dir root is on `PATH`, so these wrappers are
+    /// reachable through the returned paths and never shadow basenames.
+    pub fn mock_path_layout(&self, layout: &[(&str, &str)]) -> Vec<PathBuf> {
+        self.try_mock_path_layout(layout)
+            .expect("Failed to write mock wrapper script")
+    }
+
+    /// Like [`Self::mock_path_layout`], but returns an error instead of panicking when a wrapper or the directories
+    /// leading to it can't be written.
+    pub fn try_mock_path_layout(&self, layout: &[(&str, &str)]) -> Result<Vec<PathBuf>> {
+        layout
+            .iter()
+            .map(|(real_path, command_name)| {
+                let real_path = Path::new(real_path);
+                let relative: PathBuf = real_path
+                    .components()
+                    .filter(|c| matches!(c, std::path::Component::Normal(_)))
+                    .collect();
+                let mock_path = self.temp_dir.path().join(relative);
+
+                if let Some(parent) = mock_path.parent() {
+                    fs::create_dir_all(parent).map_err(|source| CommandeerError::WriteMock {
+                        path: mock_path.clone(),
+                        source,
+                    })?;
+                }
+
+                self.write_wrapper(&mock_path, command_name, Some(real_path))?;
+
+                Ok(mock_path)
+            })
+            .collect()
+    }
+
+    fn write_wrapper(
+        &self,
+        mock_path: &Path,
+        command_name: &str,
+        program: Option<&Path>,
+    ) -> Result<()> {
+        if self
+            .options
+            .passthrough
+            .iter()
+            .any(|name| name == command_name)
+        {
+            return self.write_passthrough(mock_path, command_name, program);
+        }
+
+        self.mocked
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(command_name.to_string());
+
+        let mut flags = vec![];
+
+        if self.mode != Mode::Replay {
+            // The real command sees the name the code under test invoked, not the wrapper's temp path.
+            match program {
+                Some(program) => {
+                    flags.push(format!("--program {}", quote_path(program)));
+                    flags.push(format!("--arg0 {}", quote_path(program)));
+                }
+                None => flags.push(format!("--arg0 {}", shell_quote(command_name))),
+            }
+
+            if self.options.no_wait_output {
+                flags.push("--no-wait-output".to_string());
+            }
+
+            if self.options.live_output {
+                flags.push("--live-output".to_string());
+            }
+
+            for locale in &self.options.locales {
+                flags.push(format!("--locale {}", shell_quote(locale)));
+            }
+
+            for transform in &self.options.transforms {
+                flags.push(format!("--transform {transform}"));
+            }
+
+            if let Some(epoch) = self.options.source_date_epoch {
+                flags.push(format!("--source-date-epoch {epoch}"));
+            }
+
+            if let Some(faketime) = &self.options.faketime {
+                flags.push(format!("--faketime {}", shell_quote(faketime)));
+            }
+
+            if let Some(store) = &self.options.blob_store {
+                flags.push(format!("--blob-store {}", quote_path(store)));
+            }
+
+            if self.options.index {
+                flags.push("--index".to_string());
+            }
+
+            if let Some(max) = self.options.max_per_key {
+                flags.push(format!("--max-per-key {max}"));
+            }
+
+            for option in &self.options.output_file_options {
+                flags.push(format!("--output-file-option={}", shell_quote(option)));
+            }
+
+            let timeout = self.options.command_timeouts.get(command_name);
+
+            if let Some(timeout) = timeout.or(self.options.timeout.as_ref()) {
+                flags.push(format!("--timeout-ms {}", timeout.as_millis()));
+            }
+
+            for arg in self
+                .options
+                .extra_record_args
+                .get(command_name)
+                .into_iter()
+                .flatten()
+            {
+                flags.push(format!("--extra-arg={}", shell_quote(arg)));
+            }
+
+            if self.options.capture_stdin {
+                flags.push("--capture-stdin".to_string());
+            }
+
+            if self.options.record_timing {
+                flags.push("--record-timing".to_string());
+            }
+
+            if self.options.record_executable {
+                flags.push("--record-executable".to_string());
+            }
+
+            if self.options.record_rusage {
+                flags.push("--record-rusage".to_string());
+            }
+
+            if self.options.record_fd_count {
+                flags.push("--record-fd-count".to_string());
+            }
+
+            if self.options.scan_secrets != SecretScan::Off {
+                flags.push(format!("--scan-secrets {}", self.options.scan_secrets));
+            }
+        }
+
+        if self.mode != Mode::Record {
+            flags.extend(self.options.match_options.to_cli_args());
+        }
+
+        if let Some(test_name) = &self.options.test_name {
+            flags.push(format!("--test-name {}", shell_quote(test_name)));
+        }
+
+        if self.options.normalize_temp_paths {
+            flags.push("--normalize-temp-paths".to_string());
+        }
+
+        if self.options.response_files {
+            flags.push("--response-files".to_string());
+        }
+
+        if self.options.canonical_json {
+            flags.push("--canonical-json".to_string());
+        }
+
+        if self.options.expand_home {
+            flags.push("--expand-home".to_string());
+        }
+
+        if self.options.native_line_endings {
+            flags.push("--native-line-endings".to_string());
+        }
+
+        if self.options.concurrent_output {
+            flags.push("--concurrent-output".to_string());
+        }
+
+        if self.options.debug {
+            flags.push("--debug".to_string());
+        }
+
+        if self.options.trace_invocations {
+            flags.push("--trace-invocations".to_string());
+        }
+
+        if self.options.echo_invocation {
+            flags.push("--echo-invocation".to_string());
+        }
+
+        if self.options.signal_replay != SignalReplay::default() {
+            flags.push(format!("--signal-replay {}", self.options.signal_replay));
+        }
+
+        if let Some((chunk_size, delay)) = self.options.stream_chunks {
+            flags.push(format!("--chunk-size {chunk_size}"));
+            flags.push(format!("--chunk-delay-ms {}", delay.as_millis()));
+        }
+
+        for option in &self.options.split_options {
+            flags.push(format!("--split-option={}", shell_quote(option)));
+        }
+
+        if let Some(value_options) = &self.options.unordered_flags {
+            flags.push("--unordered-flags".to_string());
+
+            for option in value_options {
+                flags.push(format!("--flag-value={}", shell_quote(option)));
+            }
+        }
+
+        for var in &self.options.capture_env {
+            flags.push(format!("--capture-env {}", shell_quote(var)));
+        }
+
+        let flags: String = flags.iter().map(|flag| format!(" {flag}")).collect();
+
+        let mode = self.mode;
+        // Every path is quoted, since nested fixture paths such as `scenario a/git.json`, or a temp dir under a
+        // home like `/Users/Jane Doe`, may hold spaces.
+        let fixture = quote_path(&self.fixture);
+
+        // The rest of the caller's environment is inherited as is. `PATH` keeps any change the caller made to it,
+        // e.g. via `Command::env`, minus the mock dir so the real command isn't resolved to this wrapper again. `env`
+        // is run by path so it can't resolve to the `mock_env` stand-in.
+        let wrapper = format!(
+            r#"#!/usr/bin/env bash
+mock_dir={}
+path=":$PATH:"
+path="${{path//":$mock_dir:"/:}}"
+path="${{path#:}}"
+exec /usr/bin/env PATH="${{path%:}}" {COMMANDEER_ACTIVE_MODE}={mode} {COMMANDEER_ACTIVE_FIXTURE}={fixture} {COMMANDEER_PARENT}=$$ {} {mode}{flags} ${{{COMMANDEER_PARENT}:+--parent-pid ${COMMANDEER_PARENT}}} --wrapper "$0" --file {fixture} --call-log {} --command {} "$@"
+"#,
+            quote_path(self.temp_dir.path()),
+            quote_path(&self.mock_runner),
+            quote_path(&self.call_log),
+            shell_quote(command_name),
+        );
+
+        write_script(mock_path, &wrapper)?;
+        check_syntax(mock_path)
+    }
+
+    /// Writes a wrapper for a [`CommandeerBuilder::passthrough`] command that execs `program`, or else the first
+    /// `command_name` on the original `PATH`, keeping the name it was invoked under.
+    fn write_passthrough(
+        &self,
+        mock_path: &Path,
+        command_name: &str,
+        program: Option<&Path>,
+    ) -> Result<()> {
+        let real = match program {
+            Some(program) => program.to_path_buf(),
+            None => env::split_paths(&self.original_path)
+                .map(|dir| dir.join(command_name))
+                .find(|path| is_executable(path))
+                .ok_or_else(|| CommandeerError::CommandNotFound {
+                    command: command_name.to_string(),
+                    path: self.original_path.clone(),
+                })?,
+        };
+
+        write_script(
+            mock_path,
+            &format!(
+                "#!/usr/bin/env bash\nexec -a {} {} \"$@\"\n",
+                shell_quote(command_name),
+                quote_path(&real)
+            ),
+        )?;
+        check_syntax(mock_path)
+    }
+
+    /// Every invocation intercepted by this instance's mocks so far, in call order.
+    pub fn calls(&self) -> Vec<CallLogEntry> {
+        read_call_log(&self.call_log).expect("Failed to read call log")
+    }
+
+    /// The intercepted invocations arranged by which mocked command ran which; see [`spawn_tree`].
+    pub fn spawn_tree(&self) -> Vec<SpawnNode> {
+        spawn_tree(&self.calls())
+    }
+
+    /// The stdin captured with the recording of `command` with `args`, or `None` if there is no such recording or it
+    /// was made without [`CommandeerBuilder::capture_stdin`].
+    pub fn recorded_stdin(&self, command: &str, args: &[&str]) -> Option<Vec<u8>> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+
+        self.fixture_recordings()?
+            .find_invocation(command, &args)?
+            .stdin
+            .clone()
+    }
+
+    /// The fixture's recordings for this instance's test, or `None` if the fixture can't be read or parsed.
+    fn fixture_recordings(&self) -> Option<RecordedCommands> {
+        let contents = compression::read(&self.fixture).ok()?;
+        let mut recordings = parse_recordings(&self.fixture, &contents).ok()?;
+
+        if let Some(test_name) = &self.options.test_name {
+            recordings = recordings.tests.remove(test_name).unwrap_or_default();
+        }
+
+        Some(recordings)
+    }
+
+    /// Keys of fixture recordings that no intercepted invocation has matched, sorted.
+    pub fn unused_recordings(&self) -> Vec<String> {
+        let Some(recordings) = self.fixture_recordings() else {
+            return vec![];
+        };
+
+        let consulted: HashSet<String> = self
+            .calls()
+            .into_iter()
+            .filter_map(|call| call.key)
+            .collect();
+
+        let mut unused: Vec<String> = recordings
+            .commands
+            .into_keys()
+            .filter(|key| !consulted.contains(key))
+            .collect();
+
+        unused.sort();
+
+        unused
+    }
+}
+
+impl Drop for Commandeer {
+    fn drop(&mut self) {
+        if !self.options.unmanaged_path {
+            unsafe {
+                std::env::set_var("PATH", &self.original_path);
+            }
+        }
+
+        if self.options.warn_unused && self.mode == Mode::Replay && !std::thread::panicking() {
+            for key in self.unused_recordings() {
+                eprintln!(
+                    "commandeer: warning: recording `{key}` in {} was never replayed",
+                    self.fixture.display()
+                );
+            }
+        }
+
+        if let Some(expected) = &self.options.expected_commands
+            && !std::thread::panicking()
+        {
+            let calls = read_call_log(&self.call_log).unwrap_or_default();
+
+            if self.options.warn_unused {
+                for command in expected {
+                    if !calls.iter().any(|call| &call.binary_name == command) {
+                        eprintln!(
+                            "commandeer: warning: expected command `{command}` was never invoked"
+                        );
+                    }
+                }
+            }
+
+            let unexpected: Vec<String> = calls
+                .into_iter()
+                .filter(|call| !expected.contains(&call.binary_name))
+                .map(|call| format!("\n  {} {}", call.binary_name, call.args.join(" ")))
+                .collect();
+
+            if !unexpected.is_empty() {
+                panic!(
+                    "commandeer: invoked commands outside {}:{}",
+                    expected.join(", "),
+                    unexpected.concat()
+                );
+            }
+        }
+
+        if self.options.fail_on_miss && !std::thread::panicking() {
+            let misses: Vec<String> = read_call_log(&self.call_log)
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|call| !call.matched)
+                .map(|call| format!("\n  {} {}", call.binary_name, call.args.join(" ")))
+                .collect();
+
+            if !misses.is_empty() {
+                panic!(
+                    "commandeer: no recording in {} for:{}",
+                    self.fixture.display(),
+                    misses.concat()
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate as commandeer_test;
+    use crate::{Commandeer, Mode, commandeer};
+
+    #[serial_test::serial]
+    fn test_mock_cmd() {
+        let commandeer = Commandeer::new("test_recordings.json", Mode::Replay);
+        let mock_path = commandeer.mock_command("echo");
+
+        let status = std::process::Command::new("echo")
+            .arg("foo")
+            .status()
+            .unwrap();
+
+        assert!(status.success());
+
+        assert!(mock_path.exists());
+    }
+
+    #[commandeer(Replay, "echo")]
+    #[serial_test::serial]
+    fn my_test() {
+        let output = std::process::Command::new("echo")
+            .arg("hello")
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+    }
+
+    #[commandeer(Replay, "date")]
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn async_replay() {
+        let output = std::process::Command::new("date").output().unwrap();
+
+        insta::assert_debug_snapshot!(output, @r#"
+        Output {
+            status: ExitStatus(
+                unix_wait_status(
+                    0,
+                ),
+            ),
+            stdout: "Wed Aug 20 12:46:19 EDT 2025\n",
+            stderr: "",
+        }
+        "#);
+    }
+
+    #[commandeer(Replay, "git", "date")]
+    #[test]
+    #[serial_test::serial]
+    fn test_flag_args() {
+        let output = std::process::Command::new("git")
+            .arg("--version")
+            .output()
+            .unwrap();
+
+        insta::assert_debug_snapshot!(output, @r#"
+        Output {
+            status: ExitStatus(
+                unix_wait_status(
+                    0,
+                ),
+            ),
+            stdout: "git version 2.51.0\n",
+            stderr: "",
+        }
+        "#);
+
+        let output = std::process::Command::new("date").output().unwrap();
+
+        insta::assert_debug_snapshot!(output, @r#"
+        Output {
+            status: ExitStatus(
+                unix_wait_status(
+                    0,
+                ),
+            ),
+            stdout: "Thu Aug 21 14:54:45 EDT 2025\n",
+            stderr: "",
+        }
+        "#);
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn sync_record_then_replay() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let file = dir.path().join("nested").join("recordings.json");
+
+        let recorded = crate::record_command_sync(
+            false,
+            file.clone(),
+            "echo".to_string(),
+            vec!["sync".to_string()],
+        )
+        .unwrap();
+
+        assert_eq!(recorded.stdout, b"sync\n");
+        assert_eq!(recorded.exit_code, 0);
+
+        let replayed =
+            crate::replay_command_sync(file.clone(), "echo".to_string(), vec!["sync".to_string()])
+                .unwrap()
+                .unwrap();
+
+        assert_eq!(replayed.stdout, recorded.stdout);
+
+        let missing =
+            crate::replay_command_sync(file, "echo".to_string(), vec!["other".to_string()])
+                .unwrap();
+
+        assert!(missing.is_none());
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    #[serial_test::serial]
+    fn description_survives_rerecord() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixture = dir.path().join("described.json");
+
+        std::fs::write(
+            &fixture,
+            r#"{
+  "description": "Greets the user.\nCovers the happy path only.",
+  "commands": {
+    "echo:stale": [
+      { "binary_name": "echo", "args": ["stale"], "stdout": "stale\n", "stderr": "", "exit_code": 0 }
+    ]
+  }
+}"#,
+        )
+        .unwrap();
+
+        let commandeer = Commandeer::new(&fixture, Mode::Record);
+        commandeer.mock_command("echo");
+
+        std::process::Command::new("echo")
+            .arg("hello")
+            .status()
+            .unwrap();
+
+        crate::record_command_sync(
+            true,
+            fixture.clone(),
+            "echo".to_string(),
+            vec!["bye".to_string()],
+        )
+        .unwrap();
+
+        let recordings = crate::load_recordings_sync(&fixture).unwrap();
+
+        assert_eq!(
+            recordings.description.as_deref(),
+            Some("Greets the user.\nCovers the happy path only.")
+        );
+        assert_eq!(
+            crate::format_recordings(&recordings).lines().next(),
+            Some("# Greets the user.")
+        );
+        assert!(
+            recordings
+                .find_invocation("echo", &["stale".to_string()])
+                .is_none()
+        );
+        assert!(
+            recordings
+                .find_invocation("echo", &["bye".to_string()])
+                .is_some()
+        );
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn sync_truncate_discards_previous_recordings() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let file = dir.path().join("recordings.json");
+
+        crate::record_command_sync(
+            false,
+            file.clone(),
+            "echo".to_string(),
+            vec!["a".to_string()],
+        )
+        .unwrap();
+        crate::record_command_sync(
+            true,
+            file.clone(),
+            "echo".to_string(),
+            vec!["b".to_string()],
+        )
+        .unwrap();
+
+        let recordings = crate::load_recordings_sync(&file).unwrap();
+
+        assert!(
+            recordings
+                .find_invocation("echo", &["a".to_string()])
+                .is_none()
+        );
+        assert!(
+            recordings
+                .find_invocation("echo", &["b".to_string()])
+                .is_some()
+        );
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn mock_path_layout() {
+        let commandeer = Commandeer::new("cmds_mock_path_layout.json", Mode::Replay);
+        let mocks = commandeer.mock_path_layout(&[("/opt/toolchain/bin/gcc", "gcc")]);
+
+        let gcc = commandeer.temp_path().join("opt/toolchain/bin/gcc");
+
+        assert_eq!(mocks, vec![gcc.clone()]);
+
+        let output = std::process::Command::new(&gcc)
+            .arg("--version")
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "gcc (GCC) 14.2.1\n"
+        );
+        assert!(!commandeer.temp_path().join("gcc").exists());
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn latin1_output_is_decoded_for_listing() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let file = dir.path().join("recordings.json");
+
+        let options = crate::RecordOptions {
+            encoding: Some("latin1".to_string()),
+            ..crate::RecordOptions::default()
+        };
+
+        let recorded = crate::record_command_sync_with(
+            &options,
+            file.clone(),
+            "printf".to_string(),
+            vec!["caf\\351\\n".to_string()],
+        )
+        .unwrap();
+
+        assert_eq!(recorded.stdout, b"caf\xe9\n");
+
+        let recordings = crate::load_recordings_sync(&file).unwrap();
+
+        insta::assert_snapshot!(crate::format_recordings(&recordings), @r#"
+        printf:caf\\351\\n
+          [0] exit 0, encoding windows-1252
+            stdout: "café\n"
+            stderr: ""
+        "#);
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn undecodable_output_is_kept_byte_for_byte() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let file = dir.path().join("recordings.json");
+
+        // An invalid Shift_JIS byte, and binary output without an encoding.
+        for (encoding, arg) in [(Some("shift_jis"), "\\202\\240\\377"), (None, "\\000\\377")] {
+            let options = crate::RecordOptions {
+                encoding: encoding.map(str::to_string),
+                ..crate::RecordOptions::default()
+            };
+
+            crate::record_command_sync_with(
+                &options,
+                file.clone(),
+                "printf".to_string(),
+                vec![arg.to_string()],
+            )
+            .unwrap();
+        }
+
+        let recordings = crate::load_recordings_sync(&file).unwrap();
+
+        assert_eq!(
+            recordings
+                .find_invocation("printf", &["\\202\\240\\377".into()])
+                .unwrap()
+                .stdout,
+            b"\x82\xa0\xff"
+        );
+        assert_eq!(
+            recordings
+                .find_invocation("printf", &["\\000\\377".into()])
+                .unwrap()
+                .stdout,
+            b"\x00\xff"
+        );
+    }
+
+    #[commandeer(Replay, "git", skip_if_missing = true)]
+    #[test]
+    #[serial_test::serial]
+    fn skips_without_fixture() {
+        panic!("ran without {}", commandeer.fixture().display());
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn skip_reason_names_missing_command() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixture = dir.path().join("partial.json");
+        let skip_reason = |mode, commands: &[&str]| {
+            Commandeer::builder(&fixture, mode)
+                .skip_if_missing(true)
+                .build()
+                .skip_reason(commands)
+        };
+
+        assert_eq!(
+            skip_reason(Mode::Replay, &["echo"]),
+            Some(format!("{} doesn't exist", fixture.display()))
+        );
+
+        std::fs::write(
+            &fixture,
+            r#"{"commands": {"echo:hi": [{"binary_name": "echo", "args": ["hi"], "stdout": "hi\n", "stderr": "", "exit_code": 0}]}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(skip_reason(Mode::Replay, &["echo"]), None);
+        assert_eq!(
+            skip_reason(Mode::Replay, &["echo", "git"]),
+            Some(format!("{} has no recordings of `git`", fixture.display()))
+        );
+        assert_eq!(skip_reason(Mode::RecordOnce, &["git"]), None);
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn skip_reason_counts_layers_and_override() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let base = dir.path().join("base.json");
+        let fixture = dir.path().join("layered.json");
+        let recording = |command: &str| {
+            format!(
+                r#"{{"commands": {{"{command}:": [{{"binary_name": "{command}", "args": [], "stdout": "", "stderr": "", "exit_code": 0}}]}}}}"#
+            )
+        };
+        std::fs::write(&base, recording("git")).unwrap();
+        std::fs::write(crate::override_path(&fixture), recording("kubectl")).unwrap();
+
+        let commandeer = Commandeer::builder(&fixture, Mode::Replay)
+            .skip_if_missing(true)
+            .fixture_layers([&base])
+            .build();
+
+        assert_eq!(commandeer.skip_reason(&["git", "kubectl"]), None);
+        assert_eq!(
+            commandeer.skip_reason(&["helm"]),
+            Some(format!("{} has no recordings of `helm`", fixture.display()))
+        );
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn malformed_fixture_reads_as_no_recordings() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixture = dir.path().join("malformed.json");
+        std::fs::write(&fixture, "{not json").unwrap();
+
+        let commandeer = Commandeer::builder(&fixture, Mode::Replay)
+            .warn_unused(true)
+            .build();
+
+        assert!(commandeer.unused_recordings().is_empty());
+        assert!(commandeer.recorded_stdin("echo", &["hi"]).is_none());
+    }
+
+    #[commandeer(Replay, "git", validate_fixture = true)]
+    #[test]
+    #[serial_test::serial]
+    #[should_panic(expected = "cmds_validates_fixture_upfront.json: it doesn't exist")]
+    fn validates_fixture_upfront() {
+        std::process::Command::new("git")
+            .arg("status")
+            .status()
+            .unwrap();
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn validation_fails_on_fixture_without_mocked_command() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixture = dir.path().join("empty.json");
+        std::fs::write(&fixture, r#"{"commands": {}}"#).unwrap();
+
+        let commandeer = Commandeer::builder(&fixture, Mode::Replay).build();
+        commandeer.mock_command("git");
+
+        let err = commandeer.validate_fixture().unwrap_err();
+
+        assert!(matches!(err, crate::CommandeerError::InvalidFixture { .. }));
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Invalid fixture {}: no recordings of `git`",
+                fixture.display()
+            )
+        );
+
+        let commandeer = Commandeer::builder(&fixture, Mode::RecordOnce).build();
+        commandeer.mock_command("git");
+
+        assert!(commandeer.validate_fixture().is_ok());
+
+        std::fs::write(&fixture, "{").unwrap();
+
+        assert!(matches!(
+            commandeer.validate_fixture(),
+            Err(crate::CommandeerError::Parse { .. })
+        ));
+    }
+
+    #[commandeer(Replay)]
+    #[test]
+    #[serial_test::serial]
+    fn no_commands_form() {
+        if cfg!(unix) {
+            commandeer.mock_command("echo");
+        }
+
+        let output = std::process::Command::new("echo")
+            .arg("conditional")
+            .output()
+            .unwrap();
+
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "conditional\n");
+    }
+
+    #[cfg(feature = "sync")]
+    #[cfg(unix)]
+    #[test]
+    #[serial_test::serial]
+    fn signals_are_recorded() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixture = dir.path().join("signal.json");
+        let commandeer = Commandeer::builder(&fixture, Mode::Record).build();
+        commandeer.mock_command("sh");
+
+        let output = std::process::Command::new("sh")
+            .args(["-c", "kill -TERM $$"])
+            .output()
+            .unwrap();
+
+        // The mock passes the signal on as an exit code, as replay does by default.
+        assert_eq!(output.status.code(), Some(143));
+
+        let recordings = crate::load_recordings_sync(&fixture).unwrap();
+        let invocation = recordings
+            .find_invocation("sh", &["-c".to_string(), "kill -TERM $$".to_string()])
+            .unwrap();
+
+        assert_eq!(invocation.signal, Some(libc::SIGTERM));
+        assert_eq!(invocation.exit_code, 143);
+    }
+
+    #[cfg(unix)]
+    fn replay_sigterm(signal_replay: crate::SignalReplay) -> std::process::ExitStatus {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixture = dir.path().join("sigterm.json");
+        std::fs::write(
+            &fixture,
+            r#"{"commands":{"sleep:10":[{"binary_name":"sleep","args":["10"],"stdout":"","stderr":"","exit_code":143,"signal":15}]}}"#,
+        )
+        .unwrap();
+
+        let commandeer = Commandeer::builder(&fixture, Mode::Replay)
+            .signal_replay(signal_replay)
+            .build();
+        commandeer.mock_command("sleep");
+
+        std::process::Command::new("sleep")
+            .arg("10")
+            .status()
+            .unwrap()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[serial_test::serial]
+    fn sigterm_replays_as_exit_code_by_default() {
+        use std::os::unix::process::ExitStatusExt as _;
+
+        let status = replay_sigterm(crate::SignalReplay::default());
+
+        assert_eq!(status.code(), Some(143));
+        assert_eq!(status.signal(), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[serial_test::serial]
+    fn sigterm_replays_as_signal_when_raised() {
+        use std::os::unix::process::ExitStatusExt as _;
+
+        let status = replay_sigterm(crate::SignalReplay::Raise);
+
+        assert_eq!(status.signal(), Some(libc::SIGTERM));
+        assert_eq!(status.code(), None);
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn out_of_range_exit_code_is_normalized() {
+        let commandeer = Commandeer::new("cmds_out_of_range_exit_code.json", Mode::Replay);
+        commandeer.mock_command("false");
+
+        let output = std::process::Command::new("false").output().unwrap();
+
+        assert_eq!(output.status.code(), Some(44));
+        assert_eq!(
+            String::from_utf8_lossy(&output.stderr),
+            "commandeer: warning: exit code 300 is out of range for this platform, exiting with 44\n"
+        );
+    }
+
+    #[test]
+    fn select_invocation_by_occurrence() {
+        let mut recordings = crate::RecordedCommands::default();
+
+        for stdout in ["first\n", "second\n", "third\n"] {
+            recordings.add_invocation(crate::CommandInvocation {
+                binary_name: "tool".to_string(),
+                args: vec!["poll".to_string()],
+                stdout: stdout.into(),
+                stderr: vec![],
+                exit_code: 0,
+                ..Default::default()
+            });
+        }
+
+        let invocations = recordings.invocations("tool", &["poll".to_string()]);
+
+        assert_eq!(invocations.len(), 3);
+        assert_eq!(invocations[2].stdout, b"third\n");
+        assert!(invocations.get(3).is_none());
+        assert!(recordings.invocations("tool", &[]).is_empty());
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn warn_unused_recordings() {
+        let commandeer = Commandeer::builder("cmds_warn_unused_recordings.json", Mode::Replay)
+            .warn_unused(true)
+            .build();
+        commandeer.mock_command("echo");
+
+        let output = std::process::Command::new("echo")
+            .arg("used")
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+
+        let calls: Vec<_> = commandeer
+            .calls()
+            .into_iter()
+            .map(|call| (call.binary_name, call.args, call.exit_code, call.matched))
+            .collect();
+
+        assert_eq!(
+            calls,
+            vec![("echo".to_string(), vec!["used".to_string()], 0, true)]
+        );
+        assert_eq!(commandeer.unused_recordings(), vec!["echo:unused"]);
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn nested_fixture_paths() {
+        let elsewhere = tempfile::TempDir::new().unwrap();
+
+        let commandeer = Commandeer::new("scenario_a/nested/cmds.json", Mode::Replay);
+        commandeer.mock_command("echo");
+
+        let output = std::process::Command::new("echo")
+            .arg("scenario")
+            .current_dir(elsewhere.path())
+            .output()
+            .unwrap();
+
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "from scenario_a\n");
+
+        drop(commandeer);
+
+        let fixtures = tempfile::TempDir::new().unwrap();
+        let fixture = fixtures.path().join("scenario b/it's nested/echo.json");
+
+        for mode in [Mode::Record, Mode::Replay] {
+            let commandeer = Commandeer::new(&fixture, mode);
+            commandeer.mock_command("echo");
+
+            let output = std::process::Command::new("echo")
+                .arg("deep")
+                .current_dir(elsewhere.path())
+                .output()
+                .unwrap();
+
+            assert_eq!(String::from_utf8_lossy(&output.stdout), "deep\n", "{mode}");
+        }
+
+        assert!(fixture.exists());
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    #[serial_test::serial]
+    fn mock_from_different_cwd() {
+        let elsewhere = tempfile::TempDir::new().unwrap();
+
+        let commandeer = Commandeer::new("cmds_my_test.json", Mode::Replay);
+        commandeer.mock_command("echo");
+
+        let output = std::process::Command::new("echo")
+            .arg("hello")
+            .current_dir(elsewhere.path())
+            .output()
+            .unwrap();
+
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "hello\n");
+
+        drop(commandeer);
+
+        let fixtures = tempfile::TempDir::new().unwrap();
+        let fixture = fixtures.path().join("cwd.json");
+
+        let commandeer = Commandeer::new(&fixture, Mode::Record);
+        commandeer.mock_command("pwd");
+
+        let output = std::process::Command::new("pwd")
+            .current_dir(elsewhere.path())
+            .output()
+            .unwrap();
+
+        let cwd = format!("{}\n", elsewhere.path().display());
+
+        assert_eq!(String::from_utf8_lossy(&output.stdout), cwd);
+
+        let recordings = crate::load_recordings_sync(&fixture).unwrap();
+
+        assert_eq!(
+            recordings.find_invocation("pwd", &[]).unwrap().stdout,
+            cwd.as_bytes()
+        );
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    #[serial_test::serial]
+    fn record_once_runs_real_command_once() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixture = dir.path().join("record_once.json");
+        let counter = dir.path().join("counter");
+
+        let commandeer = Commandeer::new(&fixture, Mode::RecordOnce);
+        commandeer.mock_command("sh");
+
+        let script = format!("echo run >> {}; echo slow", counter.display());
+
+        for _ in 0..2 {
+            let output = std::process::Command::new("sh")
+                .args(["-c", &script])
+                .output()
+                .unwrap();
+
+            assert_eq!(String::from_utf8_lossy(&output.stdout), "slow\n");
+        }
+
+        assert_eq!(std::fs::read_to_string(&counter).unwrap(), "run\n");
+
+        let recordings = crate::load_recordings_sync(&fixture).unwrap();
+
+        assert_eq!(
+            recordings
+                .invocations("sh", &["-c".to_string(), script])
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    #[serial_test::serial]
+    #[should_panic(expected = "invoked commands outside echo:\n  printf hi")]
+    fn unexpected_command_panics_on_drop() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let commandeer = Commandeer::builder(dir.path().join("expected.json"), Mode::Record)
+            .expect_commands(["echo"])
+            .build();
+        commandeer.mock_command("echo");
+        commandeer.mock_command("printf");
+
+        for command in ["echo", "printf"] {
+            std::process::Command::new(command)
+                .arg("hi")
+                .output()
+                .unwrap();
+        }
+    }
+
+    #[test]
+    #[serial_test::serial]
+    #[should_panic(expected = "for:\n  echo unrecorded")]
+    fn fail_on_miss_panics_on_drop() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let commandeer = Commandeer::builder(dir.path().join("miss.json"), Mode::Replay)
+            .fail_on_miss(true)
+            .build();
+        commandeer.mock_command("echo");
+
+        // The code under test ignores the failed exit, so only the drop-time check fails the test.
+        let _ = std::process::Command::new("echo")
+            .arg("unrecorded")
+            .output()
+            .unwrap();
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn mock_missing_command_is_not_found() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let commandeer = Commandeer::new(dir.path().join("missing.json"), Mode::Replay);
+        commandeer.mock_missing("ls");
+
+        let output = std::process::Command::new("ls").output().unwrap();
+
+        assert_eq!(output.status.code(), Some(127));
+        assert_eq!(
+            String::from_utf8_lossy(&output.stderr),
+            "ls: command not found\n"
+        );
+        assert!(output.stdout.is_empty());
+        assert!(commandeer.calls().is_empty());
+
+        let quoted = commandeer.try_mock_missing("it's").unwrap();
+        let output = std::process::Command::new(quoted).output().unwrap();
+
+        assert_eq!(
+            String::from_utf8_lossy(&output.stderr),
+            "it's: command not found\n"
+        );
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn debug_reports_active_mode_and_fixture() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixture = dir.path().join("debug.json");
+        let commandeer = Commandeer::builder(&fixture, Mode::Record)
+            .debug(true)
+            .build();
+        commandeer.mock_command("echo");
+
+        let output = std::process::Command::new("echo")
+            .arg("hi")
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "hi\n");
+        assert!(stderr.contains("COMMANDEER_ACTIVE_MODE=record"), "{stderr}");
+        assert!(
+            stderr.contains(&format!("COMMANDEER_ACTIVE_FIXTURE={}", fixture.display())),
+            "{stderr}"
+        );
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn spawn_tree_nests_mock_run_by_mock() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let commandeer = Commandeer::new(dir.path().join("tree.json"), Mode::Record);
+        commandeer.mock_command("sh");
+        let echo = commandeer.mock_command("echo");
+
+        // The recorded `sh` runs without the mocks on `PATH`, so it reaches the nested mock by its path.
+        let script = format!("{} nested", echo.display());
+        let output = std::process::Command::new("sh")
+            .args(["-c", &script])
+            .output()
+            .unwrap();
+
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "nested\n");
+
+        let tree = commandeer.spawn_tree();
+        let shape: Vec<(&str, Vec<&str>)> = tree
+            .iter()
+            .map(|node| {
+                let children = node
+                    .children
+                    .iter()
+                    .map(|child| child.call.binary_name.as_str())
+                    .collect();
+
+                (node.call.binary_name.as_str(), children)
+            })
+            .collect();
+
+        assert_eq!(shape, vec![("sh", vec!["echo"])]);
+        assert!(tree[0].children[0].children.is_empty());
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    #[serial_test::serial]
+    fn nested_instances_are_diagnosed() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixture = dir.path().join("nested.json");
+
+        let outer = Commandeer::new(&fixture, Mode::Record);
+        outer.mock_command("echo");
+
+        assert_eq!(
+            crate::shadowed_instance(&std::env::var("PATH").unwrap()).as_deref(),
+            Some(outer.temp_path())
+        );
+
+        let inner = Commandeer::new(&fixture, Mode::Record);
+        let wrapper = inner.mock_command("echo");
+
+        std::process::Command::new("echo")
+            .arg("shim")
+            .output()
+            .unwrap();
+
+        let recordings = crate::load_recordings_sync(&fixture).unwrap();
+        let recorded = recordings
+            .find_invocation("echo", &["shim".to_string()])
+            .unwrap();
+
+        assert_eq!(recorded.wrapper.as_ref(), Some(&wrapper));
+        assert!(
+            crate::format_recordings(&recordings)
+                .contains(&format!("wrapper: {}", wrapper.display()))
+        );
+
+        drop(inner);
+        drop(outer);
+
+        assert_eq!(
+            crate::shadowed_instance(&std::env::var("PATH").unwrap()),
+            None
+        );
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn unmanaged_path_is_left_alone() {
+        let path = std::env::var("PATH").unwrap();
+        let commandeer = Commandeer::builder("cmds_my_test.json", Mode::Replay)
+            .manage_path(false)
+            .build();
+        commandeer.mock_command("echo");
+
+        assert_eq!(std::env::var("PATH").unwrap(), path);
+
+        let output = commandeer.command("echo").arg("hello").output().unwrap();
+
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "hello\n");
+        assert_eq!(commandeer.calls().len(), 1);
+
+        drop(commandeer);
+
+        assert_eq!(std::env::var("PATH").unwrap(), path);
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn try_mock_command_reports_write_failure() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let commandeer = Commandeer::new(dir.path().join("unwritable.json"), Mode::Replay);
+        // A directory in the wrapper's place makes the write fail even when running as root.
+        std::fs::create_dir(commandeer.temp_path().join("echo")).unwrap();
+
+        let err = commandeer.try_mock_command("echo").unwrap_err();
+
+        assert!(
+            matches!(&err, crate::CommandeerError::WriteMock { path, .. } if path.ends_with("echo")),
+            "{err}"
+        );
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    #[serial_test::serial]
+    fn blob_store_dedups_identical_output() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let store = dir.path().join("blobs");
+
+        for test in ["first", "second"] {
+            let commandeer =
+                Commandeer::builder(dir.path().join(format!("{test}.json")), Mode::Record)
+                    .blob_store(&store)
+                    .build();
+            commandeer.mock_command("echo");
+
+            std::process::Command::new("echo")
+                .arg("shared")
+                .output()
+                .unwrap();
+        }
+
+        assert_eq!(std::fs::read_dir(&store).unwrap().count(), 1);
+
+        let blob = |test: &str| {
+            let recordings =
+                crate::load_recordings_sync(&dir.path().join(format!("{test}.json"))).unwrap();
+
+            recordings
+                .find_invocation("echo", &["shared".to_string()])
+                .unwrap()
+                .stdout_blob
+                .clone()
+                .unwrap()
+        };
+
+        assert_eq!(blob("first"), blob("second"));
+        assert!(blob("first").is_relative());
+
+        let commandeer = Commandeer::new(dir.path().join("second.json"), Mode::Replay);
+        commandeer.mock_command("echo");
+
+        let output = std::process::Command::new("echo")
+            .arg("shared")
+            .output()
+            .unwrap();
+
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "shared\n");
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    #[serial_test::serial]
+    fn command_timeout_overrides_default() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixture = dir.path().join("timeouts.json");
+        let commandeer = Commandeer::builder(&fixture, Mode::Record)
+            .timeout(std::time::Duration::from_millis(200))
+            .command_timeout("sh", std::time::Duration::from_secs(10))
+            .build();
+        commandeer.mock_command("sleep");
+        commandeer.mock_command("sh");
+
+        let start = std::time::Instant::now();
+        let output = std::process::Command::new("sleep")
+            .arg("5")
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success());
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains("`sleep` didn't finish within 200ms"),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+
+        let output = std::process::Command::new("sh")
+            .args(["-c", "sleep 0.5; echo done"])
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "done\n");
+
+        let recordings = crate::load_recordings_sync(&fixture).unwrap();
+
+        assert!(recordings.find_invocation("sleep", &["5".into()]).is_none());
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    #[serial_test::serial]
+    fn output_files_are_recreated_on_replay() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixture = dir.path().join("output_files.json");
+        let input = dir.path().join("input.txt");
+        let sorted = dir.path().join("out").join("sorted.txt");
+        std::fs::write(&input, "b\na\n").unwrap();
+        std::fs::create_dir(dir.path().join("out")).unwrap();
+
+        let sort = || {
+            std::process::Command::new("sort")
+                .arg("-o")
+                .arg(&sorted)
+                .arg(&input)
+                .status()
+                .unwrap()
+        };
+
+        let commandeer = Commandeer::builder(&fixture, Mode::Record)
+            .output_files(["-o"])
+            .build();
+        commandeer.mock_command("sort");
+
+        assert!(sort().success());
+        drop(commandeer);
+
+        let recordings = crate::load_recordings_sync(&fixture).unwrap();
+        let args = ["-o", &sorted.to_string_lossy(), &input.to_string_lossy()].map(String::from);
+        let invocation = recordings.find_invocation("sort", &args).unwrap();
+
+        assert_eq!(invocation.writes_files.len(), 1);
+        assert_eq!(invocation.writes_files[0].contents, b"a\nb\n");
+
+        std::fs::remove_dir_all(dir.path().join("out")).unwrap();
+
+        let commandeer = Commandeer::new(&fixture, Mode::Replay);
+        commandeer.mock_command("sort");
+
+        assert!(sort().success());
+        assert_eq!(std::fs::read_to_string(&sorted).unwrap(), "a\nb\n");
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn fixture_layers_override_per_key() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let base = dir.path().join("base.json");
+        let overlay = dir.path().join("overlay.json");
+        std::fs::write(
+            &base,
+            r#"{"commands":{
+                "echo:shared":[{"binary_name":"echo","args":["shared"],"stdout":"base\n","stderr":"","exit_code":0}],
+                "echo:base-only":[{"binary_name":"echo","args":["base-only"],"stdout":"base only\n","stderr":"","exit_code":0}]
+            }}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            &overlay,
+            r#"{"commands":{
+                "echo:shared":[{"binary_name":"echo","args":["shared"],"stdout":"overlay\n","stderr":"","exit_code":0}]
+            }}"#,
+        )
+        .unwrap();
+
+        let commandeer = Commandeer::builder(&overlay, Mode::Replay)
+            .fixture_layers([&base])
+            .build();
+        commandeer.mock_command("echo");
+
+        let echo = |arg: &str| {
+            let output = std::process::Command::new("echo")
+                .arg(arg)
+                .output()
+                .unwrap();
+
+            String::from_utf8_lossy(&output.stdout).into_owned()
+        };
+
+        assert_eq!(echo("shared"), "overlay\n");
+        assert_eq!(echo("base-only"), "base only\n");
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn async_replay_reads_fixture_layers() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let base = dir.path().join("base.json");
+        let fixture = dir.path().join("cmds.json");
+        std::fs::write(
+            &base,
+            r#"{"commands":{"echo:base-only":[{"binary_name":"echo","args":["base-only"],"stdout":"base only\n","stderr":"","exit_code":0}]}}"#,
+        )
+        .unwrap();
+        std::fs::write(&fixture, r#"{"commands":{}}"#).unwrap();
+
+        let options = crate::MatchOptions {
+            fixture_layers: vec![base],
+            ..crate::MatchOptions::default()
+        };
+        let replayed = crate::replay_command_with(
+            &options,
+            fixture,
+            "echo".to_string(),
+            vec!["base-only".to_string()],
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(replayed.stdout, b"base only\n");
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn override_file_takes_precedence_per_key() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixture = dir.path().join("cmds.json");
+        let base = r#"{"commands":{
+            "echo:a":[{"binary_name":"echo","args":["a"],"stdout":"recorded a\n","stderr":"","exit_code":0}],
+            "echo:b":[{"binary_name":"echo","args":["b"],"stdout":"recorded b\n","stderr":"","exit_code":0}]
+        }}"#;
+        std::fs::write(&fixture, base).unwrap();
+        std::fs::write(
+            crate::override_path(&fixture),
+            r#"{"commands":{
+                "echo:a":[{"binary_name":"echo","args":["a"],"stdout":"tweaked a\n","stderr":"","exit_code":3}]
+            }}"#,
+        )
+        .unwrap();
+
+        let commandeer = Commandeer::builder(&fixture, Mode::Replay).build();
+        commandeer.mock_command("echo");
+
+        let echo = |arg: &str| {
+            std::process::Command::new("echo")
+                .arg(arg)
+                .output()
+                .unwrap()
+        };
+
+        let a = echo("a");
+        assert_eq!(String::from_utf8_lossy(&a.stdout), "tweaked a\n");
+        assert_eq!(a.status.code(), Some(3));
+        assert_eq!(String::from_utf8_lossy(&echo("b").stdout), "recorded b\n");
+        assert_eq!(std::fs::read_to_string(&fixture).unwrap(), base);
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    #[serial_test::serial]
+    fn replay_learn_records_misses_to_pending() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixture = dir.path().join("learn.json");
+        let fixture_json = r#"{"commands":{"echo:known":[{"binary_name":"echo","args":["known"],"stdout":"recorded\n","stderr":"","exit_code":0}]}}"#;
+        std::fs::write(&fixture, fixture_json).unwrap();
+
+        let commandeer = Commandeer::new(&fixture, Mode::ReplayLearn);
+        commandeer.mock_command("echo");
+
+        let echo = |arg: &str| {
+            let output = std::process::Command::new("echo")
+                .arg(arg)
+                .output()
+                .unwrap();
+
+            String::from_utf8_lossy(&output.stdout).into_owned()
+        };
+
+        assert_eq!(echo("known"), "recorded\n");
+        assert_eq!(echo("new"), "new\n");
+        assert_eq!(echo("new"), "new\n");
+
+        assert_eq!(std::fs::read_to_string(&fixture).unwrap(), fixture_json);
+
+        let pending = crate::load_recordings_sync(&crate::pending_path(&fixture)).unwrap();
+
+        assert_eq!(pending.invocations("echo", &["new".to_string()]).len(), 1);
+        assert!(
+            pending
+                .find_invocation("echo", &["known".to_string()])
+                .is_none()
+        );
+    }
+
+    #[cfg(all(feature = "sync", feature = "zstd"))]
+    #[test]
+    fn zstd_fixture_round_trips() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let file = dir.path().join("recordings.json.zst");
+
+        let mut recordings = crate::RecordedCommands::default();
+        recordings.add_invocation(crate::CommandInvocation {
+            binary_name: "echo".to_string(),
+            args: vec!["packed".to_string()],
+            stdout: b"packed\n".repeat(1000),
+            stderr: vec![],
+            exit_code: 0,
+            ..Default::default()
+        });
+        crate::save_recordings_sync(&file, &recordings).unwrap();
+
+        let stored = std::fs::read(&file).unwrap();
+        let json = serde_json::to_string_pretty(&recordings).unwrap();
+
+        assert!(stored.len() < json.len());
+        assert_eq!(
+            zstd::decode_all(stored.as_slice()).unwrap(),
+            json.as_bytes()
+        );
+
+        let loaded = crate::load_recordings_sync(&file).unwrap();
+
+        assert_eq!(serde_json::to_string_pretty(&loaded).unwrap(), json);
+    }
+
+    #[cfg(all(feature = "sync", feature = "gzip"))]
+    #[test]
+    fn gzip_fixture_round_trips() {
+        use std::io::Read as _;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let file = dir.path().join("recordings.json.gz");
+
+        let mut recordings = crate::RecordedCommands::default();
+        recordings.add_invocation(crate::CommandInvocation {
+            binary_name: "echo".to_string(),
+            args: vec!["packed".to_string()],
+            stdout: b"packed\n".repeat(1000),
+            stderr: vec![],
+            exit_code: 0,
+            ..Default::default()
+        });
+        crate::save_recordings_sync(&file, &recordings).unwrap();
+
+        let stored = std::fs::read(&file).unwrap();
+        let json = serde_json::to_string_pretty(&recordings).unwrap();
+
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(stored.as_slice())
+            .read_to_string(&mut decoded)
+            .unwrap();
+
+        assert!(stored.len() < json.len());
+        assert_eq!(decoded, json);
+
+        let loaded = crate::load_recordings_sync(&file).unwrap();
+
+        assert_eq!(serde_json::to_string_pretty(&loaded).unwrap(), json);
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn fixture_min_version_is_enforced() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let file = dir.path().join("recordings.json");
+
+        std::fs::write(&file, r#"{"min_version": "999.0.0", "commands": {}}"#).unwrap();
+
+        let err = crate::load_recordings_sync(&file).unwrap_err();
+
+        assert!(
+            matches!(err, crate::CommandeerError::FixtureTooNew { ref required, .. } if required == "999.0.0")
+        );
+        assert!(err.to_string().ends_with(&format!(
+            "requires commandeer >= 999.0.0, but this is {}",
+            env!("CARGO_PKG_VERSION")
+        )));
+
+        std::fs::write(&file, r#"{"min_version": "0.1.0", "commands": {}}"#).unwrap();
+
+        assert!(crate::load_recordings_sync(&file).is_ok());
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    #[serial_test::serial]
+    fn structured_errors() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let file = dir.path().join("recordings.json");
+
+        let err = crate::record_command_sync(
+            false,
+            file.clone(),
+            "commandeer-definitely-missing".to_string(),
+            vec![],
+        )
+        .unwrap_err();
+
+        let path = std::env::var("PATH").unwrap();
+
+        assert_eq!(
+            err.to_string(),
+            format!("Command not found: commandeer-definitely-missing (searched PATH={path})")
+        );
+        assert!(matches!(
+            err,
+            crate::CommandeerError::CommandNotFound { ref command, path: ref searched }
+                if command == "commandeer-definitely-missing" && *searched == path
+        ));
+
+        std::fs::write(&file, "{ not json").unwrap();
+
+        let err = crate::load_recordings_sync(&file).unwrap_err();
+
+        assert!(matches!(err, crate::CommandeerError::Parse { ref path, .. } if *path == file));
+    }
+
+    #[cfg(all(unix, feature = "sync"))]
+    #[test]
+    fn max_per_key_keeps_latest_recordings() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let file = dir.path().join("recordings.json");
+        let counter = dir.path().join("counter");
+
+        let options = crate::RecordOptions {
+            max_per_key: Some(3),
+            ..crate::RecordOptions::default()
+        };
+        let args = vec![
+            "-c".to_string(),
+            format!(
+                "n=$(($(cat {0} 2>/dev/null || echo 0) + 1)); echo $n > {0}; echo $n",
+                counter.display()
+            ),
+        ];
+
+        for _ in 0..10 {
+            crate::record_command_sync_with(&options, file.clone(), "sh".to_string(), args.clone())
+                .unwrap();
+        }
+
+        let recordings = crate::load_recordings_sync(&file).unwrap();
+        let stdout: Vec<&[u8]> = recordings
+            .invocations("sh", &args)
+            .iter()
+            .map(|invocation| invocation.stdout.as_slice())
+            .collect();
+
+        assert_eq!(stdout, [b"8\n".as_slice(), b"9\n", b"10\n"]);
+    }
+
+    #[cfg(all(unix, feature = "sync"))]
+    #[test]
+    fn record_propagates_arg0() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let file = dir.path().join("recordings.json");
+
+        let options = crate::RecordOptions {
+            arg0: Some("applet".to_string()),
+            ..crate::RecordOptions::default()
+        };
+
+        let recorded = crate::record_command_sync_with(
+            &options,
+            file,
+            "sh".to_string(),
+            vec!["-c".to_string(), "echo $0".to_string()],
+        )
+        .unwrap();
+
+        assert_eq!(recorded.stdout, b"applet\n");
+        assert_eq!(recorded.binary_name, "sh");
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    #[serial_test::serial]
+    fn record_whole_session() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixture = dir.path().join("session.json");
+
+        let commandeer = Commandeer::new(&fixture, Mode::Record);
+        let mocked = commandeer.mock_all();
+
+        assert!(mocked.iter().any(|name| name == "printf"));
+        assert!(!mocked.iter().any(|name| name == "bash"));
+
+        let output = std::process::Command::new("echo")
+            .arg("one")
+            .output()
+            .unwrap();
+
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "one\n");
+
+        let output = std::process::Command::new("printf")
+            .arg("two")
+            .output()
+            .unwrap();
+
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "two");
+
+        let recordings = crate::load_recordings_sync(&fixture).unwrap();
+
+        assert_eq!(
+            recordings
+                .find_invocation("echo", &["one".to_string()])
+                .unwrap()
+                .stdout,
+            b"one\n"
+        );
+        assert_eq!(
+            recordings
+                .find_invocation("printf", &["two".to_string()])
+                .unwrap()
+                .stdout,
+            b"two"
+        );
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn numeric_tolerance_matches_nearby_args() {
+        let commandeer = Commandeer::builder("cmds_numeric_tolerance.json", Mode::Replay)
+            .numeric_tolerance(crate::NumericTolerance {
+                absolute: 2.0,
+                relative: 0.0,
+            })
+            .build();
+        commandeer.mock_command("tool");
+
+        let output = std::process::Command::new("tool")
+            .args(["--timeout", "31"])
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "done\n");
+
+        let output = std::process::Command::new("tool")
+            .args(["--timeout", "40"])
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success());
+        assert!(commandeer.unused_recordings().is_empty());
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn shared_fixture_is_scoped_by_test_name() {
+        for (test_name, own, other) in [("test_a", "a", "b"), ("test_b", "b", "a")] {
+            let commandeer = Commandeer::builder("shared_fixture.json", Mode::Replay)
+                .test_name(test_name)
+                .build();
+            commandeer.mock_command("echo");
+
+            let output = std::process::Command::new("echo")
+                .arg(own)
+                .output()
+                .unwrap();
+
+            assert_eq!(String::from_utf8_lossy(&output.stdout), format!("{own}\n"));
+
+            let output = std::process::Command::new("echo")
+                .arg(other)
+                .output()
+                .unwrap();
+
+            assert!(!output.status.success(), "{test_name} matched {other}");
+        }
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    #[serial_test::serial]
+    fn pipeline_recorded_as_one_invocation() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixture = dir.path().join("pipeline.json");
+        let script = "echo hi | tr a-z A-Z";
+
+        for mode in [Mode::Record, Mode::Replay] {
+            let commandeer = Commandeer::new(&fixture, mode);
+            commandeer.mock_pipeline();
+
+            let output = std::process::Command::new("sh")
+                .args(["-c", script])
+                .output()
+                .unwrap();
+
+            assert_eq!(String::from_utf8_lossy(&output.stdout), "HI\n", "{mode}");
+        }
+
+        let recordings = crate::load_recordings_sync(&fixture).unwrap();
+
+        assert_eq!(recordings.commands.len(), 1);
+        assert_eq!(
+            recordings
+                .find_invocation("sh", &["-c".to_string(), script.to_string()])
+                .unwrap()
+                .stdout,
+            b"HI\n"
+        );
+    }
+
+    #[cfg(feature = "sync")]
+    #[cfg(unix)]
+    #[test]
+    #[serial_test::serial]
+    fn env_dispatches_to_mocked_command() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixture = dir.path().join("env.json");
+        let commandeer = Commandeer::builder(&fixture, Mode::Record)
+            .capture_env(["X"])
+            .build();
+        commandeer.mock_command("git");
+        commandeer.mock_env();
+
+        for args in [
+            ["X=1", "git", "--version"].as_slice(),
+            &["-i", "X=2", "git", "--version"],
+        ] {
+            let status = std::process::Command::new("env")
+                .args(args)
+                .status()
+                .unwrap();
+
+            assert!(status.success(), "{args:?}");
+        }
+
+        let recordings = crate::load_recordings_sync(&fixture).unwrap();
+        let values: Vec<_> = recordings
+            .invocations("git", &["--version".to_string()])
+            .iter()
+            .map(|invocation| invocation.env["X"].clone())
+            .collect();
+
+        assert_eq!(values, ["1", "2"]);
+        assert!(
+            commandeer
+                .calls()
+                .iter()
+                .all(|call| call.binary_name == "git")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[serial_test::serial]
+    fn prebuilt_binary_skips_cargo_build() {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let stub = dir.path().join("commandeer-stub");
+
+        std::fs::write(&stub, "#!/bin/sh\necho stub\n").unwrap();
+        std::fs::set_permissions(&stub, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        // A bogus cargo makes any attempted build fail.
+        let cargo = std::env::var_os("CARGO");
+
+        unsafe {
+            std::env::set_var(crate::COMMANDEER_BIN, &stub);
+            std::env::set_var("CARGO", dir.path().join("no-such-cargo"));
+        }
+
+        let commandeer = Commandeer::try_new("cmds_prebuilt_binary.json", Mode::Replay);
+
+        unsafe {
+            std::env::remove_var(crate::COMMANDEER_BIN);
+
+            match cargo {
+                Some(cargo) => std::env::set_var("CARGO", cargo),
+                None => std::env::remove_var("CARGO"),
+            }
+        }
+
+        let commandeer = commandeer.unwrap();
+        commandeer.mock_command("echo");
+
+        let output = std::process::Command::new("echo")
+            .arg("hello")
+            .output()
+            .unwrap();
+
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "stub\n");
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn assert_exit_in_accepts_range() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let commandeer = Commandeer::new(dir.path().join("exit_codes.json"), Mode::Record);
+        commandeer.mock_command("false");
+        commandeer.mock_command("true");
+
+        std::process::Command::new("false").status().unwrap();
+        std::process::Command::new("true").status().unwrap();
+
+        commandeer.assert_exit_in("false", &[], 1..=255);
+        commandeer.assert_exit_in("false", &[], |code| code != 0);
+        commandeer.assert_exit_code("true", &[], 0);
+
+        let result = std::panic::catch_unwind(|| commandeer.assert_exit_in("true", &[], 1..=255));
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn assert_all_calls_include_requires_flag() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let commandeer = Commandeer::new(dir.path().join("required_flag.json"), Mode::Record);
+        commandeer.mock_command("echo");
+
+        for args in [
+            &["--fail", "a"][..],
+            &["--fail=yes", "b"],
+            &["c", "--", "--fail"],
+        ] {
+            std::process::Command::new("echo")
+                .args(args)
+                .output()
+                .unwrap();
+        }
+
+        commandeer.assert_all_calls_include("ls", "--fail");
+
+        let result =
+            std::panic::catch_unwind(|| commandeer.assert_all_calls_include("echo", "--fail"));
+        let message = result.unwrap_err();
+        let message = message.downcast_ref::<String>().unwrap();
+
+        assert!(message.ends_with("\n  echo c -- --fail"), "{message}");
+    }
+
+    #[test]
+    fn ansi_output_listing() {
+        let mut recordings = crate::RecordedCommands::default();
+        recordings.add_invocation(crate::CommandInvocation {
+            binary_name: "ls".to_string(),
+            args: vec!["--color".to_string()],
+            stdout: b"\x1b[1;34mdir\x1b[0m\n".to_vec(),
+            stderr: vec![],
+            exit_code: 0,
+            ..Default::default()
+        });
+
+        insta::assert_snapshot!(crate::format_recordings_with(&recordings, &crate::ListOptions { ansi: crate::AnsiDisplay::Strip, ..Default::default() }), @r#"
+        ls:--color
+          [0] exit 0
+            stdout: "dir\n"
+            stderr: ""
+        "#);
+
+        assert_eq!(
+            crate::format_recordings_with(
+                &recordings,
+                &crate::ListOptions {
+                    ansi: crate::AnsiDisplay::Raw,
+                    ..Default::default()
+                }
+            ),
+            "ls:--color\n  [0] exit 0\n    stdout:\n\u{1b}[1;34mdir\u{1b}[0m\n    stderr:\n"
+        );
+    }
+
+    #[commandeer(
+        Replay,
+        "echo",
+        capture_env = ["TZ"],
+        env_match = crate::EnvMatch::Subset,
+        warn_unused = true,
+        normalize_temp_paths = true
+    )]
+    #[test]
+    #[serial_test::serial]
+    fn macro_config() {
+        let echo = |tz: &str| {
+            std::process::Command::new("echo")
+                .arg("config")
+                .env("TZ", tz)
+                .output()
+                .unwrap()
+        };
+
+        let output = echo("UTC");
+
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "configured\n");
+        assert_eq!(echo("Europe/London").status.code(), Some(1));
+    }
+
+    #[commandeer(Golden, "echo")]
+    #[test]
+    #[serial_test::serial]
+    fn golden() {
+        assert!(commandeer.fixture().ends_with("cmds_golden.json"));
+    }
+
+    const MODE_EXPRESSION: Mode = Mode::Replay;
+
+    #[commandeer(mode = MODE_EXPRESSION, "echo")]
+    #[test]
+    #[serial_test::serial]
+    fn mode_expression() {
+        let output = std::process::Command::new("echo")
+            .arg("expression")
+            .output()
+            .unwrap();
+
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "replayed\n");
+    }
+
+    #[cfg(all(windows, feature = "sync"))]
+    #[test]
+    fn windows_crash_code_round_trips() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let file = dir.path().join("recordings.json");
+        let access_violation = 0xC000_0005_u32.cast_signed();
+        let args = vec![
+            "/c".to_string(),
+            "exit".to_string(),
+            access_violation.to_string(),
+        ];
+
+        let recorded =
+            crate::record_command_sync(false, file.clone(), "cmd".to_string(), args.clone())
+                .unwrap();
+
+        assert_eq!(recorded.exit_code, access_violation);
+
+        let replayed = crate::replay_command_sync(file.clone(), "cmd".to_string(), args)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(replayed.exit_code, access_violation);
+        assert!(
+            crate::format_recordings(&crate::load_recordings_sync(&file).unwrap())
+                .contains("(STATUS_ACCESS_VIOLATION)")
+        );
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    #[serial_test::serial]
+    fn temp_path_args_match_across_runs() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixture = dir.path().join("temp_paths.json");
+        let mut recorded = None;
+        let mut recorded_out = None;
+
+        for mode in [Mode::Record, Mode::Replay] {
+            let run_dir = tempfile::TempDir::new().unwrap();
+            let out = run_dir.path().join("out").display().to_string();
+            recorded_out.get_or_insert_with(|| out.clone());
+
+            let commandeer = Commandeer::builder(&fixture, mode)
+                .normalize_temp_paths(true)
+                .build();
+            commandeer.mock_command("echo");
+
+            let output = std::process::Command::new("echo")
+                .arg(&out)
+                .output()
+                .unwrap();
+
+            assert!(output.status.success(), "{mode}");
+
+            // Replay serves the recorded output, which names the record run's directory.
+            let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+            assert_eq!(
+                stdout,
+                recorded.get_or_insert_with(|| format!("{out}\n")).as_str()
+            );
+        }
+
+        let recordings = crate::load_recordings_sync(&fixture).unwrap();
+        let invocation = recordings
+            .find_invocation("echo", &["<TMP>/out".to_string()])
+            .unwrap();
+        let recorded_out = recorded_out.unwrap();
+
+        // Re-running the recording uses the path it was made with, not the token it is keyed by.
+        assert_eq!(
+            invocation.original_args(),
+            std::slice::from_ref(&recorded_out)
+        );
+        assert!(crate::export_script(&recordings, &fixture).contains(&recorded_out));
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    #[serial_test::serial]
+    fn env_mismatch_misses_under_exact() {
+        const VAR: &str = "COMMANDEER_ENV_MATCH_TEST";
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixture = dir.path().join("env_match.json");
+
+        let run = |mode, env_match, value: &str| {
+            unsafe {
+                std::env::set_var(VAR, value);
+            }
+
+            let commandeer = Commandeer::builder(&fixture, mode)
+                .capture_env([VAR])
+                .env_match(env_match)
+                .build();
+            commandeer.mock_command("echo");
+
+            let output = std::process::Command::new("echo")
+                .arg("env")
+                .output()
+                .unwrap();
+
+            unsafe {
+                std::env::remove_var(VAR);
+            }
+
+            output.status.success()
+        };
+
+        assert!(run(Mode::Record, crate::EnvMatch::Ignore, "recorded"));
+        assert!(run(Mode::Replay, crate::EnvMatch::Exact, "recorded"));
+        assert!(!run(Mode::Replay, crate::EnvMatch::Exact, "changed"));
+        assert!(run(Mode::Replay, crate::EnvMatch::Ignore, "changed"));
+
+        let recordings = crate::load_recordings_sync(&fixture).unwrap();
+        let invocation = recordings
+            .find_invocation("echo", &["env".to_string()])
+            .unwrap();
+
+        assert_eq!(
+            invocation.env.get(VAR).map(String::as_str),
+            Some("recorded")
+        );
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn transforms_apply_before_saving() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let file = dir.path().join("recordings.json");
+
+        let options = crate::RecordOptions {
+            transforms: vec![
+                crate::BuiltinTransform::MaskTimestamps.into(),
+                crate::BuiltinTransform::TrimTrailingWhitespace.into(),
+                crate::Transform::Custom(|invocation| {
+                    invocation.stdout.splice(0..0, *b"log: ");
+                }),
+            ],
+            ..crate::RecordOptions::default()
+        };
+
+        let args = vec!["started 2024-05-01T12:34:56.789Z, done at 12:35:02   ".to_string()];
+
+        crate::record_command_sync_with(&options, file.clone(), "echo".to_string(), args.clone())
+            .unwrap();
+
+        let recordings = crate::load_recordings_sync(&file).unwrap();
+
+        assert_eq!(
+            recordings.find_invocation("echo", &args).unwrap().stdout,
+            b"log: started <TIMESTAMP>, done at <TIMESTAMP>\n"
+        );
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    #[serial_test::serial]
+    fn concurrent_output_delivers_both_streams() {
+        use std::io::Read as _;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixture = dir.path().join("concurrent.json");
+
+        // Larger than a pipe buffer, so writing all of stdout before stderr would deadlock the reader below.
+        let stdout = b"o".repeat(256 * 1024);
+        let stderr = b"e".repeat(256 * 1024);
+
+        let mut recordings = crate::RecordedCommands::default();
+        recordings.add_invocation(crate::CommandInvocation {
+            binary_name: "noisy".to_string(),
+            args: vec![],
+            stdout: stdout.clone(),
+            stderr: stderr.clone(),
+            exit_code: 0,
+            ..Default::default()
+        });
+        crate::save_recordings_sync(&fixture, &recordings).unwrap();
+
+        let commandeer = Commandeer::builder(&fixture, Mode::Replay)
+            .concurrent_output(true)
+            .build();
+        commandeer.mock_command("noisy");
+
+        let mut child = std::process::Command::new("noisy")
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        // Stderr stays open until the mock exits, so read exactly its length rather than to EOF.
+        let mut replayed_stderr = vec![0; stderr.len()];
+        child
+            .stderr
+            .take()
+            .unwrap()
+            .read_exact(&mut replayed_stderr)
+            .unwrap();
+
+        let mut replayed_stdout = vec![];
+        child
+            .stdout
+            .take()
+            .unwrap()
+            .read_to_end(&mut replayed_stdout)
+            .unwrap();
+
+        assert!(child.wait().unwrap().success());
+        assert!(replayed_stderr == stderr);
+        assert!(replayed_stdout == stdout);
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    #[serial_test::serial]
+    fn stream_chunks_replays_whole_output() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixture = dir.path().join("chunked.json");
+        let stdout = "progress 10%\nprogress 50%\nprogress 100%\n";
+
+        let mut recordings = crate::RecordedCommands::default();
+        recordings.add_invocation(crate::CommandInvocation {
+            binary_name: "progress".to_string(),
+            args: vec![],
+            stdout: stdout.into(),
+            stderr: b"done\n".to_vec(),
+            exit_code: 0,
+            ..Default::default()
+        });
+        crate::save_recordings_sync(&fixture, &recordings).unwrap();
+
+        let commandeer = Commandeer::builder(&fixture, Mode::Replay)
+            .stream_chunks(5, std::time::Duration::from_millis(2))
+            .build();
+        commandeer.mock_command("progress");
+
+        let output = std::process::Command::new("progress").output().unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), stdout);
+        assert_eq!(String::from_utf8_lossy(&output.stderr), "done\n");
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn stdin_is_captured_while_recording() {
+        use std::io::Write as _;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let commandeer = Commandeer::builder(dir.path().join("stdin.json"), Mode::Record)
+            .capture_stdin(true)
+            .build();
+        commandeer.mock_command("cat");
+
+        let mut child = std::process::Command::new("cat")
+            .arg("-")
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        child.stdin.take().unwrap().write_all(b"piped\n").unwrap();
+
+        let output = child.wait_with_output().unwrap();
+
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "piped\n");
+        assert_eq!(
+            commandeer.recorded_stdin("cat", &["-"]).as_deref(),
+            Some(b"piped\n".as_slice())
+        );
+        assert_eq!(commandeer.recorded_stdin("cat", &[]), None);
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    #[serial_test::serial]
+    fn extra_record_args_are_not_keyed() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixture = dir.path().join("extra_args.json");
+        let repo = dir.path().join("repo");
+        std::fs::create_dir(&repo).unwrap();
+
+        let git = |args: &[&str]| {
+            std::process::Command::new("git")
+                .args(["-c", "user.name=a", "-c", "user.email=a@example.com"])
+                .args(args)
+                .current_dir(&repo)
+                .output()
+                .unwrap()
+        };
+
+        git(&["init", "-q"]);
+        git(&["commit", "-q", "--allow-empty", "-m", "first"]);
+
+        let commandeer = Commandeer::builder(&fixture, Mode::Record)
+            .extra_record_args("git", &["--no-pager"])
+            .extra_record_args("echo", &["injected"])
+            .build();
+        commandeer.mock_command("git");
+        commandeer.mock_command("echo");
+
+        let output = std::process::Command::new("echo")
+            .arg("original")
+            .output()
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "injected original\n"
+        );
+
+        let output = git(&["log", "--format=%s"]);
+
+        assert!(output.status.success(), "{output:?}");
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "first\n");
+
+        let recordings = crate::load_recordings_sync(&fixture).unwrap();
+        let args = [
+            "-c",
+            "user.name=a",
+            "-c",
+            "user.email=a@example.com",
+            "log",
+            "--format=%s",
+        ]
+        .map(String::from);
+        let invocation = recordings.find_invocation("git", &args).unwrap();
+
+        assert!(!invocation.args.contains(&"--no-pager".to_string()));
+        assert_eq!(invocation.stdout, b"first\n");
+        assert!(
+            recordings
+                .find_invocation("echo", &["original".to_string()])
+                .is_some()
+        );
+    }
+
+    #[cfg(feature = "sync")]
+    #[cfg(unix)]
+    #[test]
+    #[serial_test::serial]
+    fn rusage_is_recorded() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixture = dir.path().join("rusage.json");
+        let commandeer = Commandeer::builder(&fixture, Mode::Record)
+            .record_rusage(true)
+            .build();
+        commandeer.mock_command("sh");
+
+        let script = "i=0; while [ $i -lt 100000 ]; do i=$((i + 1)); done";
+        let status = std::process::Command::new("sh")
+            .args(["-c", script])
+            .status()
+            .unwrap();
+
+        assert!(status.success());
+
+        let recordings = crate::load_recordings_sync(&fixture).unwrap();
+        let invocation = recordings
+            .find_invocation("sh", &["-c".to_string(), script.to_string()])
+            .unwrap();
+        let rusage = invocation.rusage.unwrap();
+
+        assert!(rusage.user_ms + rusage.system_ms > 0, "{rusage:?}");
+        assert!(rusage.max_rss_kib > 0, "{rusage:?}");
+
+        let verbose = crate::ListOptions {
+            verbose: true,
+            ..Default::default()
+        };
+
+        assert!(
+            crate::format_recordings_with(&recordings, &verbose)
+                .contains(&format!("    rusage: {rusage}\n"))
+        );
+        assert!(!crate::format_recordings(&recordings).contains("rusage"));
+    }
+
+    #[cfg(feature = "sync")]
     #[test]
     #[serial_test::serial]
-    fn test_flag_args() {
-        let output = std::process::Command::new("git")
-            .arg("--version")
+    fn no_args_and_one_empty_arg_are_distinct_invocations() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixture = dir.path().join("empty_args.json");
+        // `printf` fails without a format, and prints nothing given an empty one.
+        let run = |args: &[&str]| {
+            let output = std::process::Command::new("printf")
+                .args(args)
+                .output()
+                .unwrap();
+
+            (output.status.code(), output.stderr.is_empty())
+        };
+
+        let recorded = {
+            let commandeer = Commandeer::new(&fixture, Mode::Record);
+            commandeer.mock_command("printf");
+
+            (run(&[]), run(&[""]))
+        };
+
+        assert_eq!(recorded, ((Some(1), false), (Some(0), true)));
+
+        let recordings = crate::load_recordings_sync(&fixture).unwrap();
+        let mut keys: Vec<_> = recordings.commands.keys().map(String::as_str).collect();
+        keys.sort();
+
+        assert_eq!(keys, ["printf:", r"printf:\e"]);
+        assert!(recordings.commands["printf:"][0].args.is_empty());
+        assert_eq!(recordings.commands[r"printf:\e"][0].args, [""]);
+
+        let commandeer = Commandeer::new(&fixture, Mode::Replay);
+        commandeer.mock_command("printf");
+
+        assert_eq!((run(&[]), run(&[""])), recorded);
+    }
+
+    #[cfg(feature = "sync")]
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[serial_test::serial]
+    fn replay_picks_recording_of_active_locale() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixture = dir.path().join("locales.json");
+        let args = ["-u", "-d", "@0", "+%A"];
+        let date = |locale: &str| {
+            let output = std::process::Command::new("date")
+                .args(args)
+                .env("LC_ALL", locale)
+                .output()
+                .unwrap();
+
+            output
+                .status
+                .success()
+                .then(|| String::from_utf8_lossy(&output.stdout).to_string())
+        };
+
+        {
+            let commandeer = Commandeer::builder(&fixture, Mode::Record)
+                .locales(["C", "de_DE.UTF-8"])
+                .build();
+            commandeer.mock_command("date");
+
+            assert_eq!(date("C").as_deref(), Some("Thursday\n"));
+        }
+
+        let mut recordings = crate::load_recordings_sync(&fixture).unwrap();
+        let key = crate::recording_key("date", &args);
+        let invocations = recordings.commands.get_mut(&key).unwrap();
+        let locales: Vec<_> = invocations
+            .iter()
+            .map(|invocation| invocation.env[crate::LOCALE_VAR].as_str())
+            .collect();
+
+        assert_eq!(locales, ["C", "de_DE.UTF-8"]);
+        assert_eq!(invocations[0].stdout, b"Thursday\n");
+
+        // Without the German locale installed `date` falls back to C, so mark its recording apart.
+        if invocations[1].stdout == invocations[0].stdout {
+            invocations[1].stdout = b"Donnerstag\n".to_vec();
+        }
+
+        let german = String::from_utf8(invocations[1].stdout.clone()).unwrap();
+        crate::save_recordings_sync(&fixture, &recordings).unwrap();
+
+        let commandeer = Commandeer::builder(&fixture, Mode::Replay)
+            .locales(["C", "de_DE.UTF-8"])
+            .build();
+        commandeer.mock_command("date");
+
+        assert_eq!(date("C").as_deref(), Some("Thursday\n"));
+        assert_eq!(date("de_DE.UTF-8"), Some(german));
+        assert_eq!(date("fr_FR.UTF-8"), None);
+    }
+
+    #[cfg(feature = "sync")]
+    #[cfg(unix)]
+    #[test]
+    #[serial_test::serial]
+    fn mocks_work_under_temp_dir_with_space() {
+        let root = tempfile::TempDir::new().unwrap();
+        let spaced = root.path().join("with space");
+        let fixture = spaced.join("fixture dir/echo.json");
+        std::fs::create_dir(&spaced).unwrap();
+
+        let tmpdir = std::env::var_os("TMPDIR");
+
+        unsafe {
+            std::env::set_var("TMPDIR", &spaced);
+        }
+
+        let echo = || {
+            let output = std::process::Command::new("echo")
+                .arg("hello world")
+                .output()
+                .unwrap();
+
+            (output.status.success(), output.stdout)
+        };
+
+        for mode in [Mode::Record, Mode::Replay] {
+            let commandeer = Commandeer::builder(&fixture, mode)
+                .test_name("spaced test")
+                .build();
+            let wrapper = commandeer.try_mock_command("echo").unwrap();
+
+            assert!(wrapper.starts_with(&spaced), "{}", wrapper.display());
+            assert_eq!(echo(), (true, b"hello world\n".to_vec()), "{mode:?}");
+        }
+
+        unsafe {
+            match tmpdir {
+                Some(tmpdir) => std::env::set_var("TMPDIR", tmpdir),
+                None => std::env::remove_var("TMPDIR"),
+            }
+        }
+
+        let recordings = crate::load_recordings_sync(&fixture).unwrap();
+
+        assert!(
+            recordings
+                .for_test("spaced test")
+                .and_then(|scoped| scoped.find_invocation("echo", &["hello world".to_string()]))
+                .is_some()
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[serial_test::serial]
+    fn passthrough_runs_for_real_alongside_replayed_mocks() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixture = dir.path().join("passthrough.json");
+        let recorded = r#"{"commands":{"git:status":[{"binary_name":"git","args":["status"],"stdout":"On branch recorded\n","stderr":"","exit_code":0}]}}"#;
+        std::fs::write(&fixture, recorded).unwrap();
+
+        let commandeer = Commandeer::builder(&fixture, Mode::Replay)
+            .passthrough(["date"])
+            .build();
+        commandeer.mock_command("git");
+        let date = commandeer.mock_command("date");
+
+        let git = std::process::Command::new("git")
+            .arg("status")
+            .output()
+            .unwrap();
+        let epoch = std::process::Command::new("date")
+            .args(["-u", "-d", "@0", "+%Y"])
             .output()
             .unwrap();
 
-        insta::assert_debug_snapshot!(output, @r#"
-        Output {
-            status: ExitStatus(
-                unix_wait_status(
-                    0,
-                ),
-            ),
-            stdout: "git version 2.51.0\n",
-            stderr: "",
+        assert!(date.starts_with(commandeer.temp_path()));
+        assert_eq!(String::from_utf8_lossy(&git.stdout), "On branch recorded\n");
+        assert_eq!(String::from_utf8_lossy(&epoch.stdout), "1970\n");
+        assert!(epoch.status.success());
+
+        let calls: Vec<_> = commandeer
+            .calls()
+            .into_iter()
+            .map(|call| call.binary_name)
+            .collect();
+
+        assert_eq!(calls, ["git"]);
+        assert_eq!(std::fs::read_to_string(&fixture).unwrap(), recorded);
+    }
+
+    #[cfg(feature = "sync")]
+    #[cfg(unix)]
+    #[test]
+    #[serial_test::serial]
+    fn fd_count_is_recorded() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixture = dir.path().join("fd_count.json");
+        let commandeer = Commandeer::builder(&fixture, Mode::Record)
+            .record_fd_count(true)
+            .build();
+        commandeer.mock_command("sh");
+
+        let status = std::process::Command::new("sh")
+            .args(["-c", "exit 0"])
+            .status()
+            .unwrap();
+
+        assert!(status.success());
+
+        let recordings = crate::load_recordings_sync(&fixture).unwrap();
+        let invocation = recordings
+            .find_invocation("sh", &["-c".to_string(), "exit 0".to_string()])
+            .unwrap();
+        // At least stdin, stdout and stderr.
+        let fd_count = invocation.fd_count.unwrap();
+
+        assert!(fd_count >= 3, "{fd_count}");
+
+        let verbose = crate::ListOptions {
+            verbose: true,
+            ..Default::default()
+        };
+
+        assert!(
+            crate::format_recordings_with(&recordings, &verbose)
+                .contains(&format!("    fds: {fd_count}\n"))
+        );
+    }
+
+    #[cfg(feature = "sync")]
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[serial_test::serial]
+    fn executable_follows_exec_chain() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixture = dir.path().join("executable.json");
+        let commandeer = Commandeer::builder(&fixture, Mode::Record)
+            .record_executable(true)
+            .build();
+        commandeer.mock_command("sh");
+
+        let status = std::process::Command::new("sh")
+            .args(["-c", "exec sleep 0.3"])
+            .status()
+            .unwrap();
+
+        assert!(status.success());
+
+        let recordings = crate::load_recordings_sync(&fixture).unwrap();
+        let invocation = recordings
+            .find_invocation("sh", &["-c".to_string(), "exec sleep 0.3".to_string()])
+            .unwrap();
+        let executable = invocation.executable.as_ref().unwrap();
+
+        assert_eq!(invocation.binary_name, "sh");
+        assert_eq!(executable.file_name().unwrap(), "sleep");
+        assert!(
+            crate::format_recordings(&recordings)
+                .contains(&format!("    executable: {}\n", executable.display()))
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[serial_test::serial]
+    fn json_args_match_regardless_of_key_order() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixture = dir.path().join("canonical_json.json");
+        let echo = |arg: &str| {
+            std::process::Command::new("echo")
+                .args(["-n", arg])
+                .output()
+                .unwrap()
+        };
+
+        let recorded = {
+            let commandeer = Commandeer::builder(&fixture, Mode::Record)
+                .canonical_json(true)
+                .build();
+            commandeer.mock_command("echo");
+
+            echo(r#"{"a":1,"b":2}"#)
+        };
+
+        assert_eq!(
+            String::from_utf8_lossy(&recorded.stdout),
+            r#"{"a":1,"b":2}"#
+        );
+
+        let commandeer = Commandeer::builder(&fixture, Mode::Replay)
+            .canonical_json(true)
+            .build();
+        commandeer.mock_command("echo");
+
+        let replayed = echo(r#"{"b":2,"a":1}"#);
+
+        assert!(replayed.status.success(), "{replayed:?}");
+        assert_eq!(replayed.stdout, recorded.stdout);
+        assert!(!echo(r#"{"a":1,"b":3}"#).status.success());
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    #[serial_test::serial]
+    fn response_file_args_match_on_replay() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixture = dir.path().join("response_files.json");
+        let write_response_file = |name: &str, inputs: &str| {
+            let run = dir.path().join(name);
+            std::fs::create_dir_all(&run).unwrap();
+            std::fs::write(run.join("inputs.rsp"), inputs).unwrap();
+            std::fs::write(
+                run.join("args.rsp"),
+                format!("-n 'hello world' @{}", run.join("inputs.rsp").display()),
+            )
+            .unwrap();
+
+            format!("@{}", run.join("args.rsp").display())
+        };
+        let echo = |arg: &str| {
+            std::process::Command::new("echo")
+                .arg(arg)
+                .output()
+                .unwrap()
+        };
+
+        let response_file = write_response_file("first", "a.c b.c");
+        let recorded = {
+            let commandeer = Commandeer::builder(&fixture, Mode::Record)
+                .response_files(true)
+                .build();
+            commandeer.mock_command("echo");
+
+            echo(&response_file)
+        };
+
+        assert!(recorded.status.success());
+
+        let commandeer = Commandeer::builder(&fixture, Mode::Replay)
+            .response_files(true)
+            .build();
+        commandeer.mock_command("echo");
+
+        let replayed = echo(&write_response_file("second", "a.c\nb.c\n"));
+
+        assert!(replayed.status.success(), "{replayed:?}");
+        assert_eq!(replayed.stdout, recorded.stdout);
+        assert!(
+            !echo(&write_response_file("third", "a.c c.c"))
+                .status
+                .success()
+        );
+
+        let recordings = crate::load_recordings_sync(&fixture).unwrap();
+        let invocation = recordings.commands.values().flatten().next().unwrap();
+
+        assert_eq!(invocation.args.len(), 1);
+        assert!(invocation.args[0].starts_with("@sha256:"));
+        // Only the key holds the hash; re-running the recording passes the response file again.
+        assert_eq!(invocation.original_args(), [response_file]);
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    #[serial_test::serial]
+    fn timing_stats_cover_every_recording() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixture = dir.path().join("timing.json");
+        let commandeer = Commandeer::builder(&fixture, Mode::Record)
+            .record_timing(true)
+            .build();
+        commandeer.mock_command("sleep");
+
+        for _ in 0..3 {
+            std::process::Command::new("sleep")
+                .arg("0.1")
+                .status()
+                .unwrap();
         }
-        "#);
 
-        let output = std::process::Command::new("date").output().unwrap();
+        let recordings = crate::load_recordings_sync(&fixture).unwrap();
+        let invocations = recordings.invocations("sleep", &["0.1".to_string()]);
+        let durations: Vec<u64> = invocations
+            .iter()
+            .map(|invocation| invocation.duration_ms.unwrap())
+            .collect();
+        let stats = crate::TimingStats::from_invocations(invocations).unwrap();
 
-        insta::assert_debug_snapshot!(output, @r#"
-        Output {
-            status: ExitStatus(
-                unix_wait_status(
-                    0,
-                ),
-            ),
-            stdout: "Thu Aug 21 14:54:45 EDT 2025\n",
-            stderr: "",
+        assert_eq!(stats.samples, 3);
+        assert!(stats.min_ms >= 100, "{stats}");
+        assert_eq!(stats.min_ms, *durations.iter().min().unwrap());
+        assert_eq!(stats.max_ms, *durations.iter().max().unwrap());
+        assert_eq!(stats.mean_ms, durations.iter().sum::<u64>() as f64 / 3.0);
+
+        let listing = crate::format_recordings_with(
+            &recordings,
+            &crate::ListOptions {
+                timing: true,
+                ..Default::default()
+            },
+        );
+
+        assert!(
+            listing.starts_with(&format!("sleep:0.1\n  timing: {stats}\n")),
+            "{listing}"
+        );
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn stdin_consumed_counts_bytes_read() {
+        use std::io::Write as _;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let commandeer = Commandeer::builder(dir.path().join("stdin_consumed.json"), Mode::Record)
+            .capture_stdin(true)
+            .build();
+        commandeer.mock_command("dd");
+
+        let args = ["bs=1", "count=5", "status=none"];
+        let mut child = std::process::Command::new("dd")
+            .args(args)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(b"0123456789")
+            .unwrap();
+
+        let output = child.wait_with_output().unwrap();
+
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "01234");
+
+        commandeer.assert_stdin_consumed("dd", &args, 5);
+
+        let result = std::panic::catch_unwind(|| commandeer.assert_stdin_consumed("dd", &args, 10));
+
+        assert!(result.is_err());
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    #[serial_test::serial]
+    fn crlf_is_recorded_as_lf() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixture = dir.path().join("crlf.json");
+        let commandeer = Commandeer::builder(&fixture, Mode::Record)
+            .transform(crate::BuiltinTransform::NormalizeLineEndings)
+            .build();
+        commandeer.mock_command("printf");
+
+        std::process::Command::new("printf")
+            .arg("a\\r\\nb\\r\\n")
+            .output()
+            .unwrap();
+
+        let recordings = crate::load_recordings_sync(&fixture).unwrap();
+
+        assert_eq!(
+            recordings
+                .find_invocation("printf", &["a\\r\\nb\\r\\n".to_string()])
+                .unwrap()
+                .stdout,
+            b"a\nb\n"
+        );
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    #[serial_test::serial]
+    fn home_is_masked_and_expanded() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixture = dir.path().join("home.json");
+
+        let run = |mode, home: &str| {
+            let commandeer = Commandeer::builder(&fixture, mode)
+                .transform(crate::BuiltinTransform::MaskHome)
+                .expand_home(mode == Mode::Replay)
+                .build();
+            commandeer.mock_command("sh");
+
+            let output = std::process::Command::new("sh")
+                .args(["-c", "echo $HOME/.config ${HOME}x"])
+                .env("HOME", home)
+                .output()
+                .unwrap();
+
+            String::from_utf8_lossy(&output.stdout).into_owned()
+        };
+
+        run(Mode::Record, "/home/alice");
+
+        let recordings = crate::load_recordings_sync(&fixture).unwrap();
+        let args = ["-c".to_string(), "echo $HOME/.config ${HOME}x".to_string()];
+
+        assert_eq!(
+            recordings.find_invocation("sh", &args).unwrap().stdout,
+            b"<HOME>/.config /home/alicex\n"
+        );
+        assert_eq!(
+            run(Mode::Replay, "/home/bob"),
+            "/home/bob/.config /home/alicex\n"
+        );
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn stdout_template_rendered_at_replay() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let file = dir.path().join("recordings.json");
+
+        std::fs::write(
+            dir.path().join("greeting.tmpl"),
+            "hello $ARG1, that costs $$5\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &file,
+            r#"{
+  "commands": {
+    "greet:alpha": [
+      {
+        "binary_name": "greet",
+        "args": ["alpha"],
+        "stdout": "ignored in favour of the template\n",
+        "stderr": "",
+        "exit_code": 0,
+        "stdout_template": "greeting.tmpl"
+      }
+    ]
+  }
+}"#,
+        )
+        .unwrap();
+
+        let invocation =
+            crate::replay_command_sync(file, "greet".to_string(), vec!["alpha".to_string()])
+                .unwrap()
+                .unwrap();
+
+        assert_eq!(invocation.stdout, b"hello alpha, that costs $5\n");
+    }
+
+    #[cfg(feature = "sync")]
+    #[cfg(unix)]
+    #[test]
+    #[serial_test::serial]
+    fn recorded_command_sees_caller_environment() {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let tools = dir.path().join("tools");
+        let tool = tools.join("caller-tool");
+
+        std::fs::create_dir(&tools).unwrap();
+        std::fs::write(&tool, "#!/bin/sh\necho \"tool sees $CALLER_VAR\"\n").unwrap();
+        std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let commandeer = Commandeer::builder(dir.path().join("caller_env.json"), Mode::Record)
+            .capture_env(["CALLER_VAR"])
+            .build();
+        commandeer.mock_command("sh");
+
+        let path = format!("{}:{}", tools.display(), std::env::var("PATH").unwrap());
+        let output = std::process::Command::new("sh")
+            .args(["-c", "caller-tool"])
+            .env("CALLER_VAR", "custom")
+            .env("PATH", path)
+            .output()
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "tool sees custom\n"
+        );
+
+        let recordings = crate::load_recordings_sync(&dir.path().join("caller_env.json")).unwrap();
+        let invocation = recordings
+            .find_invocation("sh", &["-c".to_string(), "caller-tool".to_string()])
+            .unwrap();
+
+        assert_eq!(
+            invocation.env.get("CALLER_VAR").map(String::as_str),
+            Some("custom")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[serial_test::serial]
+    fn strict_path_mock_wins_over_earlier_real_command() {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let tools = dir.path().join("tools");
+        let tool = tools.join("strict-tool");
+        let fixture = dir.path().join("strict_path.json");
+
+        std::fs::create_dir(&tools).unwrap();
+        std::fs::write(&tool, "#!/bin/sh\necho real\n").unwrap();
+        std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+        std::fs::write(
+            &fixture,
+            r#"{"commands":{"strict-tool:":[{"binary_name":"strict-tool","args":[],"stdout":"mocked\n","stderr":"","exit_code":0}]}}"#,
+        )
+        .unwrap();
+
+        let original = std::env::var("PATH").unwrap();
+        let path = format!("{}:{original}", tools.display());
+
+        unsafe {
+            std::env::set_var("PATH", &path);
+        }
+
+        let commandeer = Commandeer::builder(&fixture, Mode::Replay)
+            .strict_path(true)
+            .build();
+        commandeer.mock_command("strict-tool");
+
+        let output = std::process::Command::new("strict-tool").output().unwrap();
+
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "mocked\n");
+        assert_eq!(
+            std::env::var("PATH").unwrap(),
+            format!(
+                "{}:{}",
+                commandeer.temp_path().display(),
+                crate::STRICT_PATH_DIRS.join(":")
+            )
+        );
+
+        drop(commandeer);
+
+        assert_eq!(std::env::var("PATH").unwrap(), path);
+
+        unsafe {
+            std::env::set_var("PATH", original);
+        }
+    }
+
+    #[commandeer(Replay, "kubectl")]
+    #[test]
+    #[serial_test::serial]
+    fn assert_stdout_json() {
+        let args = ["get", "pod", "web", "-o", "json"];
+
+        let output = std::process::Command::new("kubectl")
+            .args(args)
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+
+        commandeer.assert_stdout_json("kubectl", &args, "/status/phase", "Running");
+        commandeer.assert_stdout_json(
+            "kubectl",
+            &args,
+            "/status/containerStatuses/0/restartCount",
+            2,
+        );
+
+        let result = std::panic::catch_unwind(|| {
+            commandeer.assert_stdout_json("kubectl", &["version"], "/clientVersion", "v1.30.0")
+        });
+        let message = result.unwrap_err();
+
+        assert!(
+            message
+                .downcast_ref::<String>()
+                .unwrap()
+                .contains("stdout of `kubectl version` is not valid JSON")
+        );
+    }
+
+    #[commandeer(Replay, "ls")]
+    #[test]
+    #[serial_test::serial]
+    fn assert_stdout_lines_eq() {
+        let output = std::process::Command::new("ls").output().unwrap();
+
+        assert!(output.status.success());
+
+        commandeer.assert_stdout_lines_eq("ls", &[], &["a.txt", "c.txt", "b.txt"]);
+
+        let result = std::panic::catch_unwind(|| {
+            commandeer.assert_stdout_lines_eq("ls", &[], &["a.txt", "b.txt", "d.txt"])
+        });
+        let message = result.unwrap_err();
+        let message = message.downcast_ref::<String>().unwrap();
+
+        assert!(message.contains("missing: [\"d.txt\"]"), "{message}");
+        assert!(message.contains("unexpected: [\"c.txt\"]"), "{message}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[serial_test::serial]
+    fn source_date_epoch_makes_recording_reproducible() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixture = dir.path().join("source_date_epoch.json");
+        let script = r#"echo "built at ${SOURCE_DATE_EPOCH:-$(date +%s%N)}""#;
+
+        for _ in 0..2 {
+            let commandeer = Commandeer::builder(&fixture, Mode::Record)
+                .source_date_epoch(1_700_000_000)
+                .build();
+            commandeer.mock_command("sh");
+
+            let output = std::process::Command::new("sh")
+                .args(["-c", script])
+                .output()
+                .unwrap();
+
+            assert_eq!(
+                String::from_utf8_lossy(&output.stdout),
+                "built at 1700000000\n"
+            );
         }
-        "#);
     }
 }
@@ -0,0 +1,44 @@
+//! Recording a command under several locales, see [`crate::RecordOptions::locales`], and replaying the recording
+//! made under the active one, see [`crate::MatchOptions::match_locale`].
+
+use crate::CommandInvocation;
+
+/// Environment variable a command is run with to record it under a locale, stored in its
+/// [`CommandInvocation::env`]. It overrides every other locale variable.
+pub const LOCALE_VAR: &str = "LC_ALL";
+
+/// The locale this process runs in: `LC_ALL`, else `LANG`, else `C`. The per-category `LC_*` variables aren't
+/// considered.
+pub fn active_locale() -> String {
+    [LOCALE_VAR, "LANG"]
+        .into_iter()
+        .find_map(|name| std::env::var(name).ok().filter(|value| !value.is_empty()))
+        .unwrap_or_else(|| "C".to_string())
+}
+
+/// Stores `locale`, which `invocation` was recorded under if set, in its env.
+pub(crate) fn tag(invocation: &mut CommandInvocation, locale: Option<&str>) {
+    if let Some(locale) = locale {
+        invocation
+            .env
+            .insert(LOCALE_VAR.to_string(), locale.to_string());
+    }
+}
+
+/// Whether `invocation` can replay under the [`active_locale`]: it was recorded under it, or under no locale.
+pub(crate) fn matches(invocation: &CommandInvocation) -> bool {
+    invocation
+        .env
+        .get(LOCALE_VAR)
+        .is_none_or(|locale| *locale == active_locale())
+}
+
+/// The one of `recorded`, a recording per locale, made under the [`active_locale`], or else the first.
+pub(crate) fn for_active(mut recorded: Vec<CommandInvocation>) -> CommandInvocation {
+    let index = recorded
+        .iter()
+        .position(|invocation| invocation.env.contains_key(LOCALE_VAR) && matches(invocation))
+        .unwrap_or(0);
+
+    recorded.swap_remove(index)
+}
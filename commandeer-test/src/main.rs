@@ -1,7 +1,23 @@
-use anyhow::Result;
-use clap::{Parser, Subcommand};
-use commandeer_test::{exit_with_code, output_invocation, record_command, replay_command};
-use std::path::PathBuf;
+use anyhow::{Context as _, Result, bail};
+use clap::{Args, Parser, Subcommand};
+use commandeer_test::{
+    AnsiDisplay, BuiltinTransform, COMMANDEER_ACTIVE_FIXTURE, COMMANDEER_ACTIVE_MODE,
+    COMMANDEER_SUMMARY, CallLogEntry, CommandInvocation, EnvMatch, FD_COUNT_DRIFT, ListOptions,
+    MatchOptions, NumericTolerance, Outcome, RecordOptions, RecordedCommands, SecretScan,
+    SignalReplay, Summary, SummaryEntry, append_call_log, append_summary, exit_as_recorded,
+    exit_with_code, export_script, format_recordings_with, load_recordings_sync,
+    load_replay_recordings_sync, open_fd_count, output_invocation_chunked,
+    output_invocation_concurrent, output_invocation_streaming, pending_path, read_summary,
+    record_command_sync_with, recording_key, refresh_recordings_sync, replay_command_sync_with,
+    save_recordings_sync,
+};
+use serde::Deserialize;
+use std::{
+    borrow::Cow,
+    path::PathBuf,
+    sync::LazyLock,
+    time::{Duration, SystemTime},
+};
 
 #[derive(Parser)]
 #[command(name = "commandeer")]
@@ -11,81 +27,936 @@ struct Cli {
     command: Commands,
 }
 
+/// Arguments shared by the subcommands a mock wrapper execs into.
+#[derive(Args)]
+struct MockArgs {
+    /// Path to the recordings.
+    #[arg(long, env = "COMMANDEER_FILE", default_value = "recordings.json")]
+    file: PathBuf,
+    /// Call log to append the invocation to.
+    #[arg(long)]
+    call_log: Option<PathBuf>,
+    /// Scope recordings to this test within a shared fixture.
+    #[arg(long)]
+    test_name: Option<String>,
+    /// Key recordings with paths inside the system temp dir replaced by a stable token.
+    #[arg(long)]
+    normalize_temp_paths: bool,
+    /// Key recordings with `@file` response file args replaced by a hash of the args they hold.
+    #[arg(long)]
+    response_files: bool,
+    /// Key recordings with args that are JSON objects or arrays in canonical form: keys sorted, no whitespace.
+    #[arg(long)]
+    canonical_json: bool,
+    /// Option whose attached value, as in `--opt=value` or `-ovalue`, is keyed as a separate arg; may be repeated.
+    #[arg(long = "split-option", value_name = "OPT", allow_hyphen_values = true)]
+    split_options: Vec<String>,
+    /// Key recordings with flags sorted, keeping each `--flag-value` option with its value.
+    #[arg(long)]
+    unordered_flags: bool,
+    /// Option taking the next arg as its value under `--unordered-flags`; may be repeated.
+    #[arg(long = "flag-value", value_name = "OPT", allow_hyphen_values = true)]
+    flag_values: Vec<String>,
+    /// Replace `<HOME>` in the output with the current `$HOME`.
+    #[arg(long)]
+    expand_home: bool,
+    /// Rewrite `\n` in the output as `\r\n` on Windows.
+    #[arg(long)]
+    native_line_endings: bool,
+    /// Write stdout and stderr concurrently from separate threads.
+    #[arg(long)]
+    concurrent_output: bool,
+    /// Write stdout this many bytes at a time, simulating a command that streams its output.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    chunk_size: Option<u64>,
+    /// Milliseconds to sleep between stdout chunks.
+    #[arg(long, requires = "chunk_size", default_value_t = 0)]
+    chunk_delay_ms: u64,
+    /// Print the command, mode and fixture the mock runs under to stderr.
+    #[arg(long)]
+    debug: bool,
+    /// Print a one-line summary of the invocation to stderr once its output is written, e.g.
+    /// `commandeer replay hit git [status] -> exit 0 (42 bytes)`.
+    #[arg(long)]
+    trace_invocations: bool,
+    /// Print a replayed invocation to stderr before its output, as `set -x` does, e.g. `+ git status`.
+    #[arg(long)]
+    echo_invocation: bool,
+    /// How to replay a command killed by a signal: `code` exits with 128 + the signal number, `raise` kills the
+    /// mock with the signal itself.
+    #[arg(long, default_value_t = SignalReplay::Code)]
+    signal_replay: SignalReplay,
+    /// Process id of the mock whose command made this invocation.
+    #[arg(long)]
+    parent_pid: Option<u32>,
+    /// Path of the wrapper script that ran this mock, stored with recordings.
+    #[arg(long)]
+    wrapper: Option<PathBuf>,
+    /// Environment variable to capture with recordings and compare on replay; may be repeated.
+    #[arg(long = "capture-env", value_name = "VAR")]
+    capture_env: Vec<String>,
+    #[arg(long)]
+    command: String,
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    args: Vec<String>,
+}
+
+/// Arguments controlling how the real command is run and stored.
+#[derive(Args)]
+struct RecordArgs {
+    /// Executable to run instead of resolving the command on `PATH`.
+    #[arg(long)]
+    program: Option<PathBuf>,
+    /// Encoding label the command's output is in, e.g. `latin1` or `shift_jis`.
+    #[arg(long)]
+    encoding: Option<String>,
+    /// `argv[0]` to run the command with (Unix only).
+    #[arg(long)]
+    arg0: Option<String>,
+    /// Stop recording when the command exits, even if a daemon it forked still holds its output open.
+    #[arg(long)]
+    no_wait_output: bool,
+    /// Show the command's output as it runs, rather than once it has been recorded.
+    #[arg(long)]
+    live_output: bool,
+    /// Run the command once under each locale, with `LC_ALL` set to it, storing a recording per locale. May be
+    /// repeated.
+    #[arg(long = "locale", value_name = "LOCALE")]
+    locales: Vec<String>,
+    /// Canonicalize output before saving: mask-timestamps or trim-trailing-whitespace. May be repeated.
+    #[arg(long = "transform", value_name = "NAME")]
+    transforms: Vec<BuiltinTransform>,
+    /// Run the command with `SOURCE_DATE_EPOCH` set, for reproducible timestamps.
+    #[arg(long)]
+    source_date_epoch: Option<u64>,
+    /// Run the command with `FAKETIME` set, for use with a preloaded libfaketime.
+    #[arg(long)]
+    faketime: Option<String>,
+    /// Move recorded stdout into this content-addressed store, so identical output is stored once.
+    #[arg(long, value_name = "DIR")]
+    blob_store: Option<PathBuf>,
+    /// Rewrite the fixture's index after recording, for faster replay from large fixtures.
+    #[arg(long)]
+    index: bool,
+    /// Keep at most this many recordings per invocation, dropping the oldest.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    max_per_key: Option<u64>,
+    /// Store the file named by this option's value, e.g. `-o`, with the recording, to recreate on replay. May be
+    /// repeated.
+    #[arg(long = "output-file-option", value_name = "OPT")]
+    output_file_options: Vec<String>,
+    /// Kill the command and record nothing if it hasn't finished within this many milliseconds.
+    #[arg(long, value_name = "MS")]
+    timeout_ms: Option<u64>,
+    /// Pass this arg to the real command ahead of its own args, without storing it in the recording. May be repeated.
+    #[arg(long = "extra-arg", value_name = "ARG", allow_hyphen_values = true)]
+    extra_args: Vec<String>,
+    /// Feed the command this process's stdin and store it with the recording.
+    #[arg(long)]
+    capture_stdin: bool,
+    /// Store how long the command took with the recording, for `list --timing`.
+    #[arg(long)]
+    record_timing: bool,
+    /// Store the binary the command ended up running, after any `exec` chain, shown by `list`.
+    #[arg(long)]
+    record_executable: bool,
+    /// Store the command's CPU time and peak memory with the recording, shown by `list --verbose`. Unix only.
+    #[arg(long)]
+    record_rusage: bool,
+    /// Store how many file descriptors were open as the command started, shown by `list --verbose` and compared on
+    /// replay. Unix only.
+    #[arg(long)]
+    record_fd_count: bool,
+    /// Check the recording for credentials before saving it: off, warn or deny.
+    #[arg(long, default_value_t = SecretScan::Off)]
+    scan_secrets: SecretScan,
+}
+
+impl RecordArgs {
+    fn into_options(self, truncate: bool, mock: &MockArgs) -> RecordOptions {
+        RecordOptions {
+            truncate,
+            test_name: mock.test_name.clone(),
+            normalize_temp_paths: mock.normalize_temp_paths,
+            response_files: mock.response_files,
+            canonical_json: mock.canonical_json,
+            split_options: mock.split_options.clone(),
+            unordered_flags: mock.unordered_flags(),
+            capture_env: mock.capture_env.clone(),
+            locales: self.locales,
+            program: self.program,
+            encoding: self.encoding,
+            arg0: self.arg0,
+            no_wait_output: self.no_wait_output,
+            live_output: self.live_output,
+            transforms: self.transforms.into_iter().map(Into::into).collect(),
+            source_date_epoch: self.source_date_epoch,
+            faketime: self.faketime,
+            blob_store: self.blob_store,
+            index: self.index,
+            max_per_key: self.max_per_key.map(|max| max as usize),
+            wrapper: mock.wrapper.clone(),
+            output_file_options: self.output_file_options,
+            timeout: self.timeout_ms.map(Duration::from_millis),
+            extra_args: self.extra_args,
+            capture_stdin: self.capture_stdin,
+            record_timing: self.record_timing,
+            record_executable: self.record_executable,
+            record_rusage: self.record_rusage,
+            record_fd_count: self.record_fd_count,
+            scan_secrets: self.scan_secrets,
+        }
+    }
+}
+
+/// Arguments controlling how replay matches recordings that differ from the invocation.
+#[derive(Args)]
+struct MatchArgs {
+    /// Match numeric args differing by at most this much.
+    #[arg(long)]
+    abs_tolerance: Option<f64>,
+    /// Match numeric args differing by at most this fraction of the larger value.
+    #[arg(long)]
+    rel_tolerance: Option<f64>,
+    /// How the environment must compare to a recording's captured env: ignore, subset or exact.
+    #[arg(long, default_value_t = EnvMatch::Ignore)]
+    env_match: EnvMatch,
+    /// Replay from this fixture beneath `--file`, which overrides it per key. May be repeated, later layers overriding
+    /// earlier ones.
+    #[arg(long = "fixture-layer", value_name = "FILE")]
+    fixture_layers: Vec<PathBuf>,
+    /// Fail instead of picking one when more than one recording matches the invocation.
+    #[arg(long)]
+    require_unique_match: bool,
+    /// Only replay recordings made under the active locale (`LC_ALL`, else `LANG`), or under none.
+    #[arg(long)]
+    match_locale: bool,
+}
+
+impl MatchArgs {
+    fn to_options(&self, mock: &MockArgs) -> MatchOptions {
+        let numeric_tolerance = (self.abs_tolerance.is_some() || self.rel_tolerance.is_some())
+            .then(|| NumericTolerance {
+                absolute: self.abs_tolerance.unwrap_or_default(),
+                relative: self.rel_tolerance.unwrap_or_default(),
+            });
+
+        MatchOptions {
+            numeric_tolerance,
+            test_name: mock.test_name.clone(),
+            normalize_temp_paths: mock.normalize_temp_paths,
+            response_files: mock.response_files,
+            canonical_json: mock.canonical_json,
+            split_options: mock.split_options.clone(),
+            unordered_flags: mock.unordered_flags(),
+            env_match: self.env_match,
+            capture_env: mock.capture_env.clone(),
+            fixture_layers: self.fixture_layers.clone(),
+            require_unique_match: self.require_unique_match,
+            match_locale: self.match_locale,
+        }
+    }
+}
+
+impl MockArgs {
+    /// Args for running `command` directly rather than from a mock wrapper, with every mock flag at its default.
+    fn for_command(
+        file: PathBuf,
+        test_name: Option<String>,
+        command: String,
+        args: Vec<String>,
+    ) -> Self {
+        Self {
+            file,
+            call_log: None,
+            test_name,
+            normalize_temp_paths: false,
+            response_files: false,
+            canonical_json: false,
+            split_options: vec![],
+            unordered_flags: false,
+            flag_values: vec![],
+            expand_home: false,
+            native_line_endings: false,
+            concurrent_output: false,
+            chunk_size: None,
+            chunk_delay_ms: 0,
+            debug: false,
+            trace_invocations: false,
+            echo_invocation: false,
+            signal_replay: SignalReplay::Code,
+            parent_pid: None,
+            wrapper: None,
+            capture_env: vec![],
+            command,
+            args,
+        }
+    }
+
+    fn unordered_flags(&self) -> Option<Vec<String>> {
+        self.unordered_flags.then(|| self.flag_values.clone())
+    }
+}
+
 #[derive(Subcommand)]
 enum Commands {
     Record {
-        /// Path to save recordings.
-        #[arg(long, default_value = "recordings.json")]
+        /// Whether to truncate the file before recording.
+        #[arg(long)]
+        truncate: bool,
+        #[command(flatten)]
+        record: RecordArgs,
+        #[command(flatten)]
+        mock: MockArgs,
+    },
+    Replay {
+        /// Replay the Nth (zero-based) recorded invocation instead of the first.
+        #[arg(long)]
+        occurrence: Option<usize>,
+        #[command(flatten)]
+        matching: MatchArgs,
+        #[command(flatten)]
+        mock: MockArgs,
+    },
+    /// Replay a recorded invocation, recording it first if there is none.
+    RecordOnce {
+        #[command(flatten)]
+        record: RecordArgs,
+        #[command(flatten)]
+        matching: MatchArgs,
+        #[command(flatten)]
+        mock: MockArgs,
+    },
+    /// Replay a recorded invocation, recording a miss to the fixture's pending file instead of failing.
+    ReplayLearn {
+        #[command(flatten)]
+        record: RecordArgs,
+        #[command(flatten)]
+        matching: MatchArgs,
+        #[command(flatten)]
+        mock: MockArgs,
+    },
+    /// Run a command and record it, e.g. `commandeer exec --file f.json -- mytool arg1`.
+    Exec {
+        /// Path to the recordings.
+        #[arg(long, env = "COMMANDEER_FILE", default_value = "recordings.json")]
         file: PathBuf,
         /// Whether to truncate the file before recording.
         #[arg(long)]
         truncate: bool,
+        /// Scope the recording to this test within a shared fixture.
         #[arg(long)]
-        command: String,
-        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
-        args: Vec<String>,
+        test_name: Option<String>,
+        #[command(flatten)]
+        record: RecordArgs,
+        /// The command to run followed by its arguments.
+        #[arg(last = true, required = true)]
+        argv: Vec<String>,
     },
-    Replay {
+    /// Record a command twice and report whether the recordings match, failing if they don't, e.g.
+    /// `commandeer check-deterministic -- mytool arg1`.
+    ///
+    /// Use it to find commands whose output needs a `--transform` before recording them makes a stable fixture.
+    CheckDeterministic {
+        #[command(flatten)]
+        record: RecordArgs,
+        /// The command to run followed by its arguments.
+        #[arg(last = true, required = true)]
+        argv: Vec<String>,
+    },
+    /// Record every command listed on stdin, one `{"command": ..., "args": [...]}` JSON object per line.
+    Batch {
+        /// Path to the recordings.
+        #[arg(long, env = "COMMANDEER_FILE", default_value = "recordings.json")]
+        file: PathBuf,
+        /// Whether to truncate the file before recording the first command.
+        #[arg(long)]
+        truncate: bool,
+        /// Scope the recordings to this test within a shared fixture.
+        #[arg(long)]
+        test_name: Option<String>,
+        #[command(flatten)]
+        record: RecordArgs,
+    },
+    /// Re-run every recorded invocation and rewrite its output and exit code in place.
+    Refresh {
+        /// Path to the recordings.
+        #[arg(long, env = "COMMANDEER_FILE", default_value = "recordings.json")]
+        file: PathBuf,
+        /// Confirm rewriting the file; without it nothing is run.
+        #[arg(long)]
+        yes: bool,
+        #[command(flatten)]
+        record: RecordArgs,
+    },
+    /// Remove every recording of one command, leaving the rest of the file intact.
+    Clear {
         /// Path to the recordings.
-        #[arg(long, default_value = "recordings.json")]
+        #[arg(long, env = "COMMANDEER_FILE", default_value = "recordings.json")]
         file: PathBuf,
+        /// Only clear the command's recordings for this test within a shared fixture, rather than at the top level and
+        /// in every test.
+        #[arg(long)]
+        test_name: Option<String>,
+        /// Command whose recordings to remove.
         #[arg(long)]
         command: String,
-        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
-        args: Vec<String>,
+    },
+    /// Print totals over a `COMMANDEER_SUMMARY` file: replays, misses, recordings and bytes emitted.
+    Summarize {
+        /// Path to the summary file.
+        #[arg(long)]
+        file: PathBuf,
+    },
+    /// Print a shell script that re-records every invocation in a file against the real commands.
+    ExportScript {
+        /// Path to the recordings.
+        #[arg(long, env = "COMMANDEER_FILE", default_value = "recordings.json")]
+        file: PathBuf,
+    },
+    /// Rewrite the recordings in one file into another, in the format its extension names: a VCR cassette for
+    /// `.yaml` or `.yml` (needs the `vcr` feature), JSON otherwise, e.g.
+    /// `commandeer convert --file recordings.json --output cassette.yaml`.
+    Convert {
+        /// Path to the recordings.
+        #[arg(long, env = "COMMANDEER_FILE", default_value = "recordings.json")]
+        file: PathBuf,
+        /// Path to write the converted recordings to.
+        #[arg(long)]
+        output: PathBuf,
+    },
+    /// Print the recordings stored in a file.
+    List {
+        /// Path to the recordings.
+        #[arg(long, env = "COMMANDEER_FILE", default_value = "recordings.json")]
+        file: PathBuf,
+        /// Remove ANSI escape sequences from the listed output.
+        #[arg(long, conflicts_with = "raw")]
+        strip_ansi: bool,
+        /// Print output verbatim so ANSI escape sequences render.
+        #[arg(long)]
+        raw: bool,
+        /// Show the stdin captured with each recording.
+        #[arg(long)]
+        show_stdin: bool,
+        /// Show the min, max and mean duration of each invocation's timed recordings.
+        #[arg(long)]
+        timing: bool,
+        /// Show diagnostics recorded with each invocation, such as its resource usage.
+        #[arg(long)]
+        verbose: bool,
     },
 }
 
-async fn record_mode(
-    truncate: bool,
-    file_path: PathBuf,
+/// A command to record, as read by `batch`.
+#[derive(Deserialize)]
+struct BatchCommand {
     command: String,
+    #[serde(default)]
     args: Vec<String>,
+}
+
+fn batch_mode(mut options: RecordOptions, file: PathBuf) -> Result<()> {
+    for (number, line) in std::io::stdin().lines().enumerate() {
+        let line = line?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let spec: BatchCommand = serde_json::from_str(&line)
+            .with_context(|| format!("Invalid command on line {}", number + 1))?;
+
+        record_command_sync_with(&options, file.clone(), spec.command, spec.args)?;
+
+        options.truncate = false;
+    }
+
+    Ok(())
+}
+
+/// Records the command of `mock` twice into its scratch fixture and compares the two recordings.
+fn check_deterministic(options: &RecordOptions, mock: MockArgs) -> Result<()> {
+    let record = || {
+        record_command_sync_with(
+            options,
+            mock.file.clone(),
+            mock.command.clone(),
+            mock.args.clone(),
+        )
+    };
+
+    let (first, second) = (record()?, record()?);
+    let mut divergences = vec![];
+
+    if first.exit_code != second.exit_code {
+        divergences.push(format!(
+            "exit code differs: {} then {}",
+            first.exit_code, second.exit_code
+        ));
+    }
+
+    for (name, first_output, second_output) in [
+        ("stdout", &first.stdout, &second.stdout),
+        ("stderr", &first.stderr, &second.stderr),
+    ] {
+        if first_output != second_output {
+            let divergence =
+                first_divergence(&first.decode(first_output), &second.decode(second_output))
+                    .unwrap_or_else(|| "differs in bytes that don't decode as text".to_string());
+
+            divergences.push(format!("{name} {divergence}"));
+        }
+    }
+
+    let invocation = format!("{} {}", mock.command, mock.args.join(" "));
+
+    if !divergences.is_empty() {
+        bail!(
+            "`{}` is not deterministic:\n{}",
+            invocation.trim_end(),
+            divergences.join("\n")
+        );
+    }
+
+    println!("`{}` is deterministic", invocation.trim_end());
+
+    Ok(())
+}
+
+/// Where `second` first differs from `first`, by line.
+fn first_divergence(first: &str, second: &str) -> Option<String> {
+    if first == second {
+        return None;
+    }
+
+    let mut first_lines = first.lines();
+    let mut second_lines = second.lines();
+
+    for line in 1.. {
+        let (a, b) = (first_lines.next(), second_lines.next());
+
+        if a != b {
+            return Some(format!(
+                "differs at line {line}:\n  - {}\n  + {}",
+                a.unwrap_or("<end>"),
+                b.unwrap_or("<end>")
+            ));
+        }
+
+        if a.is_none() {
+            break;
+        }
+    }
+
+    Some("differs only in line endings or a trailing newline".to_string())
+}
+
+/// Reports what the wrapper exported for this mock, to trace which instance handles a nested invocation.
+fn print_debug(mock: &MockArgs) {
+    if !mock.debug {
+        return;
+    }
+
+    let var = |name| std::env::var(name).unwrap_or_default();
+
+    eprintln!(
+        "commandeer: {} {:?} {COMMANDEER_ACTIVE_MODE}={} {COMMANDEER_ACTIVE_FIXTURE}={}",
+        mock.command,
+        mock.args,
+        var(COMMANDEER_ACTIVE_MODE),
+        var(COMMANDEER_ACTIVE_FIXTURE),
+    );
+}
+
+/// Logs the call of `mock`, served by the recording `matched` unless it missed.
+fn log_call(mock: &MockArgs, exit_code: i32, matched: Option<&CommandInvocation>) -> Result<()> {
+    let Some(call_log) = &mock.call_log else {
+        return Ok(());
+    };
+
+    let entry = CallLogEntry {
+        binary_name: mock.command.clone(),
+        args: mock.args.clone(),
+        exit_code,
+        matched: matched.is_some(),
+        key: matched.map(|invocation| recording_key(&invocation.binary_name, &invocation.args)),
+        pid: Some(std::process::id()),
+        parent_pid: mock.parent_pid,
+        started_ms: STARTED
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .ok()
+            .map(|started| started.as_millis() as u64),
+    };
+
+    append_call_log(call_log, &entry)?;
+
+    Ok(())
+}
+
+/// Appends the invocation's outcome to the suite-wide summary when `COMMANDEER_SUMMARY` is set.
+fn log_summary(mock: &MockArgs, outcome: Outcome, bytes: u64) -> Result<()> {
+    let Some(summary) = std::env::var_os(COMMANDEER_SUMMARY) else {
+        return Ok(());
+    };
+
+    let entry = SummaryEntry {
+        binary_name: mock.command.clone(),
+        args: mock.args.clone(),
+        outcome,
+        bytes,
+    };
+
+    append_summary(summary.as_ref(), &entry)?;
+
+    Ok(())
+}
+
+/// Prints the `--trace-invocations` line for the invocation, after all of its output so the two don't interleave.
+fn trace(mock: &MockArgs, outcome: Outcome, exit_code: i32, bytes: u64) {
+    if !mock.trace_invocations {
+        return;
+    }
+
+    let outcome = match outcome {
+        Outcome::Replayed => "replay hit",
+        Outcome::Missed => "replay miss",
+        Outcome::Recorded => "record",
+    };
+
+    eprintln!(
+        "commandeer {outcome} {} [{}] -> exit {exit_code} ({bytes} bytes)",
+        mock.command,
+        mock.args.join(" ")
+    );
+}
+
+/// Quotes `arg` for `--echo-invocation` only where a shell would need it, as `set -x` does.
+fn trace_word(arg: &str) -> Cow<'_, str> {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "%+,-./:=@_".contains(c);
+
+    if !arg.is_empty() && arg.chars().all(plain) {
+        Cow::Borrowed(arg)
+    } else {
+        Cow::Owned(format!("'{}'", arg.replace('\'', r"'\''")))
+    }
+}
+
+/// Writes the output of `invocation` and exits as it did. `live` is set when recording already copied the output
+/// through as it arrived, see [`RecordOptions::live_output`].
+fn emit(
+    mock: &MockArgs,
+    invocation: &CommandInvocation,
+    outcome: Outcome,
+    live: bool,
 ) -> Result<()> {
-    let invocation = record_command(truncate, file_path, command, args).await?;
+    log_call(mock, invocation.exit_code, Some(invocation))?;
+
+    let mut invocation = invocation.clone();
+
+    // Recording ran the real command, which wrote the files itself.
+    if outcome == Outcome::Replayed {
+        if mock.echo_invocation {
+            let words: Vec<_> = std::iter::once(mock.command.as_str())
+                .chain(mock.args.iter().map(String::as_str))
+                .map(trace_word)
+                .collect();
+
+            eprintln!("+ {}", words.join(" "));
+        }
+
+        invocation.write_files()?;
 
-    output_invocation(&invocation);
+        if let Some(recorded) = invocation.fd_count
+            && let Some(open) = open_fd_count()
+            && open.abs_diff(recorded) > FD_COUNT_DRIFT
+        {
+            eprintln!(
+                "commandeer: warning: {recorded} file descriptors were open when `{}` was recorded, {open} now; it \
+                 may behave differently with what it inherits",
+                mock.command
+            );
+        }
+    }
 
-    exit_with_code(invocation.exit_code);
+    if mock.expand_home {
+        invocation.expand_home();
+    }
+
+    if mock.native_line_endings {
+        invocation.native_line_endings();
+    }
+
+    let written = if live {
+        (invocation.stdout.len() + invocation.stderr.len()) as u64
+    } else if let Some(chunk_size) = mock.chunk_size {
+        output_invocation_chunked(
+            &invocation,
+            &mock.file,
+            chunk_size as usize,
+            Duration::from_millis(mock.chunk_delay_ms),
+        )?
+    } else if mock.concurrent_output {
+        output_invocation_concurrent(&invocation, &mock.file)?
+    } else {
+        output_invocation_streaming(&invocation, &mock.file)?
+    };
+
+    log_summary(mock, outcome, written)?;
+    trace(mock, outcome, invocation.exit_code, written);
+
+    exit_as_recorded(&invocation, mock.signal_replay);
 }
 
-async fn replay_mode(file_path: PathBuf, command: String, args: Vec<String>) -> Result<()> {
-    match replay_command(file_path, command.clone(), args.clone()).await? {
-        Some(invocation) => {
-            output_invocation(&invocation);
+fn record_mode(options: RecordOptions, mock: MockArgs) -> Result<()> {
+    let invocation = record_command_sync_with(
+        &options,
+        mock.file.clone(),
+        mock.command.clone(),
+        mock.args.clone(),
+    )?;
+
+    emit(&mock, &invocation, Outcome::Recorded, options.live_output)
+}
+
+fn replay_mode(mock: MockArgs, matching: MatchOptions, occurrence: Option<usize>) -> Result<()> {
+    let invocation = match occurrence {
+        Some(occurrence) => {
+            let recordings = load_replay_recordings_sync(&matching, &mock.file)?;
+            let invocations = recordings.matching_invocations(&mock.command, &mock.args, &matching);
+
+            if !invocations.is_empty() && occurrence >= invocations.len() {
+                bail!(
+                    "Occurrence {occurrence} is out of range, {} invocations recorded for: {} {}",
+                    invocations.len(),
+                    mock.command,
+                    mock.args.join(" ")
+                );
+            }
 
-            exit_with_code(invocation.exit_code);
+            let mut invocation = invocations.get(occurrence).copied().cloned();
+
+            if let Some(invocation) = &mut invocation {
+                invocation.render_stdout_template(&mock.file, &mock.args)?;
+            }
+
+            invocation
         }
+        None => replay_command_sync_with(
+            &matching,
+            mock.file.clone(),
+            mock.command.clone(),
+            mock.args.clone(),
+        )?,
+    };
+
+    match invocation {
+        Some(invocation) => emit(&mock, &invocation, Outcome::Replayed, false),
         None => {
+            log_call(&mock, 1, None)?;
+            log_summary(&mock, Outcome::Missed, 0)?;
+
             eprintln!(
-                "No recorded invocation found for: {command} {}",
-                args.join(" ")
+                "No recorded invocation found for: {} {}",
+                mock.command,
+                mock.args.join(" ")
             );
+            trace(&mock, Outcome::Missed, 1, 0);
 
             exit_with_code(1);
         }
     }
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
+fn record_once_mode(options: RecordOptions, matching: MatchOptions, mock: MockArgs) -> Result<()> {
+    match replay_command_sync_with(
+        &matching,
+        mock.file.clone(),
+        mock.command.clone(),
+        mock.args.clone(),
+    )? {
+        Some(invocation) => emit(&mock, &invocation, Outcome::Replayed, false),
+        None => record_mode(options, mock),
+    }
+}
+
+fn replay_learn_mode(
+    options: RecordOptions,
+    matching: MatchOptions,
+    mut mock: MockArgs,
+) -> Result<()> {
+    if let Some(invocation) = replay_command_sync_with(
+        &matching,
+        mock.file.clone(),
+        mock.command.clone(),
+        mock.args.clone(),
+    )? {
+        return emit(&mock, &invocation, Outcome::Replayed, false);
+    }
+
+    // A miss seen earlier in the run is already pending, so replay it rather than recording a duplicate.
+    mock.file = pending_path(&mock.file);
+
+    record_once_mode(options, matching, mock)
+}
+
+/// When this process started, for ordering nested invocations in the call log.
+static STARTED: LazyLock<SystemTime> = LazyLock::new(SystemTime::now);
+
+/// Loads `file` for a subcommand that only reads it, which unlike recording shouldn't create it when it is missing.
+fn read_recordings(file: &PathBuf) -> Result<RecordedCommands> {
+    if !std::fs::exists(file)? {
+        bail!("{} doesn't exist", file.display());
+    }
+
+    Ok(load_recordings_sync(file)?)
+}
+
+fn main() -> Result<()> {
+    LazyLock::force(&STARTED);
+
     let cli = Cli::parse();
 
     match cli.command {
         Commands::Record {
-            file,
-            command,
-            args,
             truncate,
+            record,
+            mock,
         } => {
-            record_mode(truncate, file, command, args).await?;
+            print_debug(&mock);
+            record_mode(record.into_options(truncate, &mock), mock)?;
         }
         Commands::Replay {
+            occurrence,
+            matching,
+            mock,
+        } => {
+            print_debug(&mock);
+            let matching = matching.to_options(&mock);
+
+            replay_mode(mock, matching, occurrence)?;
+        }
+        Commands::RecordOnce {
+            record,
+            matching,
+            mock,
+        } => {
+            print_debug(&mock);
+            let record = record.into_options(false, &mock);
+            let matching = matching.to_options(&mock);
+
+            record_once_mode(record, matching, mock)?;
+        }
+        Commands::ReplayLearn {
+            record,
+            matching,
+            mock,
+        } => {
+            print_debug(&mock);
+            let record = record.into_options(false, &mock);
+            let matching = matching.to_options(&mock);
+
+            replay_learn_mode(record, matching, mock)?;
+        }
+        Commands::Exec {
+            file,
+            truncate,
+            test_name,
+            record,
+            mut argv,
+        } => {
+            let command = argv.remove(0);
+            let mock = MockArgs::for_command(file, test_name, command, argv);
+
+            record_mode(record.into_options(truncate, &mock), mock)?;
+        }
+        Commands::CheckDeterministic { record, mut argv } => {
+            let dir = tempfile::TempDir::new()?;
+            let command = argv.remove(0);
+            let mock = MockArgs::for_command(dir.path().join("check.json"), None, command, argv);
+
+            check_deterministic(&record.into_options(true, &mock), mock)?;
+        }
+        Commands::Batch {
             file,
+            truncate,
+            test_name,
+            record,
+        } => {
+            let mock = MockArgs::for_command(file, test_name, String::new(), vec![]);
+            let options = record.into_options(truncate, &mock);
+
+            batch_mode(options, mock.file)?;
+        }
+        Commands::Refresh { file, yes, record } => {
+            if !yes {
+                bail!(
+                    "refresh re-runs every recorded command in {} and overwrites its output; pass --yes to confirm",
+                    file.display()
+                );
+            }
+
+            let mock = MockArgs::for_command(file, None, String::new(), vec![]);
+            let options = record.into_options(false, &mock);
+
+            let refreshed = refresh_recordings_sync(&options, &mock.file)?;
+
+            eprintln!(
+                "Refreshed {refreshed} recordings in {}",
+                mock.file.display()
+            );
+        }
+        Commands::Clear {
+            file,
+            test_name,
             command,
-            args,
         } => {
-            replay_mode(file, command, args).await?;
+            let mut recordings = read_recordings(&file)?;
+
+            let scope = match &test_name {
+                Some(test_name) => recordings.for_test_mut(test_name),
+                None => &mut recordings,
+            };
+
+            if scope.remove_command(&command) > 0 {
+                save_recordings_sync(&file, &recordings)?;
+            }
+        }
+        Commands::Summarize { file } => {
+            print!("{}", Summary::from_entries(&read_summary(&file)?));
+        }
+        Commands::ExportScript { file } => {
+            print!("{}", export_script(&read_recordings(&file)?, &file));
+        }
+        Commands::Convert { file, output } => {
+            save_recordings_sync(&output, &read_recordings(&file)?)?;
+        }
+        Commands::List {
+            file,
+            strip_ansi,
+            raw,
+            show_stdin,
+            timing,
+            verbose,
+        } => {
+            let ansi = match (strip_ansi, raw) {
+                (true, _) => AnsiDisplay::Strip,
+                (_, true) => AnsiDisplay::Raw,
+                _ => AnsiDisplay::Escaped,
+            };
+
+            print!(
+                "{}",
+                format_recordings_with(
+                    &read_recordings(&file)?,
+                    &ListOptions {
+                        ansi,
+                        show_stdin,
+                        timing,
+                        verbose,
+                    }
+                )
+            );
         }
     }
 
@@ -0,0 +1,1037 @@
+use crate::{CommandInvocation, CommandeerError, RecordedCommands};
+use sha2::{Digest, Sha256};
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    env, fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// Replaces the per-run directory of a temp path in an arg when temp path normalization is enabled.
+pub const TEMP_PATH_TOKEN: &str = "<TMP>";
+
+/// Controls how replay matches an invocation against recordings when there is no exact match.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MatchOptions {
+    /// Match numeric args within a tolerance instead of requiring identical strings.
+    pub numeric_tolerance: Option<NumericTolerance>,
+    /// Only consider recordings belonging to this test in a shared fixture.
+    pub test_name: Option<String>,
+    /// Look up args with temp paths replaced, see [`normalize_temp_paths`].
+    pub normalize_temp_paths: bool,
+    /// Look up args with `@file` response files expanded and hashed, see [`expand_response_files`].
+    pub response_files: bool,
+    /// Look up args that are JSON documents in canonical form, see [`canonicalize_json_args`].
+    pub canonical_json: bool,
+    /// How the current environment must compare to a recording's captured env for it to match.
+    pub env_match: EnvMatch,
+    /// Options whose attached values are split off before lookup, see [`split_option_args`].
+    pub split_options: Vec<String>,
+    /// Look up args with flags sorted, given the options that take a value; see [`sort_flag_args`].
+    pub unordered_flags: Option<Vec<String>>,
+    /// Environment variables considered by [`EnvMatch::Exact`], normally the allowlist recordings were captured with.
+    pub capture_env: Vec<String>,
+    /// Fixtures layered beneath the replayed one, each overriding those before it per key; see
+    /// [`crate::load_layered_recordings_sync`].
+    pub fixture_layers: Vec<PathBuf>,
+    /// Fail replay with [`CommandeerError::AmbiguousMatch`] when recordings of more than one invocation match,
+    /// rather than picking the first, to catch fixtures where tolerances make it unclear which one a call gets.
+    /// Repeated recordings of the same invocation don't count, and ones given a higher
+    /// [`CommandInvocation::priority`] than the rest still win outright.
+    pub require_unique_match: bool,
+    /// Only match recordings made under the [`crate::active_locale`], or under no locale; see
+    /// [`crate::RecordOptions::locales`].
+    pub match_locale: bool,
+}
+
+/// How replay compares the environment against the env captured with a recording.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EnvMatch {
+    /// Match regardless of the environment.
+    #[default]
+    Ignore,
+    /// Every captured variable must have the same value now; variables captured as unset are not checked.
+    Subset,
+    /// The allowlisted variables must be set exactly as captured: same values, and none set now that were unset
+    /// when recording.
+    Exact,
+}
+
+impl fmt::Display for EnvMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnvMatch::Ignore => write!(f, "ignore"),
+            EnvMatch::Subset => write!(f, "subset"),
+            EnvMatch::Exact => write!(f, "exact"),
+        }
+    }
+}
+
+impl FromStr for EnvMatch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ignore" => Ok(EnvMatch::Ignore),
+            "subset" => Ok(EnvMatch::Subset),
+            "exact" => Ok(EnvMatch::Exact),
+            _ => Err(format!(
+                "unknown env match `{s}`, expected ignore, subset or exact"
+            )),
+        }
+    }
+}
+
+/// Tolerance for numeric args; two numbers match if they are within either bound.
+///
+/// Args only compare numerically when both the recorded and the invoked arg parse as numbers. Any other pair,
+/// including a number against a non-number, must be equal as strings.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct NumericTolerance {
+    /// Maximum absolute difference, e.g. `2.0` lets `30` match `31`.
+    pub absolute: f64,
+    /// Maximum difference relative to the larger magnitude, e.g. `0.1` for 10%.
+    pub relative: f64,
+}
+
+impl NumericTolerance {
+    fn matches(&self, recorded: &str, invoked: &str) -> bool {
+        match (recorded.parse::<f64>(), invoked.parse::<f64>()) {
+            (Ok(a), Ok(b)) => {
+                let diff = (a - b).abs();
+
+                diff <= self.absolute || diff <= self.relative * a.abs().max(b.abs())
+            }
+            _ => recorded == invoked,
+        }
+    }
+}
+
+/// Splits values attached to any of `options` into a separate arg, so `--opt=value` and `-ovalue` are keyed the same
+/// as `--opt value` and `-o value`.
+///
+/// `options` lists option names as invoked, e.g. `--width` or `-o`. A long option is split at its first `=`; a short
+/// option of one dash and one character is split after that character. Args after a `--` terminator are left alone.
+pub fn split_option_args(args: &[String], options: &[String]) -> Vec<String> {
+    let mut split = Vec::with_capacity(args.len());
+    let mut args = args.iter();
+
+    for arg in args.by_ref() {
+        if arg == "--" {
+            split.push(arg.clone());
+            break;
+        }
+
+        let value = options.iter().find_map(|option| {
+            let rest = arg.strip_prefix(option.as_str())?;
+
+            if option.starts_with("--") {
+                Some((option, rest.strip_prefix('=')?))
+            } else if option.len() == 2 && option.starts_with('-') && !rest.is_empty() {
+                Some((option, rest))
+            } else {
+                None
+            }
+        });
+
+        match value {
+            Some((option, value)) => split.extend([option.clone(), value.to_string()]),
+            None => split.push(arg.clone()),
+        }
+    }
+
+    split.extend(args.cloned());
+
+    split
+}
+
+/// Reorders `args` so invocations passing the same flags in a different order are keyed the same: every flag moves
+/// to the front, sorted, followed by the positional args in their original order.
+///
+/// An arg starting with `-` is a flag, except a lone `-`. A flag listed in `value_options` takes the next arg as its
+/// value and moves with it, so `git -c x log` and `git log -c x` agree when `-c` is listed; an unlisted flag's next
+/// arg is positional and keeps its place. Attached values such as `--opt=value` move with their flag as one arg.
+/// Args after a `--` terminator are left alone. Negative numbers count as flags too, so fixtures relying on their
+/// order shouldn't enable this.
+pub fn sort_flag_args(args: &[String], value_options: &[String]) -> Vec<String> {
+    let mut flags: Vec<&[String]> = vec![];
+    let mut positionals = vec![];
+    let mut i = 0;
+
+    while i < args.len() {
+        let arg = &args[i];
+
+        if arg == "--" {
+            break;
+        }
+
+        if arg.starts_with('-') && arg != "-" {
+            let len = if value_options.contains(arg) && i + 1 < args.len() {
+                2
+            } else {
+                1
+            };
+
+            flags.push(&args[i..i + len]);
+            i += len;
+        } else {
+            positionals.push(arg.clone());
+            i += 1;
+        }
+    }
+
+    // Stable, so repeated flags such as `-v -v` keep their count.
+    flags.sort();
+
+    let mut sorted: Vec<String> = flags.concat();
+    sorted.extend(positionals);
+    sorted.extend_from_slice(&args[i..]);
+
+    sorted
+}
+
+/// Response files nested deeper than this are left unexpanded, which also stops a file that names itself.
+const MAX_RESPONSE_FILE_DEPTH: usize = 16;
+
+/// Replaces each `@file` arg naming a readable response file with a single `@sha256:<hex>` arg hashing the args it
+/// expands to, so toolchain invocations passing thousands of inputs through one are keyed compactly by what they
+/// actually pass.
+///
+/// Files are split into args as GCC and Clang do: on whitespace, with single or double quotes grouping and a
+/// backslash escaping the next character. `@file` args inside a response file are expanded in turn, and paths are
+/// resolved against the current directory. Only the contents are hashed, so the same args passed through a file at
+/// a different path match, while editing the file doesn't. An `@` arg naming no readable file is kept as it is, as
+/// the tools themselves treat it.
+pub fn expand_response_files(args: &[String]) -> Vec<String> {
+    args.iter()
+        .map(|arg| {
+            let mut expanded = vec![];
+
+            if !expand_response_file(arg, &mut expanded, 0) {
+                return arg.clone();
+            }
+
+            let mut hash = Sha256::new();
+
+            for arg in &expanded {
+                hash.update(arg.as_bytes());
+                hash.update([0]);
+            }
+
+            format!("@sha256:{:x}", hash.finalize())
+        })
+        .collect()
+}
+
+/// Appends the args `arg` expands to as a response file to `out`, returning whether it was one.
+fn expand_response_file(arg: &str, out: &mut Vec<String>, depth: usize) -> bool {
+    let Some(path) = arg.strip_prefix('@') else {
+        return false;
+    };
+
+    let Ok(contents) = fs::read_to_string(path) else {
+        return false;
+    };
+
+    if depth >= MAX_RESPONSE_FILE_DEPTH {
+        return false;
+    }
+
+    for arg in split_response_file(&contents) {
+        if !expand_response_file(&arg, out, depth + 1) {
+            out.push(arg);
+        }
+    }
+
+    true
+}
+
+fn split_response_file(contents: &str) -> Vec<String> {
+    let mut args = vec![];
+    let mut arg: Option<String> = None;
+    let mut quote = None;
+    let mut chars = contents.chars();
+
+    while let Some(c) = chars.next() {
+        match (c, quote) {
+            ('\\', _) => {
+                if let Some(next) = chars.next() {
+                    arg.get_or_insert_default().push(next);
+                }
+            }
+            (c, Some(q)) if c == q => quote = None,
+            (c, Some(_)) => arg.get_or_insert_default().push(c),
+            ('\'' | '"', None) => {
+                // Opening a quote starts an arg, so `""` passes an empty one.
+                arg.get_or_insert_default();
+                quote = Some(c);
+            }
+            (c, None) if c.is_whitespace() => args.extend(arg.take()),
+            (c, None) => arg.get_or_insert_default().push(c),
+        }
+    }
+
+    args.extend(arg);
+
+    args
+}
+
+/// Replaces each arg that is a JSON object or array, i.e. starts with `{` or `[` and parses as JSON, with its
+/// canonical form: object keys sorted and no whitespace between tokens. Tools wrapping APIs often take a JSON blob
+/// whose key order or formatting varies between runs, which this keys by what it means, so `{"a":1,"b":2}` and
+/// `{ "b": 2, "a": 1 }` match. Any other arg, including one that only looks like JSON, is kept as it is.
+pub fn canonicalize_json_args(args: &[String]) -> Vec<String> {
+    args.iter()
+        .map(|arg| {
+            if !arg.trim_start().starts_with(['{', '[']) {
+                return arg.clone();
+            }
+
+            match serde_json::from_str(arg) {
+                Ok(value) => canonical_json(value).to_string(),
+                Err(_) => arg.clone(),
+            }
+        })
+        .collect()
+}
+
+/// `value` with the keys of every object in it sorted, which `serde_json` only does itself without its
+/// `preserve_order` feature.
+fn canonical_json(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(object) => {
+            let mut entries: Vec<_> = object
+                .into_iter()
+                .map(|(key, value)| (key, canonical_json(value)))
+                .collect();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+            serde_json::Value::Object(entries.into_iter().collect())
+        }
+        serde_json::Value::Array(items) => {
+            serde_json::Value::Array(items.into_iter().map(canonical_json).collect())
+        }
+        value => value,
+    }
+}
+
+/// Applies [`expand_response_files`] if `response_files` is set, [`split_option_args`], [`canonicalize_json_args`]
+/// if `canonical_json` is set, then [`sort_flag_args`] if `unordered_flags` is set, and then, if `temp_paths` is set,
+/// [`normalize_temp_paths`].
+pub(crate) fn normalize_args(
+    args: &[String],
+    response_files: bool,
+    split_options: &[String],
+    canonical_json: bool,
+    unordered_flags: Option<&[String]>,
+    temp_paths: bool,
+) -> Vec<String> {
+    let mut args = split_option_args(
+        &if response_files {
+            expand_response_files(args)
+        } else {
+            args.to_vec()
+        },
+        split_options,
+    );
+
+    if canonical_json {
+        args = canonicalize_json_args(&args);
+    }
+
+    if let Some(value_options) = unordered_flags {
+        args = sort_flag_args(&args, value_options);
+    }
+
+    if temp_paths {
+        normalize_temp_paths(&args)
+    } else {
+        args
+    }
+}
+
+/// Replaces each path inside the system temp dir in `args` with [`TEMP_PATH_TOKEN`].
+///
+/// The directory directly below the temp dir is assumed to be created per run and is replaced along with it, while
+/// anything deeper is kept, so `/tmp/.tmpA1b2/out` and `/tmp/.tmpZ9y8/out` both become `<TMP>/out`. Paths embedded
+/// in an arg such as `--out=/tmp/.tmpA1b2/out` or a `:`-separated list are replaced too. On Windows the temp dir is
+/// matched case-insensitively and with either separator.
+pub fn normalize_temp_paths(args: &[String]) -> Vec<String> {
+    let temp_dir = env::temp_dir();
+
+    args.iter()
+        .map(|arg| normalize_temp_path(arg, &temp_dir).into_owned())
+        .collect()
+}
+
+fn normalize_temp_path<'a>(arg: &'a str, temp_dir: &Path) -> Cow<'a, str> {
+    // Folding must preserve byte offsets so matches in the folded string index into `arg`.
+    let fold = |s: &str| {
+        if cfg!(windows) {
+            s.to_ascii_lowercase().replace('/', "\\")
+        } else {
+            s.to_string()
+        }
+    };
+
+    let root = temp_dir.to_string_lossy();
+    let needle = fold(root.trim_end_matches(std::path::is_separator));
+
+    if needle.is_empty() {
+        return Cow::Borrowed(arg);
+    }
+
+    let haystack = fold(arg);
+    let is_path_char = |c: char| {
+        c.is_alphanumeric() || std::path::is_separator(c) || ['.', '_', '-', '~'].contains(&c)
+    };
+
+    let mut normalized = String::new();
+    let (mut copied, mut search) = (0, 0);
+
+    while let Some(found) = haystack[search..].find(&needle) {
+        let start = search + found;
+        let after = start + needle.len();
+        search = after;
+
+        // Skip matches that are only part of a longer path, e.g. `/var/tmp` when the temp dir is `/tmp`.
+        if arg[..start].chars().next_back().is_some_and(is_path_char) {
+            continue;
+        }
+
+        let Some(tail) = arg[after..].strip_prefix(std::path::is_separator) else {
+            continue;
+        };
+
+        let len = tail.find(std::path::is_separator).unwrap_or(tail.len());
+
+        if len == 0 {
+            continue;
+        }
+
+        normalized.push_str(&arg[copied..start]);
+        normalized.push_str(TEMP_PATH_TOKEN);
+        copied = after + 1 + len;
+        search = copied;
+    }
+
+    if copied == 0 {
+        return Cow::Borrowed(arg);
+    }
+
+    normalized.push_str(&arg[copied..]);
+
+    Cow::Owned(normalized)
+}
+
+impl MatchOptions {
+    pub(crate) fn is_fuzzy(&self) -> bool {
+        self.numeric_tolerance.is_some()
+    }
+
+    /// `args` as recordings are keyed under these options, see [`normalize_args`].
+    pub(crate) fn key_args<'a>(&self, args: &'a [String]) -> Cow<'a, [String]> {
+        if self.normalize_temp_paths
+            || self.response_files
+            || self.canonical_json
+            || !self.split_options.is_empty()
+            || self.unordered_flags.is_some()
+        {
+            Cow::Owned(normalize_args(
+                args,
+                self.response_files,
+                &self.split_options,
+                self.canonical_json,
+                self.unordered_flags.as_deref(),
+                self.normalize_temp_paths,
+            ))
+        } else {
+            Cow::Borrowed(args)
+        }
+    }
+
+    fn env_matches(&self, invocation: &CommandInvocation) -> bool {
+        if self.match_locale && !crate::locale::matches(invocation) {
+            return false;
+        }
+
+        let current = |name: &str| env::var(name).ok();
+
+        match self.env_match {
+            EnvMatch::Ignore => true,
+            EnvMatch::Subset => invocation
+                .env
+                .iter()
+                .all(|(name, value)| current(name).as_ref() == Some(value)),
+            EnvMatch::Exact => self
+                .capture_env
+                .iter()
+                .chain(invocation.env.keys())
+                .all(|name| current(name).as_ref() == invocation.env.get(name)),
+        }
+    }
+
+    fn args_match(&self, recorded: &[String], invoked: &[String]) -> bool {
+        recorded.len() == invoked.len()
+            && recorded
+                .iter()
+                .zip(invoked)
+                .all(|(r, i)| match self.numeric_tolerance {
+                    Some(tolerance) => tolerance.matches(r, i),
+                    None => r == i,
+                })
+    }
+
+    /// Arguments passing these options to the `commandeer` binary.
+    pub(crate) fn to_cli_args(&self) -> Vec<String> {
+        let mut args = vec![];
+
+        if let Some(tolerance) = self.numeric_tolerance {
+            args.push(format!("--abs-tolerance={}", tolerance.absolute));
+            args.push(format!("--rel-tolerance={}", tolerance.relative));
+        }
+
+        if self.env_match != EnvMatch::Ignore {
+            args.push(format!("--env-match={}", self.env_match));
+        }
+
+        if self.require_unique_match {
+            args.push("--require-unique-match".to_string());
+        }
+
+        if self.match_locale {
+            args.push("--match-locale".to_string());
+        }
+
+        for layer in &self.fixture_layers {
+            args.push(format!(
+                "--fixture-layer={}",
+                crate::shell_quote(&layer.to_string_lossy())
+            ));
+        }
+
+        args
+    }
+}
+
+impl RecordedCommands {
+    /// Finds the recording for an invocation, preferring an exact match and falling back to `options`.
+    ///
+    /// This picks a recording even when several match under [`MatchOptions::require_unique_match`]; use
+    /// [`Self::try_find_matching`] to have that reported.
+    pub fn find_matching(
+        &self,
+        binary_name: &str,
+        args: &[String],
+        options: &MatchOptions,
+    ) -> Option<&CommandInvocation> {
+        self.find(binary_name, args, options, false).ok().flatten()
+    }
+
+    /// Like [`Self::find_matching`], but fails with [`CommandeerError::AmbiguousMatch`] when
+    /// [`MatchOptions::require_unique_match`] is set and recordings of more than one invocation match.
+    pub fn try_find_matching(
+        &self,
+        binary_name: &str,
+        args: &[String],
+        options: &MatchOptions,
+    ) -> crate::Result<Option<&CommandInvocation>> {
+        self.find(binary_name, args, options, options.require_unique_match)
+            .map_err(|matches| CommandeerError::AmbiguousMatch {
+                command: std::iter::once(binary_name)
+                    .chain(args.iter().map(String::as_str))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                matches,
+            })
+    }
+
+    /// Every recording of exactly this invocation, in recording order, keyed as `options` key it and within its
+    /// test scope, for picking one by occurrence rather than [`Self::find_matching`].
+    pub fn matching_invocations(
+        &self,
+        binary_name: &str,
+        args: &[String],
+        options: &MatchOptions,
+    ) -> Vec<&CommandInvocation> {
+        let Some(scope) = self.scope(options) else {
+            return vec![];
+        };
+
+        scope
+            .invocations(binary_name, &options.key_args(args))
+            .iter()
+            .filter(|invocation| options.env_matches(invocation))
+            .collect()
+    }
+
+    /// The recordings of [`MatchOptions::test_name`], or all top-level ones without it.
+    fn scope(&self, options: &MatchOptions) -> Option<&RecordedCommands> {
+        match &options.test_name {
+            Some(test_name) => self.for_test(test_name),
+            None => Some(self),
+        }
+    }
+
+    /// The recording [`Self::find_matching`] picks, or with `unique` the number of recordings tied for it if more
+    /// than one.
+    fn find(
+        &self,
+        binary_name: &str,
+        args: &[String],
+        options: &MatchOptions,
+        unique: bool,
+    ) -> Result<Option<&CommandInvocation>, usize> {
+        let Some(scope) = self.scope(options) else {
+            return Ok(None);
+        };
+
+        let args = options.key_args(args);
+        let args = args.as_ref();
+
+        let exact: Vec<_> = scope
+            .invocations(binary_name, args)
+            .iter()
+            .filter(|invocation| options.env_matches(invocation))
+            .collect();
+
+        // Recordings under the invocation's own key are its repeated calls, never ambiguous.
+        if !exact.is_empty() || !options.is_fuzzy() {
+            return Ok(crate::highest_priority(exact));
+        }
+
+        let mut keys: Vec<_> = scope.commands.keys().collect();
+        keys.sort();
+
+        pick(
+            keys.into_iter()
+                .flat_map(|key| {
+                    scope.commands[key]
+                        .iter()
+                        .map(move |invocation| (key, invocation))
+                })
+                .filter(|(_, invocation)| {
+                    invocation.binary_name == binary_name
+                        && options.args_match(&invocation.args, args)
+                        && options.env_matches(invocation)
+                })
+                .collect(),
+            unique,
+        )
+    }
+}
+
+/// The highest priority of the keyed `candidates`, or with `unique` how many keys share that priority if more than
+/// one. Several recordings under one key are repeated calls of the same invocation, so they count once.
+fn pick<'a>(
+    candidates: Vec<(&String, &'a CommandInvocation)>,
+    unique: bool,
+) -> Result<Option<&'a CommandInvocation>, usize> {
+    let best = crate::highest_priority(candidates.iter().map(|(_, invocation)| *invocation));
+
+    if unique && let Some(best) = best {
+        let tied: HashSet<_> = candidates
+            .iter()
+            .filter(|(_, candidate)| candidate.priority.unwrap_or(0) == best.priority.unwrap_or(0))
+            .map(|(key, _)| key)
+            .collect();
+
+        if tied.len() > 1 {
+            return Err(tied.len());
+        }
+    }
+
+    Ok(best)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mixed_numeric_and_string_args() {
+        let tolerance = NumericTolerance {
+            absolute: 2.0,
+            relative: 0.0,
+        };
+
+        assert!(tolerance.matches("30", "31"));
+        assert!(tolerance.matches("1.5", "3.5"));
+        assert!(!tolerance.matches("30", "33"));
+        assert!(!tolerance.matches("30", "thirty"));
+        assert!(tolerance.matches("--timeout", "--timeout"));
+
+        let relative = NumericTolerance {
+            absolute: 0.0,
+            relative: 0.1,
+        };
+
+        assert!(relative.matches("100", "109"));
+        assert!(!relative.matches("100", "120"));
+    }
+
+    #[test]
+    fn json_args_are_canonicalized() {
+        let canonical = |arg: &str| canonicalize_json_args(&[arg.to_string()]).remove(0);
+
+        assert_eq!(canonical(r#"{"b": 2, "a": 1}"#), r#"{"a":1,"b":2}"#);
+        assert_eq!(
+            canonical(r#" [{"z": {"y": 1, "x": [2]}}, "s"]"#),
+            r#"[{"z":{"x":[2],"y":1}},"s"]"#
+        );
+
+        for kept in [
+            "{not json",
+            "[1, 2",
+            "42",
+            r#""quoted""#,
+            "--data={\"a\":1}",
+        ] {
+            assert_eq!(canonical(kept), kept);
+        }
+    }
+
+    #[test]
+    fn ambiguous_match_fails_when_unique_required() {
+        let mut recordings = RecordedCommands::default();
+
+        for width in ["100", "104"] {
+            recordings.add_invocation(
+                serde_json::from_str(&format!(
+                    r#"{{"binary_name":"resize","args":["{width}"],"stdout":"{width}\n","stderr":"","exit_code":0}}"#
+                ))
+                .unwrap(),
+            );
+        }
+
+        let mut matching = MatchOptions {
+            numeric_tolerance: Some(NumericTolerance {
+                absolute: 3.0,
+                relative: 0.0,
+            }),
+            ..MatchOptions::default()
+        };
+        let args = |width: &str| vec![width.to_string()];
+
+        assert_eq!(
+            recordings
+                .try_find_matching("resize", &args("102"), &matching)
+                .unwrap()
+                .map(|invocation| invocation.stdout.as_slice()),
+            Some(b"100\n".as_slice())
+        );
+
+        matching.require_unique_match = true;
+
+        let err = recordings
+            .try_find_matching("resize", &args("102"), &matching)
+            .unwrap_err();
+
+        assert!(
+            matches!(err, CommandeerError::AmbiguousMatch { matches: 2, .. }),
+            "{err}"
+        );
+        assert_eq!(
+            err.to_string(),
+            "`resize 102` matched 2 recordings, but a unique match is required"
+        );
+        assert!(
+            recordings
+                .find_matching("resize", &args("102"), &matching)
+                .is_some()
+        );
+        // An exact match is preferred over fuzzy ones, so it is unique.
+        assert_eq!(
+            recordings
+                .try_find_matching("resize", &args("104"), &matching)
+                .unwrap()
+                .map(|invocation| invocation.stdout.as_slice()),
+            Some(b"104\n".as_slice())
+        );
+    }
+
+    #[test]
+    fn repeated_recordings_are_not_ambiguous() {
+        let mut recordings = RecordedCommands::default();
+
+        for stdout in ["first", "second"] {
+            recordings.add_invocation(
+                serde_json::from_str(&format!(
+                    r#"{{"binary_name":"resize","args":["100"],"stdout":"{stdout}\n","stderr":"","exit_code":0}}"#
+                ))
+                .unwrap(),
+            );
+        }
+
+        let mut matching = MatchOptions {
+            require_unique_match: true,
+            ..MatchOptions::default()
+        };
+
+        assert_eq!(
+            recordings
+                .try_find_matching("resize", &["100".to_string()], &matching)
+                .unwrap()
+                .map(|invocation| invocation.stdout.as_slice()),
+            Some(b"first\n".as_slice())
+        );
+
+        matching.numeric_tolerance = Some(NumericTolerance {
+            absolute: 3.0,
+            relative: 0.0,
+        });
+
+        assert_eq!(
+            recordings
+                .try_find_matching("resize", &["102".to_string()], &matching)
+                .unwrap()
+                .map(|invocation| invocation.stdout.as_slice()),
+            Some(b"first\n".as_slice())
+        );
+    }
+
+    #[test]
+    fn priority_picks_between_fuzzy_matches() {
+        let recording = |arg: &str, priority| CommandInvocation {
+            binary_name: "sleep".to_string(),
+            args: vec![arg.to_string()],
+            stdout: format!("slept {arg}\n").into_bytes(),
+            stderr: vec![],
+            exit_code: 0,
+            priority,
+            ..Default::default()
+        };
+        let matching = MatchOptions {
+            numeric_tolerance: Some(NumericTolerance {
+                absolute: 1.0,
+                relative: 0.0,
+            }),
+            ..MatchOptions::default()
+        };
+        let find = |recordings: &RecordedCommands| {
+            recordings
+                .find_matching("sleep", &["1.5".to_string()], &matching)
+                .map(|invocation| invocation.stdout.clone())
+        };
+
+        let mut recordings = RecordedCommands::default();
+        recordings.add_invocation(recording("1", None));
+        recordings.add_invocation(recording("2", None));
+
+        assert_eq!(find(&recordings).as_deref(), Some(b"slept 1\n".as_slice()));
+
+        recordings.add_invocation(recording("2", Some(1)));
+
+        assert_eq!(find(&recordings).as_deref(), Some(b"slept 2\n".as_slice()));
+        assert_eq!(
+            recordings
+                .find_invocation("sleep", &["2".to_string()])
+                .unwrap()
+                .priority,
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn response_files_are_split_and_nested() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let outer = dir.path().join("outer.rsp");
+        let inner = dir.path().join("inner.rsp");
+
+        fs::write(
+            &outer,
+            format!("-c 'a b' \"\" x\\ y\n@{} @missing.rsp", inner.display()),
+        )
+        .unwrap();
+        fs::write(&inner, "z").unwrap();
+
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            split_response_file("-c 'a b' \"\" x\\ y"),
+            args(&["-c", "a b", "", "x y"])
+        );
+
+        let mut expanded = vec![];
+
+        assert!(expand_response_file(
+            &format!("@{}", outer.display()),
+            &mut expanded,
+            0
+        ));
+        assert_eq!(
+            expanded,
+            args(&["-c", "a b", "", "x y", "z", "@missing.rsp"])
+        );
+
+        let hashed = expand_response_files(&args(&["-o", &format!("@{}", outer.display())]));
+
+        assert_eq!(hashed[0], "-o");
+        assert!(hashed[1].starts_with("@sha256:"), "{hashed:?}");
+        assert_eq!(
+            expand_response_files(&args(&["@missing.rsp"])),
+            args(&["@missing.rsp"])
+        );
+
+        // A file naming itself stops at the depth limit instead of recursing forever.
+        fs::write(&inner, format!("@{}", inner.display())).unwrap();
+
+        assert!(
+            expand_response_files(&args(&[&format!("@{}", inner.display())]))[0]
+                .starts_with("@sha256:")
+        );
+    }
+
+    #[test]
+    fn attached_option_values_are_split() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        let options = args(&["--width", "-o"]);
+
+        assert_eq!(
+            split_option_args(&args(&["--width=80", "-oout.txt", "--height=9"]), &options),
+            args(&["--width", "80", "-o", "out.txt", "--height=9"])
+        );
+        assert_eq!(
+            split_option_args(&args(&["--width", "80", "-o", "out.txt"]), &options),
+            args(&["--width", "80", "-o", "out.txt"])
+        );
+        assert_eq!(
+            split_option_args(&args(&["--widths=1", "--", "--width=80"]), &options),
+            args(&["--widths=1", "--", "--width=80"])
+        );
+
+        let mut recordings = RecordedCommands::default();
+        recordings.add_invocation(CommandInvocation {
+            binary_name: "fmt".to_string(),
+            args: split_option_args(&args(&["--width=80", "-ofile"]), &options),
+            stdout: b"formatted\n".to_vec(),
+            stderr: vec![],
+            exit_code: 0,
+            ..Default::default()
+        });
+
+        let matching = MatchOptions {
+            split_options: options,
+            ..MatchOptions::default()
+        };
+
+        for invoked in [
+            ["--width", "80", "-o", "file"].as_slice(),
+            &["--width=80", "-ofile"],
+        ] {
+            let found = recordings
+                .find_matching("fmt", &args(invoked), &matching)
+                .unwrap();
+
+            assert_eq!(found.stdout, b"formatted\n");
+        }
+
+        assert!(
+            recordings
+                .find_matching(
+                    "fmt",
+                    &args(&["--width=80", "-ofile"]),
+                    &MatchOptions::default()
+                )
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn reordered_flags_match() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        let value_options = args(&["-c"]);
+
+        assert_eq!(
+            sort_flag_args(
+                &args(&["log", "-c", "x", "--oneline", "main"]),
+                &value_options
+            ),
+            args(&["--oneline", "-c", "x", "log", "main"])
+        );
+        assert_eq!(
+            sort_flag_args(&args(&["-v", "a", "-q", "--", "-z", "b"]), &[]),
+            args(&["-q", "-v", "a", "--", "-z", "b"])
+        );
+
+        let mut recordings = RecordedCommands::default();
+        recordings.add_invocation(CommandInvocation {
+            binary_name: "git".to_string(),
+            args: sort_flag_args(&args(&["-c", "x", "log", "--oneline"]), &value_options),
+            stdout: b"abc123 init\n".to_vec(),
+            stderr: vec![],
+            exit_code: 0,
+            ..Default::default()
+        });
+
+        let matching = MatchOptions {
+            unordered_flags: Some(value_options),
+            ..MatchOptions::default()
+        };
+
+        for invoked in [
+            ["-c", "x", "log", "--oneline"].as_slice(),
+            &["log", "--oneline", "-c", "x"],
+        ] {
+            let found = recordings
+                .find_matching("git", &args(invoked), &matching)
+                .unwrap();
+
+            assert_eq!(found.stdout, b"abc123 init\n");
+        }
+
+        assert!(
+            recordings
+                .find_matching(
+                    "git",
+                    &args(&["log", "-c", "x", "--oneline"]),
+                    &MatchOptions::default()
+                )
+                .is_none()
+        );
+        // `-c` taking a value keeps `x` from being reordered as a positional.
+        assert!(
+            recordings
+                .find_matching("git", &args(&["-c", "log", "x", "--oneline"]), &matching)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn temp_paths_are_normalized() {
+        let temp_dir = Path::new("/tmp/");
+
+        assert_eq!(normalize_temp_path("/tmp/xxxx/out", temp_dir), "<TMP>/out");
+        assert_eq!(normalize_temp_path("/tmp/yyyy/out", temp_dir), "<TMP>/out");
+        assert_eq!(normalize_temp_path("/tmp/xxxx", temp_dir), "<TMP>");
+        assert_eq!(
+            normalize_temp_path("--out=/tmp/a/b/c:/tmp/d/e", temp_dir),
+            "--out=<TMP>/b/c:<TMP>/e"
+        );
+        assert_eq!(
+            normalize_temp_path("/var/tmp/xxxx", temp_dir),
+            "/var/tmp/xxxx"
+        );
+        assert_eq!(
+            normalize_temp_path("/tmpfoo/xxxx", temp_dir),
+            "/tmpfoo/xxxx"
+        );
+        assert_eq!(normalize_temp_path("/tmp", temp_dir), "/tmp");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn windows_temp_paths_are_normalized() {
+        let temp_dir = Path::new(r"C:\Users\me\AppData\Local\Temp\");
+
+        assert_eq!(
+            normalize_temp_path(r"c:\users\me\appdata\local\temp\xxxx\out", temp_dir),
+            r"<TMP>\out"
+        );
+        assert_eq!(
+            normalize_temp_path("C:/Users/me/AppData/Local/Temp/yyyy/out", temp_dir),
+            "<TMP>/out"
+        );
+    }
+}
@@ -0,0 +1,167 @@
+use crate::{CommandeerError, Result};
+use serde::Deserialize;
+use std::{path::Path, process::Command};
+
+/// Package and binary target to build as the mock runner; see [`crate::CommandeerBuilder::mock_bin`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct MockBin {
+    pub(crate) package: String,
+    pub(crate) bin: String,
+}
+
+#[derive(Deserialize)]
+struct Metadata {
+    packages: Vec<Package>,
+}
+
+#[derive(Deserialize)]
+struct Package {
+    name: String,
+    dependencies: Vec<Dependency>,
+    targets: Vec<Target>,
+}
+
+#[derive(Deserialize)]
+struct Dependency {
+    name: String,
+    /// `dev` or `build`, or `None` for a normal dependency.
+    kind: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Target {
+    name: String,
+    kind: Vec<String>,
+}
+
+/// Finds the binary target in the workspace of `manifest` that depends on `commandeer-test`, for workspaces that
+/// wrap the mock CLI in a package of their own.
+///
+/// Only normal dependencies count, and `under_test` is skipped: a binary crate testing itself with commandeer-test
+/// as a dev-dependency is the code under test, not a mock runner.
+///
+/// Returns `None` when there is none, so the `commandeer` binary of this crate is used, and fails when there are
+/// several, since the right one can't be told apart.
+pub(crate) fn detect(manifest: &Path, under_test: Option<&str>) -> Result<Option<MockBin>> {
+    let cargo = std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+
+    let output = Command::new(cargo)
+        .args([
+            "metadata",
+            "--format-version",
+            "1",
+            "--no-deps",
+            "--manifest-path",
+        ])
+        .arg(manifest)
+        .output()?;
+
+    if !output.status.success() {
+        return Err(CommandeerError::MockBinDetection(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+
+    let metadata: Metadata = serde_json::from_slice(&output.stdout)?;
+
+    let mut candidates: Vec<MockBin> = metadata
+        .packages
+        .into_iter()
+        .filter(|package| {
+            Some(package.name.as_str()) != under_test
+                && package.dependencies.iter().any(|dependency| {
+                    dependency.name == env!("CARGO_PKG_NAME") && dependency.kind.is_none()
+                })
+        })
+        .flat_map(|package| {
+            package
+                .targets
+                .into_iter()
+                .filter(|target| target.kind.iter().any(|kind| kind == "bin"))
+                .map(move |target| MockBin {
+                    package: package.name.clone(),
+                    bin: target.name,
+                })
+        })
+        .collect();
+
+    match candidates.len() {
+        0 | 1 => Ok(candidates.pop()),
+        _ => {
+            let names: Vec<String> = candidates
+                .iter()
+                .map(|candidate| format!("{}/{}", candidate.package, candidate.bin))
+                .collect();
+
+            Err(CommandeerError::MockBinDetection(format!(
+                "several binaries depend on {}: {}; pick one with `CommandeerBuilder::mock_bin`",
+                env!("CARGO_PKG_NAME"),
+                names.join(", ")
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes package `name` with a binary target, depending on commandeer-test under `section` if given, e.g.
+    /// `dev-dependencies`.
+    fn write_package(root: &Path, name: &str, section: Option<&str>) {
+        let dependency = section.map_or_else(String::new, |section| {
+            format!(
+                "[{section}]\ncommandeer-test = {{ path = {:?} }}",
+                env!("CARGO_MANIFEST_DIR")
+            )
+        });
+
+        std::fs::create_dir_all(root.join(name).join("src")).unwrap();
+        std::fs::write(root.join(name).join("src/main.rs"), "fn main() {}\n").unwrap();
+        std::fs::write(
+            root.join(name).join("Cargo.toml"),
+            format!(
+                "[package]\nname = \"{name}\"\nversion = \"0.1.0\"\nedition = \"2024\"\n\n\
+                 [[bin]]\nname = \"{name}-bin\"\npath = \"src/main.rs\"\n\n{dependency}\n"
+            ),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn detects_bin_depending_on_commandeer() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let manifest = dir.path().join("Cargo.toml");
+
+        write_package(dir.path(), "mock-cli", Some("dependencies"));
+        write_package(dir.path(), "app", Some("dev-dependencies"));
+        write_package(dir.path(), "tested-cli", Some("dependencies"));
+        std::fs::write(
+            &manifest,
+            "[workspace]\nmembers = [\"mock-cli\", \"app\", \"tested-cli\"]\nresolver = \"3\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            detect(&manifest, Some("tested-cli")).unwrap(),
+            Some(MockBin {
+                package: "mock-cli".to_string(),
+                bin: "mock-cli-bin".to_string(),
+            })
+        );
+
+        write_package(dir.path(), "second-cli", Some("dependencies"));
+        std::fs::write(
+            &manifest,
+            "[workspace]\nmembers = [\"mock-cli\", \"app\", \"second-cli\"]\nresolver = \"3\"\n",
+        )
+        .unwrap();
+
+        let err = detect(&manifest, None).unwrap_err().to_string();
+
+        assert!(
+            err.contains("mock-cli/mock-cli-bin, second-cli/second-cli-bin"),
+            "{err}"
+        );
+    }
+}
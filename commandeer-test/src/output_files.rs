@@ -0,0 +1,67 @@
+//! Files a recorded command writes through an output option, see [`crate::RecordOptions::output_file_options`].
+
+use crate::{CommandInvocation, Result, split_option_args};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::PathBuf};
+
+/// A file written by a recorded command, recreated when the invocation is replayed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WrittenFile {
+    /// Path as passed to the command, so a relative path is recreated relative to the replaying process's cwd.
+    pub path: PathBuf,
+    /// Stored as a string when it is UTF-8 and as an array of bytes otherwise.
+    #[serde(with = "crate::stdin::bytes")]
+    pub contents: Vec<u8>,
+}
+
+/// Snapshots the files named by the value of any of `options` in `args`, e.g. `out.txt` in `tool -o out.txt` when
+/// `options` holds `-o`. Values attached as `--output=out.txt` or `-oout.txt` count too; files the command didn't
+/// create are skipped.
+pub(crate) fn capture(args: &[String], options: &[String]) -> Result<Vec<WrittenFile>> {
+    if options.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let args = split_option_args(args, options);
+    let mut files = vec![];
+
+    for pair in args.windows(2) {
+        if pair[0] == "--" {
+            break;
+        }
+
+        if !options.contains(&pair[0]) {
+            continue;
+        }
+
+        let path = PathBuf::from(&pair[1]);
+
+        if path.is_file() {
+            files.push(WrittenFile {
+                contents: fs::read(&path)?,
+                path,
+            });
+        }
+    }
+
+    Ok(files)
+}
+
+impl CommandInvocation {
+    /// Recreates [`Self::writes_files`], creating missing parent directories, as the real command did when recorded.
+    pub fn write_files(&self) -> Result<()> {
+        for file in &self.writes_files {
+            if let Some(parent) = file
+                .path
+                .parent()
+                .filter(|parent| !parent.as_os_str().is_empty())
+            {
+                fs::create_dir_all(parent)?;
+            }
+
+            fs::write(&file.path, &file.contents)?;
+        }
+
+        Ok(())
+    }
+}
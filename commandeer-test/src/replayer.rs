@@ -0,0 +1,214 @@
+use crate::{
+    CommandInvocation, MatchOptions, RecordedCommands, Result, compression, override_path,
+    parse_recordings,
+};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+type HitHook = Arc<dyn Fn(&CommandInvocation) + Send + Sync>;
+type MissHook = Arc<dyn Fn(&str, &[String]) + Send + Sync>;
+
+/// Serves a fixture's recordings in process, for code that runs commands through an injectable runner rather than
+/// `std::process::Command`.
+///
+/// Unlike [`crate::Commandeer`] this neither touches `PATH` nor spawns anything, and the fixture is parsed once up
+/// front. Stdout stored with [`crate::CommandeerBuilder::blob_store`] is left as [`CommandInvocation::stdout_blob`].
+#[derive(Clone)]
+pub struct Replayer {
+    fixture: PathBuf,
+    recordings: RecordedCommands,
+    options: MatchOptions,
+    on_hit: Option<HitHook>,
+    on_miss: Option<MissHook>,
+}
+
+impl fmt::Debug for Replayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Replayer")
+            .field("fixture", &self.fixture)
+            .field("recordings", &self.recordings)
+            .field("options", &self.options)
+            .field("on_hit", &self.on_hit.is_some())
+            .field("on_miss", &self.on_miss.is_some())
+            .finish()
+    }
+}
+
+impl Replayer {
+    /// Loads the recordings of `fixture`, which must exist, with any [`override_path`] sidecar laid over them.
+    pub fn new(fixture: impl AsRef<Path>) -> Result<Self> {
+        let fixture = fixture.as_ref().to_path_buf();
+        let recordings = load(&fixture, &[])?;
+
+        Ok(Self {
+            fixture,
+            recordings,
+            options: MatchOptions::default(),
+            on_hit: None,
+            on_miss: None,
+        })
+    }
+
+    /// Match invocations as `options` describes instead of exactly, reloading the fixture over its
+    /// [`MatchOptions::fixture_layers`] if it has any.
+    pub fn with_options(mut self, options: MatchOptions) -> Result<Self> {
+        if !options.fixture_layers.is_empty() {
+            self.recordings = load(&self.fixture, &options.fixture_layers)?;
+        }
+
+        self.options = options;
+        Ok(self)
+    }
+
+    /// Call `hook` with each recording [`Self::run`] replays, e.g. to count hits or log them.
+    pub fn on_hit(mut self, hook: impl Fn(&CommandInvocation) + Send + Sync + 'static) -> Self {
+        self.on_hit = Some(Arc::new(hook));
+        self
+    }
+
+    /// Call `hook` with the command and args of each invocation [`Self::run`] has no recording for.
+    pub fn on_miss(mut self, hook: impl Fn(&str, &[String]) + Send + Sync + 'static) -> Self {
+        self.on_miss = Some(Arc::new(hook));
+        self
+    }
+
+    /// The recording for `command` invoked with `args`, or `None` if there is none.
+    ///
+    /// # Panics
+    ///
+    /// If the recording's stdout template can't be read, or more than one recording matches under
+    /// [`crate::MatchOptions::require_unique_match`]; see [`Self::try_run`].
+    pub fn run(&self, command: &str, args: &[&str]) -> Option<CommandInvocation> {
+        self.try_run(command, args)
+            .expect("Failed to replay recording")
+    }
+
+    /// Like [`Self::run`], but returns an error instead of panicking when a stdout template can't be read or the
+    /// match is ambiguous.
+    pub fn try_run(&self, command: &str, args: &[&str]) -> Result<Option<CommandInvocation>> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+
+        let Some(mut invocation) = self
+            .recordings
+            .try_find_matching(command, &args, &self.options)?
+            .cloned()
+        else {
+            if let Some(on_miss) = &self.on_miss {
+                on_miss(command, &args);
+            }
+
+            return Ok(None);
+        };
+
+        invocation.render_stdout_template(&self.fixture, &args)?;
+
+        if let Some(on_hit) = &self.on_hit {
+            on_hit(&invocation);
+        }
+
+        Ok(Some(invocation))
+    }
+}
+
+/// The recordings of `fixture`, which must exist, laid over those of `layers` and under its [`override_path`] sidecar.
+/// Layers and a sidecar that don't exist are skipped.
+fn load(fixture: &Path, layers: &[PathBuf]) -> Result<RecordedCommands> {
+    let read = |path: &Path| parse_recordings(path, &compression::read(path)?);
+    let mut recordings = RecordedCommands::default();
+
+    for layer in layers {
+        if std::fs::exists(layer)? {
+            recordings.overlay(read(layer)?);
+        }
+    }
+
+    recordings.overlay(read(fixture)?);
+
+    let override_file = override_path(fixture);
+
+    if std::fs::exists(&override_file)? {
+        recordings.overlay(read(&override_file)?);
+    }
+
+    Ok(recordings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_recordings_in_process() {
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("testcmds/cmds_my_test.json");
+        let replayer = Replayer::new(&fixture).unwrap();
+
+        let invocation = replayer.run("echo", &["hello"]).unwrap();
+
+        assert_eq!(invocation.stdout, b"hello\n");
+        assert_eq!(invocation.exit_code, 0);
+        assert!(replayer.run("echo", &["goodbye"]).is_none());
+        assert!(Replayer::new(fixture.with_file_name("missing.json")).is_err());
+    }
+
+    #[test]
+    fn hooks_see_hits_and_misses() {
+        use std::sync::Mutex;
+
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("testcmds/cmds_my_test.json");
+        let hits = Arc::new(Mutex::new(vec![]));
+        let misses = Arc::new(Mutex::new(vec![]));
+
+        let replayer = Replayer::new(&fixture)
+            .unwrap()
+            .on_hit({
+                let hits = hits.clone();
+                move |invocation| hits.lock().unwrap().push(invocation.stdout.clone())
+            })
+            .on_miss({
+                let misses = misses.clone();
+                move |command, args| {
+                    misses
+                        .lock()
+                        .unwrap()
+                        .push((command.to_string(), args.to_vec()))
+                }
+            });
+
+        replayer.run("echo", &["hello"]);
+        replayer.run("echo", &["goodbye"]);
+
+        assert_eq!(*hits.lock().unwrap(), [b"hello\n"]);
+        assert_eq!(
+            *misses.lock().unwrap(),
+            [("echo".to_string(), vec!["goodbye".to_string()])]
+        );
+    }
+    #[test]
+    fn fixture_layers_are_replayed_beneath() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let base = dir.path().join("base.json");
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("testcmds/cmds_my_test.json");
+        std::fs::write(
+            &base,
+            r#"{"commands":{"echo:goodbye":[{"binary_name":"echo","args":["goodbye"],"stdout":"base\n","stderr":"","exit_code":0}]}}"#,
+        )
+        .unwrap();
+
+        let replayer = Replayer::new(&fixture)
+            .unwrap()
+            .with_options(MatchOptions {
+                fixture_layers: vec![base],
+                ..MatchOptions::default()
+            })
+            .unwrap();
+
+        assert_eq!(
+            replayer.run("echo", &["goodbye"]).unwrap().stdout,
+            b"base\n"
+        );
+        assert_eq!(replayer.run("echo", &["hello"]).unwrap().stdout, b"hello\n");
+    }
+}
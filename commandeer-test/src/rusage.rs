@@ -0,0 +1,106 @@
+//! Resource usage of recorded commands, captured with [`crate::RecordOptions::record_rusage`], and the open file
+//! descriptors they start with, captured with [`crate::RecordOptions::record_fd_count`].
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// CPU time and peak memory of a recorded command, as `wait4` reports them when it exits. Descendants the command
+/// waited for are included.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResourceUsage {
+    /// CPU time spent in user mode, in milliseconds.
+    pub user_ms: u64,
+    /// CPU time spent in the kernel on the command's behalf, in milliseconds.
+    pub system_ms: u64,
+    /// Peak resident set size, in KiB.
+    pub max_rss_kib: u64,
+}
+
+impl fmt::Display for ResourceUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "user {}ms, system {}ms, max rss {} KiB",
+            self.user_ms, self.system_ms, self.max_rss_kib
+        )
+    }
+}
+
+/// How far the open file descriptor count at replay may be from [`crate::CommandInvocation::fd_count`] before the
+/// mock warns about it.
+pub const FD_COUNT_DRIFT: usize = 4;
+
+/// How many file descriptors this process has open, from `/proc/self/fd` or else `/dev/fd`. `None` where neither
+/// can be listed, including everywhere but Unix.
+pub fn open_fd_count() -> Option<usize> {
+    if !cfg!(unix) {
+        return None;
+    }
+
+    ["/proc/self/fd", "/dev/fd"].into_iter().find_map(|dir| {
+        let entries = std::fs::read_dir(dir).ok()?;
+
+        // Listing the dir holds a descriptor of its own open.
+        Some(entries.count().saturating_sub(1))
+    })
+}
+
+/// Reaps the child `pid` if it has exited, returning its exit status and resource usage, or `None` if it is still
+/// running.
+///
+/// The child's [`std::process::Child`] must not be waited on afterwards, as the status is no longer there to collect.
+#[cfg(unix)]
+pub(crate) fn try_wait4(
+    pid: u32,
+) -> std::io::Result<Option<(std::process::ExitStatus, ResourceUsage)>> {
+    use std::os::unix::process::ExitStatusExt as _;
+
+    let pid = libc::pid_t::try_from(pid).map_err(std::io::Error::other)?;
+    let mut status = 0;
+    // `rusage` is plain integers, for which all zeroes is a valid value.
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+
+    loop {
+        match unsafe { libc::wait4(pid, &mut status, libc::WNOHANG, &mut usage) } {
+            0 => return Ok(None),
+            -1 => {
+                let error = std::io::Error::last_os_error();
+
+                if error.kind() != std::io::ErrorKind::Interrupted {
+                    return Err(error);
+                }
+            }
+            _ => {
+                return Ok(Some((
+                    std::process::ExitStatus::from_raw(status),
+                    ResourceUsage::from_rusage(&usage),
+                )));
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+impl ResourceUsage {
+    fn from_rusage(usage: &libc::rusage) -> Self {
+        let ms = |time: libc::timeval| {
+            let secs = u64::try_from(time.tv_sec).unwrap_or_default();
+            let micros = u64::try_from(time.tv_usec).unwrap_or_default();
+
+            secs * 1000 + micros / 1000
+        };
+
+        let max_rss = u64::try_from(usage.ru_maxrss).unwrap_or_default();
+
+        Self {
+            user_ms: ms(usage.ru_utime),
+            system_ms: ms(usage.ru_stime),
+            // macOS reports bytes where Linux and the BSDs report KiB.
+            max_rss_kib: if cfg!(target_os = "macos") {
+                max_rss / 1024
+            } else {
+                max_rss
+            },
+        }
+    }
+}
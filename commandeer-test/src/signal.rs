@@ -0,0 +1,62 @@
+use std::{fmt, process::ExitStatus, str::FromStr};
+
+/// How a mock replays a recording of a command that was killed by a signal, see [`crate::CommandInvocation::signal`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SignalReplay {
+    /// Kill the mock with the recorded signal, as the real command died. Some CI runners react to their children
+    /// dying by signal, so this is opt-in.
+    Raise,
+    /// Exit with the recorded exit code, which for a signal is the 128 + signal number a shell reports.
+    #[default]
+    Code,
+}
+
+impl fmt::Display for SignalReplay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignalReplay::Raise => write!(f, "raise"),
+            SignalReplay::Code => write!(f, "code"),
+        }
+    }
+}
+
+impl FromStr for SignalReplay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raise" => Ok(SignalReplay::Raise),
+            "code" => Ok(SignalReplay::Code),
+            _ => Err(format!(
+                "unknown signal replay `{s}`, expected raise or code"
+            )),
+        }
+    }
+}
+
+/// The signal that terminated the process `status` is from, if any.
+pub(crate) fn terminating(status: &ExitStatus) -> Option<i32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt as _;
+
+        status.signal()
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = status;
+
+        None
+    }
+}
+
+/// Kills this process with `signal`, restoring its default action first. Returns if that action doesn't terminate
+/// the process, e.g. for `SIGCHLD`.
+#[cfg(unix)]
+pub(crate) fn raise(signal: i32) {
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+    }
+}
@@ -0,0 +1,170 @@
+//! Storage and display of the stdin captured with [`crate::RecordOptions::capture_stdin`].
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    fmt::Write as _,
+    io::{self, PipeReader, Read as _, Write as _},
+    process::{Command, Stdio},
+    thread::JoinHandle,
+};
+
+/// Writes stdin to a command on a thread, closing it when done, so a command that only reads part of its input
+/// can't deadlock against a full pipe.
+///
+/// A read end of the pipe is kept, so once the command has exited whatever it left unread can be drained to tell
+/// how much it consumed, see [`crate::CommandInvocation::stdin_consumed`].
+pub(crate) struct StdinFeed {
+    unread: PipeReader,
+    len: usize,
+    writer: JoinHandle<()>,
+}
+
+impl StdinFeed {
+    /// Feeds `stdin` to `command` once spawned, or runs it with stdin closed when there is none.
+    pub(crate) fn attach(
+        command: &mut Command,
+        stdin: Option<Vec<u8>>,
+    ) -> io::Result<Option<Self>> {
+        let Some(stdin) = stdin else {
+            command.stdin(Stdio::null());
+
+            return Ok(None);
+        };
+
+        let (unread, mut pipe) = io::pipe()?;
+        command.stdin(unread.try_clone()?);
+
+        let len = stdin.len();
+        let writer = std::thread::spawn(move || {
+            // The command may exit without reading all of it.
+            let _ = pipe.write_all(&stdin);
+        });
+
+        Ok(Some(Self {
+            unread,
+            len,
+            writer,
+        }))
+    }
+
+    /// How many bytes the command read, once it has exited.
+    ///
+    /// Draining the rest unblocks the writer if the pipe is full, and ends once it is done.
+    pub(crate) fn consumed(mut self) -> io::Result<usize> {
+        let mut rest = vec![];
+        self.unread.read_to_end(&mut rest)?;
+        let _ = self.writer.join();
+
+        Ok(self.len - rest.len())
+    }
+}
+
+/// Bytes shown by [`describe`] for stdin that isn't UTF-8.
+const PREVIEW_BYTES: usize = 64;
+
+/// Stdin as stored in a fixture: a string when it is UTF-8, so fixtures stay readable, and an array of bytes
+/// otherwise.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Stored {
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+pub(crate) fn serialize<S: Serializer>(
+    stdin: &Option<Vec<u8>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    stdin
+        .as_ref()
+        .map(|bytes| match std::str::from_utf8(bytes) {
+            Ok(text) => Stored::Text(text.to_string()),
+            Err(_) => Stored::Bytes(bytes.clone()),
+        })
+        .serialize(serializer)
+}
+
+pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<u8>>, D::Error> {
+    Ok(
+        Option::<Stored>::deserialize(deserializer)?.map(|stored| match stored {
+            Stored::Text(text) => text.into_bytes(),
+            Stored::Bytes(bytes) => bytes,
+        }),
+    )
+}
+
+/// The helpers above for bytes that are always present, e.g. [`crate::WrittenFile::contents`].
+pub(crate) mod bytes {
+    use super::Stored;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(crate) fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        super::serialize(&Some(bytes.to_vec()), serializer)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<u8>, D::Error> {
+        Ok(match Stored::deserialize(deserializer)? {
+            Stored::Text(text) => text.into_bytes(),
+            Stored::Bytes(bytes) => bytes,
+        })
+    }
+}
+
+/// Renders captured stdin for `list --show-stdin`: quoted when it is UTF-8, otherwise its length and a hexdump of
+/// the first [`PREVIEW_BYTES`] bytes.
+pub(crate) fn describe(stdin: &[u8]) -> String {
+    if let Ok(text) = std::str::from_utf8(stdin) {
+        return format!("{text:?}");
+    }
+
+    let mut out = format!("{} bytes", stdin.len());
+
+    for (row, chunk) in stdin[..stdin.len().min(PREVIEW_BYTES)]
+        .chunks(16)
+        .enumerate()
+    {
+        let _ = write!(out, "\n      {:08x} ", row * 16);
+
+        for byte in chunk {
+            let _ = write!(out, " {byte:02x}");
+        }
+
+        let ascii: String = chunk
+            .iter()
+            .map(|&byte| {
+                if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+
+        let _ = write!(out, "{:pad$}  |{ascii}|", "", pad = (16 - chunk.len()) * 3);
+    }
+
+    if stdin.len() > PREVIEW_BYTES {
+        out.push_str("\n      ...");
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binary_stdin_is_hexdumped() {
+        assert_eq!(describe(b"hello\n"), r#""hello\n""#);
+        assert_eq!(
+            describe(&[0xff, b'a', b'b', 0x00]),
+            "4 bytes\n      00000000  ff 61 62 00                                      |.ab.|"
+        );
+        assert!(describe(&[0xff; 100]).ends_with("\n      ..."));
+    }
+}
@@ -0,0 +1,80 @@
+use crate::{
+    Result,
+    call_log::{append_json_line, read_json_lines},
+};
+use serde::{Deserialize, Serialize};
+use std::{fmt, path::Path};
+
+/// Environment variable naming a JSON-lines file every mock appends its [`SummaryEntry`] to.
+///
+/// Unlike the per-instance call log it is shared by every [`crate::Commandeer`] in a run, so pointing it at one path
+/// for a whole suite gives an overview of fixture usage.
+pub const COMMANDEER_SUMMARY: &str = "COMMANDEER_SUMMARY";
+
+/// What a mock did for one invocation.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Outcome {
+    /// Served from an existing recording.
+    Replayed,
+    /// No recording matched.
+    Missed,
+    /// Ran the real command and recorded it.
+    Recorded,
+}
+
+/// One mock invocation, as appended to the [`COMMANDEER_SUMMARY`] file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SummaryEntry {
+    pub binary_name: String,
+    pub args: Vec<String>,
+    pub outcome: Outcome,
+    /// Bytes of stdout and stderr the mock emitted.
+    pub bytes: u64,
+}
+
+/// Totals over a [`COMMANDEER_SUMMARY`] file, as printed by `commandeer summarize`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub replayed: usize,
+    pub missed: usize,
+    pub recorded: usize,
+    pub bytes: u64,
+}
+
+impl Summary {
+    pub fn from_entries<'a>(entries: impl IntoIterator<Item = &'a SummaryEntry>) -> Self {
+        let mut summary = Self::default();
+
+        for entry in entries {
+            match entry.outcome {
+                Outcome::Replayed => summary.replayed += 1,
+                Outcome::Missed => summary.missed += 1,
+                Outcome::Recorded => summary.recorded += 1,
+            }
+
+            summary.bytes += entry.bytes;
+        }
+
+        summary
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "replayed: {}", self.replayed)?;
+        writeln!(f, "missed: {}", self.missed)?;
+        writeln!(f, "recorded: {}", self.recorded)?;
+        writeln!(f, "bytes: {}", self.bytes)
+    }
+}
+
+/// Appends `entry` to the summary file at `path`, creating it if needed.
+pub fn append_summary(path: &Path, entry: &SummaryEntry) -> Result<()> {
+    append_json_line(path, entry)
+}
+
+/// Reads every entry of the summary file at `path`. A missing file means no mock ran.
+pub fn read_summary(path: &Path) -> Result<Vec<SummaryEntry>> {
+    read_json_lines(path)
+}
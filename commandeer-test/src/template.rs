@@ -0,0 +1,41 @@
+use std::env;
+
+/// Expands `$ARGn`, `${NAME}` and `$$` in `template`, as described on
+/// [`crate::CommandInvocation::render_stdout_template`]. Any other `$` is kept as is.
+pub(crate) fn render(template: &str, args: &[String]) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(dollar) = rest.find('$') {
+        rendered.push_str(&rest[..dollar]);
+        rest = &rest[dollar + 1..];
+
+        if let Some(after) = rest.strip_prefix('$') {
+            rendered.push('$');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("ARG")
+            && let digits =
+                after.len() - after.trim_start_matches(|c: char| c.is_ascii_digit()).len()
+            && digits > 0
+        {
+            let index: usize = after[..digits].parse().unwrap_or(0);
+
+            if let Some(arg) = index.checked_sub(1).and_then(|i| args.get(i)) {
+                rendered.push_str(arg);
+            }
+
+            rest = &after[digits..];
+        } else if let Some(after) = rest.strip_prefix('{')
+            && let Some(end) = after.find('}')
+        {
+            rendered.push_str(&env::var(&after[..end]).unwrap_or_default());
+            rest = &after[end + 1..];
+        } else {
+            rendered.push('$');
+        }
+    }
+
+    rendered.push_str(rest);
+
+    rendered
+}
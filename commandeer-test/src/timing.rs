@@ -0,0 +1,41 @@
+//! Latency of recorded invocations, captured with [`crate::RecordOptions::record_timing`].
+
+use crate::CommandInvocation;
+use std::fmt;
+
+/// Spread of [`CommandInvocation::duration_ms`] over the recordings of one invocation, as shown by `list --timing`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimingStats {
+    pub min_ms: u64,
+    pub max_ms: u64,
+    pub mean_ms: f64,
+    /// Recordings with a duration; those made without timing are left out.
+    pub samples: usize,
+}
+
+impl TimingStats {
+    /// The stats over the timed recordings in `invocations`, or `None` if none were timed.
+    pub fn from_invocations(invocations: &[CommandInvocation]) -> Option<Self> {
+        let durations: Vec<u64> = invocations
+            .iter()
+            .filter_map(|invocation| invocation.duration_ms)
+            .collect();
+
+        Some(Self {
+            min_ms: *durations.iter().min()?,
+            max_ms: *durations.iter().max()?,
+            mean_ms: durations.iter().sum::<u64>() as f64 / durations.len() as f64,
+            samples: durations.len(),
+        })
+    }
+}
+
+impl fmt::Display for TimingStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "min {}ms, max {}ms, mean {:.1}ms over {}",
+            self.min_ms, self.max_ms, self.mean_ms, self.samples
+        )
+    }
+}
@@ -0,0 +1,161 @@
+use crate::CommandInvocation;
+use regex::Regex;
+use std::{env, fmt, str::FromStr, sync::LazyLock};
+
+/// Replaces timestamps masked by [`BuiltinTransform::MaskTimestamps`].
+pub const TIMESTAMP_TOKEN: &str = "<TIMESTAMP>";
+
+/// Replaces the recorder's home directory masked by [`BuiltinTransform::MaskHome`].
+pub const HOME_TOKEN: &str = "<HOME>";
+
+static TIMESTAMP: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"\d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}:\d{2}(?:\.\d+)?(?:Z|[+-]\d{2}:?\d{2})?|\b\d{2}:\d{2}:\d{2}(?:\.\d+)?\b",
+    )
+    .expect("Timestamp pattern is valid")
+});
+
+/// Canonicalizes a recorded invocation before it is saved, keeping fixture diffs stable.
+#[derive(Debug, Clone, Copy)]
+pub enum Transform {
+    Builtin(BuiltinTransform),
+    /// Arbitrary in-process transform. Only honoured by the library record functions: mocks run in a separate
+    /// process, so [`crate::CommandeerBuilder::transform`] takes a [`BuiltinTransform`].
+    Custom(fn(&mut CommandInvocation)),
+}
+
+/// Transforms available by name, including to mocks and the CLI (`--transform mask-timestamps`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuiltinTransform {
+    /// Replace ISO-8601 date-times and `HH:MM:SS` times with [`TIMESTAMP_TOKEN`].
+    MaskTimestamps,
+    /// Strip trailing whitespace from every line.
+    TrimTrailingWhitespace,
+    /// Replace the `$HOME` directory with [`HOME_TOKEN`], so fixtures don't embed the recorder's home. A no-op when
+    /// `HOME` is unset, empty or `/`; see [`CommandInvocation::expand_home`] to restore it at replay.
+    MaskHome,
+    /// Rewrite `\r\n` line endings as `\n`, so fixtures recorded on Windows and Unix agree. Opt-in, since it would
+    /// corrupt binary output; see [`CommandInvocation::native_line_endings`] to restore `\r\n` at replay.
+    NormalizeLineEndings,
+}
+
+/// `$HOME` without trailing separators, or `None` when unset or the filesystem root.
+fn home() -> Option<String> {
+    let home = env::var("HOME").ok()?;
+    let home = home.trim_end_matches(std::path::is_separator);
+
+    (!home.is_empty()).then(|| home.to_string())
+}
+
+/// Replaces `home` in `output` where it is a whole path component, so `/home/al` doesn't match `/home/alice`.
+fn mask_home(output: &str, home: &str) -> String {
+    let mut masked = String::with_capacity(output.len());
+    let mut rest = output;
+
+    while let Some(found) = rest.find(home) {
+        let after = &rest[found + home.len()..];
+        let whole = !after
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_alphanumeric() || ['.', '_', '-'].contains(&c));
+
+        masked.push_str(&rest[..found]);
+        masked.push_str(if whole { HOME_TOKEN } else { home });
+        rest = after;
+    }
+
+    masked.push_str(rest);
+
+    masked
+}
+
+impl CommandInvocation {
+    /// Replaces [`HOME_TOKEN`] in `stdout` and `stderr` with the current `$HOME`, undoing
+    /// [`BuiltinTransform::MaskHome`] for code that expects real paths. Leaves the token when `HOME` is unset.
+    pub fn expand_home(&mut self) {
+        let Some(home) = home() else {
+            return;
+        };
+
+        self.edit_outputs(|output| output.replace(HOME_TOKEN, &home));
+    }
+
+    /// Rewrites `\n` line endings in `stdout` and `stderr` as `\r\n` on Windows, undoing
+    /// [`BuiltinTransform::NormalizeLineEndings`] for code that expects the platform's line endings. A no-op
+    /// elsewhere.
+    pub fn native_line_endings(&mut self) {
+        if !cfg!(windows) {
+            return;
+        }
+
+        self.edit_outputs(|output| output.replace("\r\n", "\n").replace('\n', "\r\n"));
+    }
+}
+
+impl Transform {
+    pub fn apply(&self, invocation: &mut CommandInvocation) {
+        match self {
+            Transform::Builtin(builtin) => builtin.apply(invocation),
+            Transform::Custom(transform) => transform(invocation),
+        }
+    }
+}
+
+impl From<BuiltinTransform> for Transform {
+    fn from(builtin: BuiltinTransform) -> Self {
+        Transform::Builtin(builtin)
+    }
+}
+
+impl BuiltinTransform {
+    pub fn apply(&self, invocation: &mut CommandInvocation) {
+        let home = home();
+
+        invocation.edit_outputs(|output| match self {
+            BuiltinTransform::MaskTimestamps => {
+                TIMESTAMP.replace_all(output, TIMESTAMP_TOKEN).into_owned()
+            }
+            BuiltinTransform::TrimTrailingWhitespace => output
+                .split_inclusive('\n')
+                .map(|line| {
+                    let newline = if line.ends_with('\n') { "\n" } else { "" };
+
+                    format!("{}{newline}", line.trim_end())
+                })
+                .collect(),
+            BuiltinTransform::MaskHome => match &home {
+                Some(home) => mask_home(output, home),
+                None => output.to_string(),
+            },
+            BuiltinTransform::NormalizeLineEndings => output.replace("\r\n", "\n"),
+        });
+    }
+}
+
+impl fmt::Display for BuiltinTransform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuiltinTransform::MaskTimestamps => write!(f, "mask-timestamps"),
+            BuiltinTransform::TrimTrailingWhitespace => write!(f, "trim-trailing-whitespace"),
+            BuiltinTransform::MaskHome => write!(f, "mask-home"),
+            BuiltinTransform::NormalizeLineEndings => write!(f, "normalize-line-endings"),
+        }
+    }
+}
+
+impl FromStr for BuiltinTransform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mask-timestamps" => Ok(BuiltinTransform::MaskTimestamps),
+            "trim-trailing-whitespace" => Ok(BuiltinTransform::TrimTrailingWhitespace),
+            "mask-home" => Ok(BuiltinTransform::MaskHome),
+            "normalize-line-endings" => Ok(BuiltinTransform::NormalizeLineEndings),
+            _ => Err(format!(
+                "unknown transform `{s}`, expected mask-timestamps, trim-trailing-whitespace, mask-home or \
+                 normalize-line-endings"
+            )),
+        }
+    }
+}
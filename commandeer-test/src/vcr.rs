@@ -0,0 +1,215 @@
+//! VCR-style cassettes: fixtures stored as YAML with an `interactions` list, for sharing recordings with tooling
+//! that speaks that format.
+//!
+//! Each interaction is a `request` (the command, its args and any recorded stdin or environment) and a `response`
+//! (stdout, stderr, exit code and every other field of the [`CommandInvocation`]), so a fixture survives being
+//! converted to a cassette and back unchanged.
+
+use crate::{CommandeerError, RecordedCommands, Result};
+use std::path::Path;
+
+/// Whether the fixture at `path` is a cassette: its name ends in `.yaml` or `.yml`, before any compression or
+/// encryption extension.
+pub(crate) fn is_cassette(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return false;
+    };
+
+    let name = name
+        .trim_end_matches(".enc")
+        .trim_end_matches(".gz")
+        .trim_end_matches(".zst");
+
+    name.ends_with(".yaml") || name.ends_with(".yml")
+}
+
+#[cfg(not(feature = "vcr"))]
+pub(crate) fn parse(path: &Path, _contents: &str) -> Result<RecordedCommands> {
+    Err(CommandeerError::UnsupportedCassette {
+        path: path.to_path_buf(),
+    })
+}
+
+#[cfg(not(feature = "vcr"))]
+pub(crate) fn to_string(path: &Path, _recordings: &RecordedCommands) -> Result<String> {
+    Err(CommandeerError::UnsupportedCassette {
+        path: path.to_path_buf(),
+    })
+}
+
+#[cfg(feature = "vcr")]
+pub(crate) use cassette::{parse, to_string};
+
+#[cfg(feature = "vcr")]
+mod cassette {
+    use super::*;
+    use crate::CommandInvocation;
+    use serde::{Deserialize, Serialize};
+    use serde_json::{Map, Value};
+
+    /// Fields of a [`CommandInvocation`] that belong in the request rather than the response.
+    const REQUEST_FIELDS: [&str; 3] = ["original_args", "stdin", "env"];
+
+    #[derive(Serialize, Deserialize)]
+    struct Cassette {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        min_version: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        description: Option<String>,
+        #[serde(default)]
+        interactions: Vec<Interaction>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Interaction {
+        request: Request,
+        response: Map<String, Value>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Request {
+        /// The test the recording is scoped to within a shared fixture.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        test: Option<String>,
+        command: String,
+        #[serde(default)]
+        args: Vec<String>,
+        #[serde(flatten)]
+        rest: Map<String, Value>,
+    }
+
+    /// Reads the recordings stored in the cassette `contents` of the fixture at `path`.
+    pub(crate) fn parse(path: &Path, contents: &str) -> Result<RecordedCommands> {
+        let cassette: Cassette =
+            serde_yaml::from_str(contents).map_err(|source| CommandeerError::Cassette {
+                path: path.to_path_buf(),
+                source,
+            })?;
+
+        let mut recordings = RecordedCommands {
+            min_version: cassette.min_version,
+            description: cassette.description,
+            ..RecordedCommands::default()
+        };
+
+        for Interaction { request, response } in cassette.interactions {
+            let mut fields = response;
+            fields.extend(request.rest);
+            fields.insert("binary_name".to_string(), request.command.into());
+            fields.insert("args".to_string(), request.args.into());
+
+            let invocation: CommandInvocation = serde_json::from_value(Value::Object(fields))
+                .map_err(|source| CommandeerError::Parse {
+                    path: path.to_path_buf(),
+                    source,
+                })?;
+
+            match request.test {
+                Some(test_name) => recordings
+                    .for_test_mut(&test_name)
+                    .add_invocation(invocation),
+                None => recordings.add_invocation(invocation),
+            }
+        }
+
+        Ok(recordings)
+    }
+
+    /// `recordings` as the cassette to write to the fixture at `path`, with interactions sorted by key and in
+    /// recording order within a key.
+    pub(crate) fn to_string(path: &Path, recordings: &RecordedCommands) -> Result<String> {
+        let mut interactions = vec![];
+        push_interactions(recordings, None, &mut interactions)?;
+
+        let mut tests: Vec<_> = recordings.tests.iter().collect();
+        tests.sort_by(|a, b| a.0.cmp(b.0));
+
+        for (test_name, scoped) in tests {
+            push_interactions(scoped, Some(test_name), &mut interactions)?;
+        }
+
+        let cassette = Cassette {
+            min_version: recordings.min_version.clone(),
+            description: recordings.description.clone(),
+            interactions,
+        };
+
+        serde_yaml::to_string(&cassette).map_err(|source| CommandeerError::Cassette {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    fn push_interactions(
+        recordings: &RecordedCommands,
+        test: Option<&str>,
+        interactions: &mut Vec<Interaction>,
+    ) -> Result<()> {
+        let mut commands: Vec<_> = recordings.commands.iter().collect();
+        commands.sort_by(|a, b| a.0.cmp(b.0));
+
+        for invocation in commands
+            .into_iter()
+            .flat_map(|(_, invocations)| invocations)
+        {
+            let Value::Object(mut response) = serde_json::to_value(invocation)? else {
+                unreachable!("a CommandInvocation serializes to an object");
+            };
+
+            response.remove("binary_name");
+            response.remove("args");
+
+            let rest = REQUEST_FIELDS
+                .into_iter()
+                .filter_map(|field| Some((field.to_string(), response.remove(field)?)))
+                .collect();
+
+            interactions.push(Interaction {
+                request: Request {
+                    test: test.map(str::to_string),
+                    command: invocation.binary_name.clone(),
+                    args: invocation.args.clone(),
+                    rest,
+                },
+                response,
+            });
+        }
+
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::path::PathBuf;
+
+        #[test]
+        fn cassettes_round_trip() {
+            let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("testcmds/cmds_my_test.json");
+            let mut recordings =
+                crate::parse_recordings(&fixture, &std::fs::read_to_string(&fixture).unwrap())
+                    .unwrap();
+            recordings.description = Some("Says hello".to_string());
+
+            let mut scoped = recordings.invocations("echo", &["hello".to_string()])[0].clone();
+            scoped.stdin = Some(b"in\n".to_vec());
+            scoped.stderr = b"two\nlines\n".to_vec();
+            recordings.for_test_mut("scoped").add_invocation(scoped);
+
+            let cassette_path = PathBuf::from("cassette.yaml");
+            let cassette = to_string(&cassette_path, &recordings).unwrap();
+
+            assert!(cassette.contains("interactions:"), "{cassette}");
+            assert!(cassette.contains("request:"), "{cassette}");
+            assert!(cassette.contains("response:"), "{cassette}");
+
+            let loaded = parse(&cassette_path, &cassette).unwrap();
+
+            assert_eq!(to_string(&cassette_path, &loaded).unwrap(), cassette);
+            assert_eq!(
+                serde_json::to_value(&loaded).unwrap(),
+                serde_json::to_value(&recordings).unwrap()
+            );
+        }
+    }
+}
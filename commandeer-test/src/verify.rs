@@ -0,0 +1,217 @@
+use crate::{CallLogEntry, Commandeer};
+use std::{collections::BTreeSet, ops::RangeInclusive};
+
+/// Exit codes accepted by [`Commandeer::assert_exit_in`]: a range such as `1..=255`, or a predicate.
+pub trait ExitCodes {
+    fn accepts(&self, exit_code: i32) -> bool;
+
+    /// Describes the accepted codes for assertion failures.
+    fn describe(&self) -> String;
+}
+
+impl ExitCodes for RangeInclusive<i32> {
+    fn accepts(&self, exit_code: i32) -> bool {
+        self.contains(&exit_code)
+    }
+
+    fn describe(&self) -> String {
+        format!("{}..={}", self.start(), self.end())
+    }
+}
+
+impl<F: Fn(i32) -> bool> ExitCodes for F {
+    fn accepts(&self, exit_code: i32) -> bool {
+        self(exit_code)
+    }
+
+    fn describe(&self) -> String {
+        "the predicate".to_string()
+    }
+}
+
+/// The distinct non-blank `lines` with trailing whitespace, including `\r`, trimmed.
+fn line_set<'a>(lines: impl IntoIterator<Item = &'a str>) -> BTreeSet<&'a str> {
+    lines
+        .into_iter()
+        .map(str::trim_end)
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+impl Commandeer {
+    /// Asserts that every intercepted call of `command` with `args` exited with `exit_code`.
+    ///
+    /// # Panics
+    ///
+    /// If the command was never called, or any call exited with another code.
+    pub fn assert_exit_code(&self, command: &str, args: &[&str], exit_code: i32) {
+        self.assert_exit_in(command, args, exit_code..=exit_code);
+    }
+
+    /// Asserts that the value at JSON Pointer `pointer` (e.g. `/status/phase`) in the recorded stdout of `command` with
+    /// `args` equals `expected`.
+    ///
+    /// # Panics
+    ///
+    /// If there is no recording for the invocation, its stdout isn't valid JSON, nothing is at `pointer`, or the value
+    /// differs.
+    pub fn assert_stdout_json(
+        &self,
+        command: &str,
+        args: &[&str],
+        pointer: &str,
+        expected: impl Into<serde_json::Value>,
+    ) {
+        let invocation = format!("{command} {}", args.join(" "));
+        let invocation = invocation.trim_end();
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+
+        let recordings = self.fixture_recordings().expect("Failed to read fixture");
+
+        let Some(recorded) = recordings.find_invocation(command, &args) else {
+            panic!("No recording of `{invocation}` to check stdout of");
+        };
+
+        let stdout: serde_json::Value = serde_json::from_slice(&recorded.stdout)
+            .unwrap_or_else(|e| panic!("stdout of `{invocation}` is not valid JSON: {e}"));
+
+        let Some(actual) = stdout.pointer(pointer) else {
+            panic!("`{pointer}` not found in stdout of `{invocation}`");
+        };
+
+        assert_eq!(
+            actual,
+            &expected.into(),
+            "`{pointer}` in stdout of `{invocation}`"
+        );
+    }
+
+    /// Asserts that the recorded stdout of `command` with `args` holds exactly the lines `expected`, in any order, for
+    /// output such as `ls` or `find` whose order depends on the filesystem.
+    ///
+    /// Both sides are compared as sets of lines: trailing whitespace and line endings are trimmed, blank lines are
+    /// ignored, and a line repeated counts once.
+    ///
+    /// # Panics
+    ///
+    /// If there is no recording for the invocation, or its lines differ from `expected`.
+    pub fn assert_stdout_lines_eq(&self, command: &str, args: &[&str], expected: &[&str]) {
+        let invocation = format!("{command} {}", args.join(" "));
+        let invocation = invocation.trim_end();
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+
+        let recordings = self.fixture_recordings().expect("Failed to read fixture");
+
+        let Some(recorded) = recordings.find_invocation(command, &args) else {
+            panic!("No recording of `{invocation}` to check stdout of");
+        };
+
+        let stdout = recorded.decode(&recorded.stdout);
+        let actual = line_set(stdout.lines());
+        let expected = line_set(expected.iter().copied());
+
+        assert!(
+            actual == expected,
+            "lines of stdout of `{invocation}` differ\n  missing: {:?}\n  unexpected: {:?}",
+            expected.difference(&actual).collect::<Vec<_>>(),
+            actual.difference(&expected).collect::<Vec<_>>()
+        );
+    }
+
+    /// Asserts that the recording of `command` with `args` read `expected` bytes of the stdin it was fed, see
+    /// [`crate::CommandInvocation::stdin_consumed`].
+    ///
+    /// # Panics
+    ///
+    /// If there is no recording for the invocation, it was made without [`crate::CommandeerBuilder::capture_stdin`],
+    /// or it read a different amount.
+    pub fn assert_stdin_consumed(&self, command: &str, args: &[&str], expected: usize) {
+        let invocation = format!("{command} {}", args.join(" "));
+        let invocation = invocation.trim_end();
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+
+        let recordings = self.fixture_recordings().expect("Failed to read fixture");
+
+        let Some(recorded) = recordings.find_invocation(command, &args) else {
+            panic!("No recording of `{invocation}` to check stdin of");
+        };
+
+        let Some(consumed) = recorded.stdin_consumed else {
+            panic!("`{invocation}` was recorded without capturing stdin");
+        };
+
+        assert_eq!(
+            consumed, expected,
+            "`{invocation}` read {consumed} bytes of stdin, expected {expected}"
+        );
+    }
+
+    /// Asserts that every intercepted call of `command` with `args` exited with a code in `expected`.
+    ///
+    /// # Panics
+    ///
+    /// If the command was never called, or any call exited with a code `expected` doesn't accept.
+    pub fn assert_exit_in(&self, command: &str, args: &[&str], expected: impl ExitCodes) {
+        let invocation = format!("{command} {}", args.join(" "));
+        let calls: Vec<CallLogEntry> = self
+            .calls()
+            .into_iter()
+            .filter(|call| call.binary_name == command && call.args == args)
+            .collect();
+
+        assert!(
+            !calls.is_empty(),
+            "`{}` was never called",
+            invocation.trim_end()
+        );
+
+        for call in calls {
+            assert!(
+                expected.accepts(call.exit_code),
+                "`{}` exited with {}, expected an exit code in {}",
+                invocation.trim_end(),
+                call.exit_code,
+                expected.describe()
+            );
+        }
+    }
+
+    /// Asserts that every intercepted call of `command` passed `flag`, e.g. that `rm` is always called with `-I`.
+    ///
+    /// An arg includes `flag` if it is `flag` or, for a long flag, `flag=value`, so `--fail` matches `--fail` and
+    /// `--fail=yes`, while `--output=out.txt` only matches that exact arg. Args after `--` are operands and never
+    /// match. If the command was never called the assertion holds.
+    ///
+    /// # Panics
+    ///
+    /// If any intercepted call of `command` lacks `flag`.
+    pub fn assert_all_calls_include(&self, command: &str, flag: &str) {
+        let missing: Vec<String> = self
+            .calls()
+            .into_iter()
+            .filter(|call| call.binary_name == command && !includes_flag(&call.args, flag))
+            .map(|call| {
+                format!("{command} {}", call.args.join(" "))
+                    .trim_end()
+                    .to_string()
+            })
+            .collect();
+
+        assert!(
+            missing.is_empty(),
+            "`{command}` was called without `{flag}`:\n  {}",
+            missing.join("\n  ")
+        );
+    }
+}
+
+fn includes_flag(args: &[String], flag: &str) -> bool {
+    args.iter().take_while(|arg| *arg != "--").any(|arg| {
+        arg == flag
+            || (flag.starts_with("--")
+                && !flag.contains('=')
+                && arg
+                    .strip_prefix(flag)
+                    .is_some_and(|rest| rest.starts_with('=')))
+    })
+}